@@ -0,0 +1,198 @@
+//! Arbitrary output variables, what the camera rays of each pixel hit first,
+//! rendered next to the beauty image for compositing and denoising
+
+use std::fmt;
+
+use glam::Vec3;
+
+use crate::{
+    display::srgb_oetf,
+    film::{AovPixel, Pixel},
+};
+
+/// Id of pixels whose first sample missed everything
+pub const NO_ID: u32 = u32::MAX;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aov {
+    /// Reflectance of the material times the vertex color
+    Albedo,
+    /// World space shading normal, averaged but not normalized again
+    Normal,
+    /// Distance along the camera ray, infinite where nothing was hit
+    Depth,
+    /// World space hit point
+    Position,
+    /// Index into the scene's materials
+    MaterialId,
+    /// Triangle index, spheres continue after the last triangle and
+    /// instances after the last sphere, one id for all triangles of each
+    PrimitiveId,
+    /// Variance of the mean luminance of the beauty image, what the
+    /// denoiser expects the noise to be
+    Variance,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 7] = [
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Depth,
+        Aov::Position,
+        Aov::MaterialId,
+        Aov::PrimitiveId,
+        Aov::Variance,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::MaterialId => "material_id",
+            Aov::PrimitiveId => "primitive_id",
+            Aov::Variance => "variance",
+        }
+    }
+}
+
+impl fmt::Display for Aov {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// AOV buffers, rows top to bottom like the beauty image. All empty when
+/// the renderer doesn't produce AOVs.
+#[derive(Clone, Default)]
+pub struct Aovs {
+    pub albedo: Vec<[f32; 3]>,
+    pub normal: Vec<[f32; 3]>,
+    pub depth: Vec<f32>,
+    pub position: Vec<[f32; 3]>,
+    pub material_id: Vec<u32>,
+    pub primitive_id: Vec<u32>,
+    pub variance: Vec<f32>,
+}
+
+impl Aovs {
+    pub fn is_empty(&self) -> bool {
+        self.depth.is_empty()
+    }
+
+    /// Replaces the buffers with the averages of `pixels` and the variance
+    /// of the beauty `acc`, reusing their allocations
+    pub(crate) fn resolve(&mut self, pixels: &[AovPixel], acc: &[Pixel]) {
+        self.albedo.clear();
+        self.normal.clear();
+        self.depth.clear();
+        self.position.clear();
+        self.material_id.clear();
+        self.primitive_id.clear();
+        self.variance.clear();
+
+        for p in pixels {
+            let samples = p.samples.max(1) as f32;
+            self.albedo.push((p.albedo / samples).to_array());
+            self.normal.push((p.normal / samples).to_array());
+
+            if p.hits > 0 {
+                let hits = p.hits as f32;
+                self.depth.push(p.depth / hits);
+                self.position.push((p.position / hits).to_array());
+            } else {
+                self.depth.push(f32::INFINITY);
+                self.position.push([0.0; 3]);
+            }
+
+            self.material_id.push(p.material);
+            self.primitive_id.push(p.primitive);
+        }
+
+        self.variance.extend(acc.iter().map(Pixel::variance));
+    }
+
+    /// sRGB encoded visualization of `aov` in `[0, 1]`. Normals map to
+    /// `n / 2 + 1/2`, depth and position are scaled to the range they cover
+    /// and ids get a random color each. The variance shows as the standard
+    /// error, empty for missing buffers.
+    pub fn preview(&self, aov: Aov) -> Vec<[f32; 3]> {
+        match aov {
+            Aov::Albedo => self
+                .albedo
+                .iter()
+                .map(|c| c.map(|c| srgb_oetf(c.clamp(0.0, 1.0))))
+                .collect(),
+            Aov::Normal => self
+                .normal
+                .iter()
+                .map(|n| n.map(|n| (n * 0.5 + 0.5).clamp(0.0, 1.0)))
+                .collect(),
+            Aov::Depth => {
+                let max = self
+                    .depth
+                    .iter()
+                    .copied()
+                    .filter(|d| d.is_finite())
+                    .fold(0.0, f32::max);
+
+                // Near is bright, misses are black
+                self.depth
+                    .iter()
+                    .map(|&d| {
+                        let v = if d.is_finite() && max > 0.0 {
+                            1.0 - 0.9 * d / max
+                        } else {
+                            0.0
+                        };
+                        [v; 3]
+                    })
+                    .collect()
+            }
+            Aov::Position => {
+                let hits = self
+                    .position
+                    .iter()
+                    .zip(&self.depth)
+                    .filter(|(_, d)| d.is_finite())
+                    .map(|(p, _)| Vec3::from(*p));
+                let (min, max) = hits
+                    .fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), p| {
+                        (min.min(p), max.max(p))
+                    });
+                let extent = (max - min).max(Vec3::splat(1e-6));
+
+                self.position
+                    .iter()
+                    .zip(&self.depth)
+                    .map(|(p, d)| {
+                        if d.is_finite() {
+                            ((Vec3::from(*p) - min) / extent).to_array()
+                        } else {
+                            [0.0; 3]
+                        }
+                    })
+                    .collect()
+            }
+            Aov::MaterialId => self.material_id.iter().map(|&id| id_color(id)).collect(),
+            Aov::PrimitiveId => self.primitive_id.iter().map(|&id| id_color(id)).collect(),
+            Aov::Variance => self
+                .variance
+                .iter()
+                .map(|&v| [srgb_oetf(v.sqrt().clamp(0.0, 1.0)); 3])
+                .collect(),
+        }
+    }
+}
+
+/// Bright color picked by hashing `id`, black for [`NO_ID`]
+fn id_color(id: u32) -> [f32; 3] {
+    if id == NO_ID {
+        return [0.0; 3];
+    }
+
+    let hash = (id as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    let channel = |shift: u32| 0.2 + 0.8 * ((hash >> shift) & 0xff) as f32 / 255.0;
+    [channel(40), channel(48), channel(56)]
+}
@@ -1,9 +1,258 @@
-use std::f32::consts::PI;
+use std::{f32::consts::PI, fmt, str::FromStr};
 
-use glam::{UVec2, Vec3, vec3};
+use fastrand::Rng;
+use glam::{Quat, UVec2, Vec3, vec3};
+use serde::{Deserialize, Serialize};
 
 use crate::Ray;
 
+/// Placement of the camera in a scene, independent of the image size
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CameraSettings {
+    pub look_from: Vec3,
+    pub look_at: Vec3,
+    /// Direction that points up in the image, it needn't be perpendicular
+    /// to the view direction
+    #[serde(default = "y_up")]
+    pub up: Vec3,
+    /// Vertical field of view in degrees
+    pub vfov: f32,
+    /// Radius of the lens, zero for a pinhole with everything in focus
+    #[serde(default)]
+    pub aperture: f32,
+    /// Distance of the plane in focus along the view direction, `look_at`
+    /// is in focus when unset
+    #[serde(default)]
+    pub focus_distance: Option<f32>,
+    /// Straight blades shaping the aperture into a polygon, the aperture is
+    /// round with fewer than three
+    #[serde(default)]
+    pub blades: u32,
+    /// Rotation of the aperture polygon in degrees
+    #[serde(default)]
+    pub blade_rotation: f32,
+    /// How view directions map onto the image, the lens settings only
+    /// apply to perspective projections
+    #[serde(default)]
+    pub projection: Projection,
+    /// Time the shutter opens, moving geometry is at its start at time zero
+    /// and at its end at time one and rests outside that
+    #[serde(default)]
+    pub shutter_open: f32,
+    /// Time the shutter closes, nothing blurs when it equals `shutter_open`
+    #[serde(default)]
+    pub shutter_close: f32,
+}
+
+/// Mapping from image positions to view directions
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Projection {
+    /// Pinhole or thin lens camera with the vertical field of view `vfov`
+    #[default]
+    Perspective,
+    /// Parallel rays along the view direction, `width` world units across
+    /// the image
+    Orthographic { width: f32 },
+    /// Full 360° by 180° panorama, longitude across and latitude down the
+    /// image with the view direction in the center
+    Equirectangular,
+    /// Radial mapping of `fov` degrees across the image diagonal, up to 360
+    Fisheye {
+        fov: f32,
+        #[serde(default)]
+        mapping: FisheyeMapping,
+    },
+    /// Panorama on a cylinder around the up vector, `fov` degrees across
+    /// the image and straight vertical lines
+    Cylindrical { fov: f32 },
+}
+
+/// How the angle from the view direction grows with the distance from the
+/// image center
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FisheyeMapping {
+    /// Proportional to the distance, keeps angles evenly spaced
+    #[default]
+    Equidistant,
+    /// Preserves solid angles, so areas keep their relative size
+    Equisolid,
+}
+
+impl Projection {
+    /// Checks the width or field of view is positive and finite, which
+    /// parsing with [`Projection::from_str`] already ensures
+    pub fn validate(&self) -> Result<(), &'static str> {
+        let parameter = match *self {
+            Projection::Orthographic { width } => width,
+            Projection::Fisheye { fov, .. } | Projection::Cylindrical { fov } => fov,
+            Projection::Perspective | Projection::Equirectangular => return Ok(()),
+        };
+        match parameter.is_finite() && parameter > 0.0 {
+            true => Ok(()),
+            false => Err("width and field of view must be positive"),
+        }
+    }
+}
+
+impl fmt::Display for Projection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Projection::Perspective => write!(f, "perspective"),
+            Projection::Orthographic { width } => write!(f, "orthographic:{width}"),
+            Projection::Equirectangular => write!(f, "equirectangular"),
+            Projection::Fisheye {
+                fov,
+                mapping: FisheyeMapping::Equidistant,
+            } => write!(f, "fisheye:{fov}"),
+            Projection::Fisheye {
+                fov,
+                mapping: FisheyeMapping::Equisolid,
+            } => write!(f, "equisolid:{fov}"),
+            Projection::Cylindrical { fov } => write!(f, "cylindrical:{fov}"),
+        }
+    }
+}
+
+/// Parses `name` or `name:parameter` as written by [`Projection`]'s
+/// `Display`, the field of view defaults to 180 degrees
+impl FromStr for Projection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, parameter) = match s.split_once(':') {
+            Some((name, parameter)) => (name, Some(parameter)),
+            None => (s, None),
+        };
+        let parameter = parameter
+            .map(|p| {
+                p.trim()
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|p| p.is_finite() && *p > 0.0)
+                    .ok_or_else(|| format!("invalid projection parameter `{p}`"))
+            })
+            .transpose()?;
+        let fov = parameter.unwrap_or(180.0);
+
+        match name.trim().to_ascii_lowercase().as_str() {
+            "perspective" => Ok(Projection::Perspective),
+            "orthographic" => parameter
+                .map(|width| Projection::Orthographic { width })
+                .ok_or_else(|| "orthographic needs a width, e.g. `orthographic:20`".to_string()),
+            "equirectangular" => Ok(Projection::Equirectangular),
+            "fisheye" | "equidistant" => Ok(Projection::Fisheye {
+                fov,
+                mapping: FisheyeMapping::Equidistant,
+            }),
+            "equisolid" => Ok(Projection::Fisheye {
+                fov,
+                mapping: FisheyeMapping::Equisolid,
+            }),
+            "cylindrical" => Ok(Projection::Cylindrical { fov }),
+            _ => Err(format!(
+                "unknown projection `{name}`, expected one of perspective, orthographic, \
+                 equirectangular, fisheye, equisolid or cylindrical"
+            )),
+        }
+    }
+}
+
+fn y_up() -> Vec3 {
+    Vec3::Y
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            look_from: Vec3::ZERO,
+            look_at: vec3(0.0, 0.0, -1.0),
+            up: Vec3::Y,
+            vfov: 90.0,
+            aperture: 0.0,
+            focus_distance: None,
+            blades: 0,
+            blade_rotation: 0.0,
+            projection: Projection::Perspective,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        }
+    }
+}
+
+/// Closest the view direction gets to the up vector when rotating, in
+/// radians, the image plane is undefined when they line up
+const MIN_POLAR_ANGLE: f32 = 0.01;
+
+impl CameraSettings {
+    /// Circles `look_from` around `look_at`, by `yaw` radians around the up
+    /// vector and `pitch` radians towards it
+    pub fn orbit(&mut self, yaw: f32, pitch: f32) {
+        let offset = rotate(self.look_from - self.look_at, self.up, yaw, pitch);
+        self.look_from = self.look_at + offset;
+    }
+
+    /// Turns the view direction around `look_from`, by `yaw` radians around
+    /// the up vector and `pitch` radians towards it
+    pub fn look(&mut self, yaw: f32, pitch: f32) {
+        let direction = rotate(self.look_at - self.look_from, self.up, yaw, pitch);
+        self.look_at = self.look_from + direction;
+    }
+
+    /// Shifts the camera and its target along the image plane, by fractions
+    /// of the image height at the distance of `look_at`
+    pub fn pan(&mut self, right: f32, up: f32) {
+        let (right_dir, up_dir, _) = self.frame();
+        let distance = (self.look_at - self.look_from).length();
+        let height = 2.0 * distance * (self.vfov.to_radians() / 2.0).tan();
+
+        let offset = (right * right_dir + up * up_dir) * height;
+        self.look_from += offset;
+        self.look_at += offset;
+    }
+
+    /// Moves `look_from` towards `look_at`, scaling their distance by
+    /// `factor`
+    pub fn dolly(&mut self, factor: f32) {
+        let offset = (self.look_from - self.look_at) * factor;
+        if offset.length() > 1e-3 {
+            self.look_from = self.look_at + offset;
+        }
+    }
+
+    /// Moves the camera and its target by the given distances along the
+    /// camera's right, up and forward directions
+    pub fn fly(&mut self, right: f32, up: f32, forward: f32) {
+        let (right_dir, up_dir, forward_dir) = self.frame();
+        let offset = right * right_dir + up * up_dir + forward * forward_dir;
+        self.look_from += offset;
+        self.look_at += offset;
+    }
+
+    /// Unit right, up and forward directions of the image
+    fn frame(&self) -> (Vec3, Vec3, Vec3) {
+        let forward = (self.look_at - self.look_from).normalize();
+        let right = forward.cross(self.up).normalize();
+        (right, right.cross(forward), forward)
+    }
+}
+
+/// `v` turned by `yaw` radians around `up` and `pitch` radians towards it,
+/// keeping at least [`MIN_POLAR_ANGLE`] away from either pole
+fn rotate(v: Vec3, up: Vec3, yaw: f32, pitch: f32) -> Vec3 {
+    let up = up.normalize();
+    let length = v.length();
+
+    let polar = v.angle_between(up);
+    let polar = (polar - pitch).clamp(MIN_POLAR_ANGLE, PI - MIN_POLAR_ANGLE);
+
+    let Some(horizontal) = v.reject_from_normalized(up).try_normalize() else {
+        return v;
+    };
+    let horizontal = Quat::from_axis_angle(up, yaw) * horizontal;
+
+    length * (up * polar.cos() + horizontal * polar.sin())
+}
+
 #[derive(Clone)]
 pub struct Camera {
     pub screen_size: UVec2,
@@ -20,10 +269,35 @@ pub struct Camera {
     v: Vec3,
     w: Vec3,
     vfov: f32,
+
+    focus_distance: f32,
+    lens_radius: f32,
+    blades: u32,
+    /// In radians
+    blade_rotation: f32,
+
+    projection: Projection,
+
+    /// Shutter interval clamped to the motion of the geometry
+    shutter: (f32, f32),
 }
 
 impl Camera {
-    pub fn new(look_from: Vec3, look_at: Vec3, vfov: f32, size: UVec2) -> Camera {
+    pub fn new(settings: &CameraSettings, size: UVec2) -> Camera {
+        let CameraSettings {
+            look_from,
+            look_at,
+            up,
+            vfov,
+            aperture,
+            focus_distance,
+            blades,
+            blade_rotation,
+            projection,
+            shutter_open,
+            shutter_close,
+        } = *settings;
+
         let mut camera = Camera {
             screen_size: size,
 
@@ -33,12 +307,21 @@ impl Camera {
 
             look_from,
             look_at,
-            v_up: vec3(0.0, 1.0, 0.0),
+            v_up: up,
 
             u: Vec3::ZERO,
             v: Vec3::ZERO,
             w: Vec3::ZERO,
             vfov,
+
+            focus_distance: focus_distance.unwrap_or((look_from - look_at).length()),
+            lens_radius: aperture.max(0.0),
+            blades,
+            blade_rotation: blade_rotation.to_radians(),
+
+            projection,
+
+            shutter: (shutter_open.clamp(0.0, 1.0), shutter_close.clamp(0.0, 1.0)),
         };
 
         camera.resize(size);
@@ -51,7 +334,8 @@ impl Camera {
 
         let aspect_ratio = size.x as f32 / size.y as f32;
 
-        let focal_length = (self.look_from - self.look_at).length();
+        // The image plane is the plane in focus
+        let focal_length = self.focus_distance;
         let theta = (self.vfov * PI) / 180.0;
         let h = (theta / 2.0).tan();
         let viewport_height = 2.0 * h * focal_length;
@@ -73,17 +357,123 @@ impl Camera {
     }
 
     #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
-    pub fn get_ray(&self, x: usize, y: usize) -> Ray {
-        let jitter_x = rand::random_range(-0.5..=0.5);
-        let jitter_y = rand::random_range(-0.5..=0.5);
+    pub fn get_ray(&self, x: usize, y: usize, rng: &mut Rng) -> Ray {
+        let x = x as f32 + rng.f32() - 0.5;
+        let y = y as f32 + rng.f32() - 0.5;
+        let time = self.sample_time(rng);
+
+        if self.projection != Projection::Perspective || self.lens_radius <= 0.0 {
+            return self.pinhole_ray(x, y, time);
+        }
+
+        // Every ray through the lens meets the pinhole ray on the image
+        // plane, so only that plane is sharp
+        let pixel_pos = self.screen_upper_left + x * self.screen_right + y * self.screen_down;
+        let (lens_x, lens_y) = self.sample_aperture(rng);
+        let origin = self.look_from + self.lens_radius * (lens_x * self.u + lens_y * self.v);
+
+        Ray::new(origin, pixel_pos - origin, time)
+    }
+
+    /// Uniform time in the shutter interval, an instant shutter takes no
+    /// random numbers
+    #[inline(always)]
+    fn sample_time(&self, rng: &mut Rng) -> f32 {
+        let (open, close) = self.shutter;
+        if close > open {
+            open + (close - open) * rng.f32()
+        } else {
+            open
+        }
+    }
+
+    /// Ray through the center of the lens and the image position `(x, y)`,
+    /// in pixels with pixel centers at whole numbers
+    fn pinhole_ray(&self, x: f32, y: f32, time: f32) -> Ray {
+        let size = self.screen_size.as_vec2();
+        // Offsets from the image center, to the right and up
+        let right = x + 0.5 - size.x / 2.0;
+        let up = size.y / 2.0 - (y + 0.5);
 
-        let pixel_pos = self.screen_upper_left
-            + ((x as f32 + jitter_x) * self.screen_right)
-            + ((y as f32 + jitter_y) * self.screen_down);
+        // Right, up and forward components of the view direction
+        let (dx, dy, dz) = match self.projection {
+            Projection::Perspective => {
+                let pixel_pos =
+                    self.screen_upper_left + x * self.screen_right + y * self.screen_down;
+                return Ray::new(self.look_from, pixel_pos - self.look_from, time);
+            }
+            Projection::Orthographic { width } => {
+                let scale = width / size.x;
+                let origin = self.look_from + scale * (right * self.u + up * self.v);
+                return Ray::new(origin, -self.w, time);
+            }
+            Projection::Equirectangular => {
+                let longitude = 2.0 * PI * right / size.x;
+                let latitude = PI * up / size.y;
+                let (sin_lon, cos_lon) = longitude.sin_cos();
+                let (sin_lat, cos_lat) = latitude.sin_cos();
+                (cos_lat * sin_lon, sin_lat, cos_lat * cos_lon)
+            }
+            Projection::Fisheye { fov, mapping } => {
+                let half_fov = fov.clamp(1e-3, 360.0).to_radians() / 2.0;
+                let r = right.hypot(up) / (size.length() / 2.0);
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => r * half_fov,
+                    FisheyeMapping::Equisolid => 2.0 * (r * (half_fov / 2.0).sin()).min(1.0).asin(),
+                };
+                let (sin_theta, cos_theta) = theta.sin_cos();
+                let (sin_phi, cos_phi) = up.atan2(right).sin_cos();
+                (sin_theta * cos_phi, sin_theta * sin_phi, cos_theta)
+            }
+            Projection::Cylindrical { fov } => {
+                // Square pixels, so the height of the cylinder per pixel
+                // matches the arc length
+                let radians_per_pixel = fov.to_radians() / size.x;
+                let (sin, cos) = (right * radians_per_pixel).sin_cos();
+                (sin, up * radians_per_pixel, cos)
+            }
+        };
+
+        Ray::new(
+            self.look_from,
+            dx * self.u + dy * self.v - dz * self.w,
+            time,
+        )
+    }
 
-        Ray {
-            origin: self.look_from,
-            direction: pixel_pos - self.look_from,
+    /// Uniform point on the unit disk, or on the regular polygon inscribed
+    /// in it when the aperture has blades
+    #[inline(always)]
+    fn sample_aperture(&self, rng: &mut Rng) -> (f32, f32) {
+        if self.blades < 3 {
+            let r = rng.f32().sqrt();
+            let (sin, cos) = (2.0 * PI * rng.f32()).sin_cos();
+            return (r * cos, r * sin);
         }
+
+        // Picks one of the triangles fanning out from the center, then a
+        // point in it
+        let n = self.blades as f32;
+        let pick = rng.f32() * n;
+        let blade = pick.floor().min(n - 1.0);
+        let s = (pick - blade).sqrt();
+        let t = rng.f32();
+
+        let corner = |i: f32| (self.blade_rotation + 2.0 * PI * i / n).sin_cos();
+        let (sin_a, cos_a) = corner(blade);
+        let (sin_b, cos_b) = corner(blade + 1.0);
+        (
+            s * ((1.0 - t) * cos_a + t * cos_b),
+            s * ((1.0 - t) * sin_a + t * sin_b),
+        )
+    }
+
+    /// Distance along the view direction to the point `depth` along the
+    /// camera ray through the center of pixel `(x, y)`, what
+    /// [`CameraSettings::focus_distance`] should be to focus on the surface
+    /// the [`Aov::Depth`](crate::aov::Aov::Depth) of that pixel saw
+    pub fn focus_distance_at(&self, x: f32, y: f32, depth: f32) -> f32 {
+        let ray = self.pinhole_ray(x, y, self.shutter.0);
+        depth * ray.direction.normalize().dot(-self.w)
     }
 }
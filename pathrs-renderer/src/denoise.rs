@@ -0,0 +1,196 @@
+//! Edge-avoiding à-trous wavelet denoiser guided by the albedo and normal
+//! AOVs and the per-pixel variance, after Dammertz et al. 2010 and the
+//! variance steering of SVGF
+
+use std::{num::NonZeroUsize, thread};
+
+use glam::{Vec3, Vec4, Vec4Swizzles};
+
+use crate::{aov::Aovs, luminance};
+
+/// Albedo channels below this are treated as black and not divided out
+const MIN_ALBEDO: f32 = 1e-3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Denoiser {
+    /// Filter passes, each doubles the kernel footprint so `n` passes cover
+    /// `4 * (2^n - 1) + 1` pixels
+    pub iterations: u32,
+    /// How many standard deviations of the noise luminance differences may
+    /// span before neighbors stop counting, larger blurs more
+    pub color_sigma: f32,
+    /// Exponent of the normal similarity, larger keeps sharper geometric
+    /// edges
+    pub normal_power: f32,
+    /// Albedo distance at which neighbors stop counting
+    pub albedo_sigma: f32,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Denoiser {
+            iterations: 5,
+            color_sigma: 4.0,
+            normal_power: 64.0,
+            albedo_sigma: 0.1,
+        }
+    }
+}
+
+/// Per pixel guides, read by every pass
+struct Guides {
+    normal: Vec<Vec3>,
+    albedo: Vec<Vec3>,
+    width: usize,
+}
+
+impl Denoiser {
+    /// Filtered copy of the linear RGBA `image` of the given `width`.
+    /// `aovs` needs the albedo, normal and variance buffers, without them
+    /// the image is returned unchanged.
+    pub fn denoise(&self, image: &[[f32; 4]], aovs: &Aovs, width: u32) -> Vec<[f32; 4]> {
+        let width = width as usize;
+        if aovs.is_empty() || width == 0 || self.iterations == 0 {
+            return image.to_vec();
+        }
+        assert_eq!(image.len(), aovs.albedo.len());
+
+        let guides = Guides {
+            normal: aovs
+                .normal
+                .iter()
+                .map(|&n| Vec3::from(n).normalize_or_zero())
+                .collect(),
+            albedo: aovs.albedo.iter().map(|&a| Vec3::from(a)).collect(),
+            width,
+        };
+
+        // Filtering the irradiance instead of the radiance keeps texture and
+        // albedo edges out of the blur, the albedo is multiplied back in at
+        // the end
+        let demodulator: Vec<Vec3> = guides
+            .albedo
+            .iter()
+            .map(|&a| Vec3::select(a.cmpgt(Vec3::splat(MIN_ALBEDO)), a, Vec3::ONE))
+            .collect();
+
+        // Irradiance in xyz, the variance of its luminance in w
+        let mut current: Vec<_> = image
+            .iter()
+            .zip(&aovs.variance)
+            .zip(&demodulator)
+            .map(|((&[r, g, b, _], &variance), &d)| {
+                let scale = luminance(d);
+                (Vec3::new(r, g, b) / d).extend(variance / (scale * scale))
+            })
+            .collect();
+        let mut next = current.clone();
+
+        for i in 0..self.iterations {
+            self.pass(&current, &mut next, &guides, 1 << i);
+            std::mem::swap(&mut current, &mut next);
+        }
+
+        current
+            .iter()
+            .zip(&demodulator)
+            .zip(image)
+            .map(|((c, &d), &[.., alpha])| (c.xyz() * d).extend(alpha).to_array())
+            .collect()
+    }
+
+    /// One à-trous pass with holes of `step` pixels, split into bands of
+    /// rows filtered in parallel
+    fn pass(&self, src: &[Vec4], dst: &mut [Vec4], guides: &Guides, step: usize) {
+        let width = guides.width;
+        let height = src.len() / width;
+        let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        let band = height.div_ceil(threads).max(1) * width;
+
+        thread::scope(|s| {
+            for (b, dst) in dst.chunks_mut(band).enumerate() {
+                s.spawn(move || {
+                    for (i, out) in dst.iter_mut().enumerate() {
+                        *out = self.filter(src, guides, b * band + i, step);
+                    }
+                });
+            }
+        });
+    }
+
+    fn filter(&self, src: &[Vec4], guides: &Guides, p: usize, step: usize) -> Vec4 {
+        // B3 spline, the 5 tap kernel of the à-trous transform
+        const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+        let width = guides.width as isize;
+        let height = (src.len() / guides.width) as isize;
+        let (x, y) = ((p % guides.width) as isize, (p / guides.width) as isize);
+
+        let center = src[p];
+        let lum_p = luminance(center.xyz());
+        let normal_p = guides.normal[p];
+        let albedo_p = guides.albedo[p];
+
+        // Luminance differences are measured in standard deviations of the
+        // noise, smoothed over the 3x3 neighborhood so single outliers don't
+        // stop the filter
+        let mut variance = 0.0;
+        let mut variance_weight = 0.0;
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (qx, qy) = (x + dx, y + dy);
+                if qx < 0 || qy < 0 || qx >= width || qy >= height {
+                    continue;
+                }
+                let w = KERNEL[(dx + 2) as usize] * KERNEL[(dy + 2) as usize];
+                variance += w * src[(qy * width + qx) as usize].w;
+                variance_weight += w;
+            }
+        }
+        let sigma = self.color_sigma * (variance / variance_weight).max(0.0).sqrt() + 1e-6;
+
+        let mut sum = Vec3::ZERO;
+        let mut weight_sum = 0.0;
+        let mut weight_sq_sum = 0.0;
+
+        for (ky, &h_y) in KERNEL.iter().enumerate() {
+            let qy = y + (ky as isize - 2) * step as isize;
+            if qy < 0 || qy >= height {
+                continue;
+            }
+            for (kx, &h_x) in KERNEL.iter().enumerate() {
+                let qx = x + (kx as isize - 2) * step as isize;
+                if qx < 0 || qx >= width {
+                    continue;
+                }
+                let q = (qy * width + qx) as usize;
+                let sample = src[q];
+
+                let w_color = (-(luminance(sample.xyz()) - lum_p).abs() / sigma).exp();
+
+                // Misses have no normal and only blend with other misses
+                let normal_q = guides.normal[q];
+                let w_normal = match (normal_p == Vec3::ZERO, normal_q == Vec3::ZERO) {
+                    (true, true) => 1.0,
+                    (false, false) => normal_p.dot(normal_q).max(0.0).powf(self.normal_power),
+                    _ => 0.0,
+                };
+
+                let albedo_distance = (guides.albedo[q] - albedo_p).length_squared();
+                let w_albedo = (-albedo_distance / (self.albedo_sigma * self.albedo_sigma)).exp();
+
+                let w = h_x * h_y * w_color * w_normal * w_albedo;
+                sum += sample.xyz() * w;
+                weight_sum += w;
+                weight_sq_sum += w * w * sample.w;
+            }
+        }
+
+        // The center always contributes, so the weights can't all vanish
+        // unless they underflow
+        if weight_sum <= 0.0 {
+            return center;
+        }
+        (sum / weight_sum).extend(weight_sq_sum / (weight_sum * weight_sum))
+    }
+}
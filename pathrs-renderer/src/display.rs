@@ -0,0 +1,249 @@
+//! Display transform turning linear scene radiance into sRGB encoded colors:
+//! exposure, white balance, a tone curve compressing the dynamic range and
+//! finally the sRGB OETF
+
+use std::{fmt, str::FromStr};
+
+use glam::{Mat3, Vec3, vec3};
+
+use crate::luminance;
+
+/// Color temperature of D65, the white point of sRGB
+pub const NEUTRAL_TEMPERATURE: f32 = 6504.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DisplayTransform {
+    /// Exposure adjustment in stops, each one doubles the brightness
+    pub exposure: f32,
+    /// Color temperature in kelvin of the light that should appear white,
+    /// [`NEUTRAL_TEMPERATURE`] leaves colors unchanged
+    pub white_balance: f32,
+    pub tone_curve: ToneCurve,
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        DisplayTransform {
+            exposure: 0.0,
+            white_balance: NEUTRAL_TEMPERATURE,
+            tone_curve: ToneCurve::default(),
+        }
+    }
+}
+
+/// Mapping of scene referred to display referred linear values in `[0, 1]`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ToneCurve {
+    /// Values above 1 are cut off
+    #[default]
+    Clamp,
+    /// Luminance based `L / (1 + L)`, keeps hues but desaturates slowly
+    Reinhard,
+    /// Stephen Hill's fit of the ACES RRT and sRGB ODT
+    AcesFitted,
+    /// Troy Sobotka's AgX with the polynomial contrast approximation,
+    /// bright colors gradually turn white
+    AgX,
+    /// John Hable's filmic curve from Uncharted 2
+    Filmic,
+}
+
+impl ToneCurve {
+    pub const ALL: [ToneCurve; 5] = [
+        ToneCurve::Clamp,
+        ToneCurve::Reinhard,
+        ToneCurve::AcesFitted,
+        ToneCurve::AgX,
+        ToneCurve::Filmic,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ToneCurve::Clamp => "clamp",
+            ToneCurve::Reinhard => "reinhard",
+            ToneCurve::AcesFitted => "aces",
+            ToneCurve::AgX => "agx",
+            ToneCurve::Filmic => "filmic",
+        }
+    }
+
+    fn map(self, c: Vec3) -> Vec3 {
+        match self {
+            ToneCurve::Clamp => c,
+            ToneCurve::Reinhard => {
+                let l = luminance(c);
+                if l > 0.0 { c / (1.0 + l) } else { c }
+            }
+            ToneCurve::AcesFitted => aces_fitted(c),
+            ToneCurve::AgX => agx(c),
+            ToneCurve::Filmic => {
+                const WHITE: f32 = 11.2;
+                const EXPOSURE_BIAS: f32 = 2.0;
+                (c * EXPOSURE_BIAS).map(hable) / hable(WHITE)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ToneCurve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ToneCurve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ToneCurve::ALL
+            .into_iter()
+            .find(|curve| curve.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<_> = ToneCurve::ALL.iter().map(|c| c.name()).collect();
+                format!(
+                    "unknown tone curve `{s}`, expected one of {}",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+impl DisplayTransform {
+    /// sRGB encoded colors in `[0, 1]` of the linear RGBA `pixels`, alpha is
+    /// dropped
+    pub fn apply(&self, pixels: &[[f32; 4]]) -> Vec<[f32; 3]> {
+        let matrix = white_balance(self.white_balance) * 2f32.powf(self.exposure);
+
+        pixels
+            .iter()
+            .map(|&[r, g, b, _]| {
+                let linear = (matrix * vec3(r, g, b)).max(Vec3::ZERO);
+                let mapped = self.tone_curve.map(linear).clamp(Vec3::ZERO, Vec3::ONE);
+                mapped.map(srgb_oetf).to_array()
+            })
+            .collect()
+    }
+}
+
+fn aces_fitted(c: Vec3) -> Vec3 {
+    // sRGB to the saturated ACES AP1 input of the RRT and from the ODT
+    // output back to sRGB
+    const INPUT: Mat3 = Mat3::from_cols_array(&[
+        0.59719, 0.076, 0.0284, //
+        0.35458, 0.90834, 0.13383, //
+        0.04823, 0.01566, 0.83777,
+    ]);
+    const OUTPUT: Mat3 = Mat3::from_cols_array(&[
+        1.60475, -0.10208, -0.00327, //
+        -0.53108, 1.10813, -0.07276, //
+        -0.07367, -0.00605, 1.07602,
+    ]);
+
+    let v = INPUT * c;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.432951) + 0.238081;
+    OUTPUT * (a / b)
+}
+
+fn agx(c: Vec3) -> Vec3 {
+    #[rustfmt::skip]
+    const INSET: Mat3 = Mat3::from_cols_array(&[
+        0.84247905, 0.042328242, 0.042375654,
+        0.0784336, 0.87846863, 0.0784336,
+        0.079223745, 0.07916613, 0.879143,
+    ]);
+    #[rustfmt::skip]
+    const OUTSET: Mat3 = Mat3::from_cols_array(&[
+        1.196879, -0.052896854, -0.052971635,
+        -0.09802088, 1.1519032, -0.09804345,
+        -0.09902974, -0.098961174, 1.1510737,
+    ]);
+    const MIN_EV: f32 = -12.47393;
+    const MAX_EV: f32 = 4.026069;
+
+    // Log encoding of the inset color, normalized to the covered range
+    let v = (INSET * c).max(Vec3::splat(1e-10)).map(f32::log2);
+    let v = (v.clamp(Vec3::splat(MIN_EV), Vec3::splat(MAX_EV)) - MIN_EV) / (MAX_EV - MIN_EV);
+
+    let v = v.map(|x| {
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    });
+
+    // The contrast curve produces display encoded values with a 2.2 gamma
+    (OUTSET * v).max(Vec3::ZERO).powf(2.2)
+}
+
+fn hable(x: f32) -> f32 {
+    const A: f32 = 0.15;
+    const B: f32 = 0.5;
+    const C: f32 = 0.1;
+    const D: f32 = 0.2;
+    const E: f32 = 0.02;
+    const F: f32 = 0.3;
+    ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F
+}
+
+/// sRGB transfer function of a linear value in `[0, 1]`
+pub fn srgb_oetf(c: f32) -> f32 {
+    if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Linear sRGB matrix adapting light of color `temperature` to D65 with the
+/// Bradford transform
+fn white_balance(temperature: f32) -> Mat3 {
+    const XYZ_FROM_RGB: Mat3 = Mat3::from_cols_array(&[
+        0.4124564, 0.2126729, 0.0193339, //
+        0.3575761, 0.7151522, 0.119192, //
+        0.1804375, 0.072175, 0.9503041,
+    ]);
+    const BRADFORD: Mat3 = Mat3::from_cols_array(&[
+        0.8951, -0.7502, 0.0389, //
+        0.2664, 1.7135, -0.0685, //
+        -0.1614, 0.0367, 1.0296,
+    ]);
+
+    if temperature == NEUTRAL_TEMPERATURE {
+        return Mat3::IDENTITY;
+    }
+
+    // The locus approximation at 6504 K is used as the target white instead
+    // of the exact D65 so the neutral temperature maps to the identity
+    let source = BRADFORD * white_point(temperature);
+    let target = BRADFORD * white_point(NEUTRAL_TEMPERATURE);
+    let scale = Mat3::from_diagonal(target / source);
+
+    XYZ_FROM_RGB.inverse() * BRADFORD.inverse() * scale * BRADFORD * XYZ_FROM_RGB
+}
+
+/// XYZ with `Y = 1` of a white of the given color temperature, on the
+/// Planckian locus below 4000 K and the CIE daylight locus above
+fn white_point(temperature: f32) -> Vec3 {
+    let t = temperature.clamp(1667.0, 25000.0);
+    let (t2, t3) = (t * t, t * t * t);
+
+    let (x, y) = if t < 4000.0 {
+        let x = -0.2661239e9 / t3 - 0.2343589e6 / t2 + 0.8776956e3 / t + 0.17991;
+        let y = if t < 2222.0 {
+            -1.1063814 * x * x * x - 1.3481102 * x * x + 2.1855583 * x - 0.20219683
+        } else {
+            -0.9549476 * x * x * x - 1.3741859 * x * x + 2.09137 * x - 0.16748867
+        };
+        (x, y)
+    } else {
+        let x = if t <= 7000.0 {
+            -4.6070e9 / t3 + 2.9678e6 / t2 + 0.09911e3 / t + 0.244063
+        } else {
+            -2.0064e9 / t3 + 1.9018e6 / t2 + 0.24748e3 / t + 0.23704
+        };
+        (x, -3.0 * x * x + 2.87 * x - 0.275)
+    };
+
+    vec3(x / y, 1.0, (1.0 - x - y) / y)
+}
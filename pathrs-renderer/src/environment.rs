@@ -0,0 +1,386 @@
+use std::{
+    f32::consts::PI,
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use fastrand::Rng;
+use glam::{Vec3, vec3};
+
+use crate::{light::LightSample, luminance};
+
+/// Radiance arriving from infinitely far away, seen by rays that miss
+#[derive(Clone, Default)]
+pub enum Environment {
+    /// Procedural white to blue sky gradient, not importance sampled
+    #[default]
+    Gradient,
+    Map(Box<EnvironmentMap>),
+}
+
+impl Environment {
+    #[inline(always)]
+    pub fn radiance(&self, dir: Vec3) -> Vec3 {
+        match self {
+            Environment::Gradient => {
+                let dir = dir.normalize();
+                let a = 0.5 * (dir.y + 1.0);
+                (1.0 - a) * vec3(1.0, 1.0, 1.0) + a * vec3(0.5, 0.7, 1.0)
+            }
+            Environment::Map(map) => map.radiance(dir),
+        }
+    }
+
+    /// Whether [`Environment::sample`] can produce directions
+    pub fn is_sampled(&self) -> bool {
+        matches!(self, Environment::Map(_))
+    }
+
+    #[inline(always)]
+    pub fn sample(&self, rng: &mut Rng) -> Option<LightSample> {
+        match self {
+            Environment::Gradient => None,
+            Environment::Map(map) => map.sample(rng),
+        }
+    }
+
+    /// Solid angle density of [`Environment::sample`] returning `dir`
+    #[inline(always)]
+    pub fn pdf(&self, dir: Vec3) -> f32 {
+        match self {
+            Environment::Gradient => 0.0,
+            Environment::Map(map) => map.pdf(dir),
+        }
+    }
+}
+
+/// Equirectangular environment image, +y is up and the image center faces
+/// +x before rotation
+#[derive(Clone)]
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3>,
+
+    /// File the map was loaded from, as given to [`EnvironmentMap::load`]
+    pub source: Option<PathBuf>,
+
+    /// Rotation around the up axis, in degrees
+    pub rotation: f32,
+    pub intensity: f32,
+
+    /// Distribution over rows, then over the pixels of each row
+    rows: Distribution1D,
+    columns: Vec<Distribution1D>,
+}
+
+impl EnvironmentMap {
+    pub fn new(width: usize, height: usize, pixels: Vec<Vec3>) -> EnvironmentMap {
+        assert_eq!(pixels.len(), width * height);
+
+        // Weighted by sin θ to undo the stretching towards the poles
+        let columns: Vec<Distribution1D> = (0..height)
+            .map(|y| {
+                let sin_theta = (PI * (y as f32 + 0.5) / height as f32).sin();
+                let row = &pixels[y * width..(y + 1) * width];
+                Distribution1D::new(row.iter().map(|&p| luminance(p) * sin_theta).collect())
+            })
+            .collect();
+
+        let rows = Distribution1D::new(columns.iter().map(|c| c.integral).collect());
+
+        EnvironmentMap {
+            width,
+            height,
+            pixels,
+            source: None,
+            rotation: 0.0,
+            intensity: 1.0,
+            rows,
+            columns,
+        }
+    }
+
+    /// Loads a Radiance `.hdr` (RGBE) image
+    pub fn load(path: impl AsRef<Path>) -> io::Result<EnvironmentMap> {
+        let mut reader = BufReader::new(File::open(path.as_ref())?);
+        let (width, height, pixels) = read_rgbe(&mut reader)?;
+
+        let mut map = EnvironmentMap::new(width, height, pixels);
+        map.source = Some(path.as_ref().to_path_buf());
+        Ok(map)
+    }
+
+    /// Rotation around the up axis in degrees
+    pub fn with_rotation(mut self, degrees: f32) -> Self {
+        self.rotation = degrees;
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    #[inline(always)]
+    fn radiance(&self, dir: Vec3) -> Vec3 {
+        let (u, v) = self.direction_to_uv(dir.normalize());
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+        self.pixels[y * self.width + x] * self.intensity
+    }
+
+    #[inline(always)]
+    fn sample(&self, rng: &mut Rng) -> Option<LightSample> {
+        if self.rows.integral <= 0.0 {
+            return None;
+        }
+
+        let (v, row_pdf, y) = self.rows.sample(rng.f32());
+        let (u, column_pdf, x) = self.columns[y].sample(rng.f32());
+
+        let theta = v * PI;
+        let sin_theta = theta.sin();
+        if sin_theta <= 0.0 {
+            return None;
+        }
+
+        let dir = self.uv_to_direction(u, v);
+
+        Some(LightSample {
+            dir,
+            dist: f32::MAX,
+            emitted: self.pixels[y * self.width + x] * self.intensity,
+            pdf: row_pdf * column_pdf / (2.0 * PI * PI * sin_theta),
+        })
+    }
+
+    #[inline(always)]
+    fn pdf(&self, dir: Vec3) -> f32 {
+        if self.rows.integral <= 0.0 {
+            return 0.0;
+        }
+
+        let (u, v) = self.direction_to_uv(dir.normalize());
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+
+        self.rows.pdf(y) * self.columns[y].pdf(x) / (2.0 * PI * PI * sin_theta)
+    }
+
+    #[inline(always)]
+    fn direction_to_uv(&self, dir: Vec3) -> (f32, f32) {
+        let phi = dir.z.atan2(dir.x) + self.rotation.to_radians();
+        let u = (phi / (2.0 * PI)).rem_euclid(1.0);
+        let v = dir.y.clamp(-1.0, 1.0).acos() / PI;
+        (u, v)
+    }
+
+    #[inline(always)]
+    fn uv_to_direction(&self, u: f32, v: f32) -> Vec3 {
+        let phi = u * 2.0 * PI - self.rotation.to_radians();
+        let theta = v * PI;
+        let sin_theta = theta.sin();
+        vec3(sin_theta * phi.cos(), theta.cos(), sin_theta * phi.sin())
+    }
+}
+
+/// Piecewise constant distribution over [0, 1)
+#[derive(Clone)]
+struct Distribution1D {
+    func: Vec<f32>,
+    cdf: Vec<f32>,
+    /// Average of `func`, the integral over [0, 1)
+    integral: f32,
+}
+
+impl Distribution1D {
+    fn new(func: Vec<f32>) -> Distribution1D {
+        let n = func.len() as f32;
+
+        let mut cdf = Vec::with_capacity(func.len() + 1);
+        cdf.push(0.0);
+        for &f in &func {
+            cdf.push(cdf.last().unwrap() + f.max(0.0) / n);
+        }
+
+        let integral = *cdf.last().unwrap();
+        if integral > 0.0 {
+            for c in &mut cdf {
+                *c /= integral;
+            }
+        } else {
+            for (i, c) in cdf.iter_mut().enumerate() {
+                *c = i as f32 / n;
+            }
+        }
+
+        Distribution1D {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    /// Maps `u` to a point in [0, 1), returning it with its density and the
+    /// segment it falls in
+    #[inline(always)]
+    fn sample(&self, u: f32) -> (f32, f32, usize) {
+        let i = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .saturating_sub(1)
+            .min(self.func.len() - 1);
+
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let offset = if width > 0.0 {
+            (u - self.cdf[i]) / width
+        } else {
+            0.0
+        };
+
+        let x = (i as f32 + offset) / self.func.len() as f32;
+        (x, self.pdf(i), i)
+    }
+
+    #[inline(always)]
+    fn pdf(&self, i: usize) -> f32 {
+        if self.integral > 0.0 {
+            self.func[i].max(0.0) / self.integral
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Reads a Radiance RGBE image with flat or run-length encoded scanlines
+fn read_rgbe(reader: &mut impl BufRead) -> io::Result<(usize, usize, Vec<Vec3>)> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.starts_with("#?") {
+        return Err(invalid("missing Radiance header"));
+    }
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid("unexpected end of header"));
+        }
+
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=")
+            && format != "32-bit_rle_rgbe"
+        {
+            return Err(invalid(
+                "unsupported pixel format, expected 32-bit_rle_rgbe",
+            ));
+        }
+    }
+
+    line.clear();
+    reader.read_line(&mut line)?;
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    let (height, width) = match parts.as_slice() {
+        ["-Y", h, "+X", w] => (h.parse::<usize>(), w.parse::<usize>()),
+        _ => return Err(invalid("unsupported resolution line, expected -Y h +X w")),
+    };
+    let (Ok(height @ 1..), Ok(width @ 1..)) = (height, width) else {
+        return Err(invalid("invalid image size"));
+    };
+
+    let mut pixels = Vec::with_capacity(width * height);
+    let mut scanline = vec![[0u8; 4]; width];
+
+    for _ in 0..height {
+        read_scanline(reader, &mut scanline)?;
+        pixels.extend(scanline.iter().map(|&rgbe| rgbe_to_rgb(rgbe)));
+    }
+
+    Ok((width, height, pixels))
+}
+
+fn read_scanline(reader: &mut impl Read, scanline: &mut [[u8; 4]]) -> io::Result<()> {
+    let width = scanline.len();
+
+    let mut start = [0u8; 4];
+    reader.read_exact(&mut start)?;
+
+    let rle =
+        (8..0x8000).contains(&width) && start[0] == 2 && start[1] == 2 && start[2] & 0x80 == 0;
+
+    if !rle {
+        scanline[0] = start;
+        for pixel in &mut scanline[1..] {
+            reader.read_exact(pixel)?;
+        }
+        return Ok(());
+    }
+
+    if ((start[2] as usize) << 8 | start[3] as usize) != width {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "scanline width mismatch",
+        ));
+    }
+
+    // Each channel is stored separately as runs or literal spans
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let mut count = [0u8; 1];
+            reader.read_exact(&mut count)?;
+
+            let (count, run) = if count[0] > 128 {
+                ((count[0] - 128) as usize, true)
+            } else {
+                (count[0] as usize, false)
+            };
+
+            if count == 0 || x + count > width {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "bad scanline run length",
+                ));
+            }
+
+            if run {
+                let mut value = [0u8; 1];
+                reader.read_exact(&mut value)?;
+                for pixel in &mut scanline[x..x + count] {
+                    pixel[channel] = value[0];
+                }
+            } else {
+                for pixel in &mut scanline[x..x + count] {
+                    let mut value = [0u8; 1];
+                    reader.read_exact(&mut value)?;
+                    pixel[channel] = value[0];
+                }
+            }
+
+            x += count;
+        }
+    }
+
+    Ok(())
+}
+
+#[inline(always)]
+fn rgbe_to_rgb([r, g, b, e]: [u8; 4]) -> Vec3 {
+    if e == 0 {
+        return Vec3::ZERO;
+    }
+
+    let scale = 2f32.powi(e as i32 - 136);
+    vec3(r as f32, g as f32, b as f32) * scale
+}
@@ -0,0 +1,135 @@
+use glam::{Vec3, Vec4};
+
+use crate::{Adaptive, aov::NO_ID, luminance};
+
+/// Running sums of the samples taken for one pixel
+#[derive(Clone, Copy, Default)]
+pub struct Pixel {
+    /// Sum of the radiance samples, `w` counts the samples
+    pub sum: Vec4,
+    /// Sum of the squared sample luminance, for the variance estimate
+    pub lum_sq: f32,
+}
+
+impl Pixel {
+    #[inline(always)]
+    pub fn add(&mut self, radiance: Vec3) {
+        self.sum += radiance.extend(1.0);
+        let lum = luminance(radiance);
+        self.lum_sq += lum * lum;
+    }
+
+    #[inline(always)]
+    pub fn samples(&self) -> u32 {
+        self.sum.w as u32
+    }
+
+    /// Average of the samples so far, `w` is 1 once any were taken
+    #[inline(always)]
+    pub fn mean(&self) -> Vec4 {
+        if self.sum.w > 0.0 {
+            self.sum / self.sum.w
+        } else {
+            Vec4::ZERO
+        }
+    }
+
+    /// Standard error of the mean luminance relative to the mean itself.
+    /// A pixel without any light yet has no error to measure, its error is
+    /// the rule of three bound `3 / n` on the chance that a sample finds
+    /// some, so it needs `3 / threshold` black samples to converge.
+    #[inline(always)]
+    pub fn relative_error(&self) -> f32 {
+        let n = self.sum.w;
+        if n < 2.0 {
+            return f32::INFINITY;
+        }
+
+        let mean = luminance(self.sum.truncate()) / n;
+        if mean <= 0.0 {
+            return 3.0 / n;
+        }
+        self.variance().sqrt() / mean.max(1e-4)
+    }
+
+    /// Variance of the mean luminance, the squared standard error. Below
+    /// two samples the error is taken to be as large as the mean.
+    #[inline(always)]
+    pub fn variance(&self) -> f32 {
+        let n = self.sum.w;
+        let mean = luminance(self.sum.truncate()) / n.max(1.0);
+        if n < 2.0 {
+            return mean * mean;
+        }
+
+        let variance = ((self.lum_sq - n * mean * mean) / (n - 1.0)).max(0.0);
+        variance / n
+    }
+
+    #[inline(always)]
+    pub fn converged(&self, adaptive: &Adaptive) -> bool {
+        self.samples() >= adaptive.min_samples && self.relative_error() < adaptive.threshold
+    }
+}
+
+/// Surface the camera ray of a sample hit first
+pub struct FirstHit {
+    pub albedo: Vec3,
+    pub normal: Vec3,
+    /// Distance from the camera
+    pub depth: f32,
+    pub pos: Vec3,
+    pub material: u32,
+    /// Triangles first, then spheres, then instances, see
+    /// [`crate::aov::Aov::PrimitiveId`]
+    pub primitive: u32,
+}
+
+/// Running sums of the AOVs of one pixel. Albedo and normal average over
+/// every sample with misses counting as zero, depth and position only over
+/// the samples that hit something. The ids are those of the first sample.
+#[derive(Clone, Copy)]
+pub struct AovPixel {
+    pub albedo: Vec3,
+    pub normal: Vec3,
+    pub depth: f32,
+    pub position: Vec3,
+    pub samples: u32,
+    pub hits: u32,
+    pub material: u32,
+    pub primitive: u32,
+}
+
+impl Default for AovPixel {
+    fn default() -> Self {
+        AovPixel {
+            albedo: Vec3::ZERO,
+            normal: Vec3::ZERO,
+            depth: 0.0,
+            position: Vec3::ZERO,
+            samples: 0,
+            hits: 0,
+            material: NO_ID,
+            primitive: NO_ID,
+        }
+    }
+}
+
+impl AovPixel {
+    #[inline(always)]
+    pub fn add(&mut self, hit: Option<&FirstHit>) {
+        if let Some(hit) = hit {
+            self.albedo += hit.albedo;
+            self.normal += hit.normal;
+            self.depth += hit.depth;
+            self.position += hit.pos;
+            self.hits += 1;
+
+            if self.samples == 0 {
+                self.material = hit.material;
+                self.primitive = hit.primitive;
+            }
+        }
+        self.samples += 1;
+    }
+}
@@ -1,6 +1,8 @@
-use glam::{Vec3, vec3};
+use std::{ops::Range, time::Duration};
 
-use crate::{HitRecord, Ray};
+use glam::{Affine3A, Vec3, vec3};
+
+use crate::{HitRecord, Primitive, Ray, mesh::Mesh, metrics::BvhMetrics};
 
 #[cfg(feature = "simd")]
 use crate::simd::*;
@@ -8,6 +10,8 @@ use crate::simd::*;
 #[derive(Clone)]
 pub struct Sphere {
     pos: Vec3,
+    /// Displacement from time zero to time one
+    motion: Vec3,
     r: f32,
     r_squared: f32,
     material: u32,
@@ -15,44 +19,37 @@ pub struct Sphere {
 
 impl Sphere {
     pub fn new(pos: Vec3, r: f32, material: u32) -> Sphere {
+        Sphere::moving(pos, pos, r, material)
+    }
+
+    /// Sphere moving in a straight line from `start` at time zero to `end`
+    /// at time one
+    pub fn moving(start: Vec3, end: Vec3, r: f32, material: u32) -> Sphere {
         Sphere {
-            pos,
+            pos: start,
+            motion: end - start,
             r,
             r_squared: r * r,
             material,
         }
     }
 
-    #[inline(always)]
-    pub fn intersect(&self, ray: &Ray, tmin: f32, tmax: f32) -> Option<HitRecord> {
-        let oc = self.pos - ray.origin;
-        let a = ray.direction.length_squared();
-        let h = ray.direction.dot(oc);
-        let c = oc.length_squared() - self.r_squared;
-        let disc = h * h - a * c;
-
-        if disc < 0.0 {
-            None
-        } else {
-            let sqrtd = disc.sqrt();
+    /// Center at time zero
+    pub fn center(&self) -> Vec3 {
+        self.pos
+    }
 
-            let mut t = (h - sqrtd) / a;
-            if t <= tmin || tmax <= t {
-                t = (h + sqrtd) / a;
-                if t <= tmin || tmax <= t {
-                    return None;
-                }
-            }
+    /// Displacement of the center from time zero to time one
+    pub fn motion(&self) -> Vec3 {
+        self.motion
+    }
 
-            let pos = ray.origin + t * ray.direction;
+    pub fn radius(&self) -> f32 {
+        self.r
+    }
 
-            Some(HitRecord {
-                pos,
-                normal: (pos - self.pos) / self.r,
-                t,
-                material: self.material,
-            })
-        }
+    pub fn material(&self) -> u32 {
+        self.material
     }
 }
 
@@ -61,6 +58,9 @@ pub struct Spheres {
     s_x: Vec<f32>,
     s_y: Vec<f32>,
     s_z: Vec<f32>,
+    m_x: Vec<f32>,
+    m_y: Vec<f32>,
+    m_z: Vec<f32>,
     r_squared: Vec<f32>,
     r_inv: Vec<f32>,
     materials: Vec<u32>,
@@ -71,64 +71,130 @@ impl Spheres {
         self.s_x.push(s.pos.x);
         self.s_y.push(s.pos.y);
         self.s_z.push(s.pos.z);
+        self.m_x.push(s.motion.x);
+        self.m_y.push(s.motion.y);
+        self.m_z.push(s.motion.z);
         self.r_squared.push(s.r_squared);
         self.r_inv.push(1.0 / s.r);
         self.materials.push(s.material);
     }
 
+    /// Replaces sphere `i`
+    pub fn set(&mut self, i: usize, s: Sphere) {
+        self.s_x[i] = s.pos.x;
+        self.s_y[i] = s.pos.y;
+        self.s_z[i] = s.pos.z;
+        self.m_x[i] = s.motion.x;
+        self.m_y[i] = s.motion.y;
+        self.m_z[i] = s.motion.z;
+        self.r_squared[i] = s.r_squared;
+        self.r_inv[i] = 1.0 / s.r;
+        self.materials[i] = s.material;
+    }
+
+    /// Removes sphere `i`, the ones after it move down one index
+    pub fn remove(&mut self, i: usize) {
+        self.s_x.remove(i);
+        self.s_y.remove(i);
+        self.s_z.remove(i);
+        self.m_x.remove(i);
+        self.m_y.remove(i);
+        self.m_z.remove(i);
+        self.r_squared.remove(i);
+        self.r_inv.remove(i);
+        self.materials.remove(i);
+    }
+
+    pub fn len(&self) -> usize {
+        self.s_x.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.s_x.is_empty()
+    }
+
+    /// Center of sphere `i` at time zero
+    pub fn center(&self, i: usize) -> Vec3 {
+        vec3(self.s_x[i], self.s_y[i], self.s_z[i])
+    }
+
+    /// Displacement of sphere `i` from time zero to time one
+    pub fn motion(&self, i: usize) -> Vec3 {
+        vec3(self.m_x[i], self.m_y[i], self.m_z[i])
+    }
+
     #[inline(always)]
-    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
-    pub fn intersect(&self, ray: &Ray, tmin: f32, tmax: f32) -> Option<HitRecord> {
-        let mut closest = tmax;
-        let mut hit = None;
+    pub fn center_at(&self, i: usize, time: f32) -> Vec3 {
+        self.center(i) + time * self.motion(i)
+    }
+
+    pub fn radius_squared(&self, i: usize) -> f32 {
+        self.r_squared[i]
+    }
+
+    pub fn radius(&self, i: usize) -> f32 {
+        self.r_squared[i].sqrt()
+    }
+
+    pub fn material(&self, i: usize) -> u32 {
+        self.materials[i]
+    }
+
+    /// Bounds of each sphere over its whole motion
+    pub fn bounds(&self) -> Vec<Aabb> {
+        (0..self.len())
+            .map(|i| {
+                let (start, end) = (self.center(i), self.center_at(i, 1.0));
+                let r = Vec3::splat(1.0 / self.r_inv[i]);
+                Aabb::new(start.min(end) - r, start.max(end) + r)
+            })
+            .collect()
+    }
+
+    #[inline(always)]
+    pub fn intersect(&self, i: usize, ray: &Ray, tmin: f32, tmax: f32) -> Option<f32> {
         let a = ray.direction.length_squared();
         let a_inv = 1.0 / a;
 
-        for i in 0..self.s_x.len() {
-            let oc_x = self.s_x[i] - ray.origin.x;
-            let oc_y = self.s_y[i] - ray.origin.y;
-            let oc_z = self.s_z[i] - ray.origin.z;
-
-            let h = ray.direction.x * oc_x + ray.direction.y * oc_y + ray.direction.z * oc_z;
-            let c = oc_x * oc_x + oc_y * oc_y + oc_z * oc_z - self.r_squared[i];
-            let disc = h * h - a * c;
-
-            if disc > 0.0 {
-                let sqrtd = disc.sqrt();
-
-                let t = (h - sqrtd) * a_inv;
-                if t > tmin && t < tmax && t < closest {
-                    let pos = ray.origin + t * ray.direction;
-                    let normal =
-                        (pos - vec3(self.s_x[i], self.s_y[i], self.s_z[i])) * self.r_inv[i];
-
-                    closest = t;
-                    hit = Some(HitRecord {
-                        pos,
-                        normal,
-                        t,
-                        material: self.materials[i],
-                    })
-                }
+        let oc_x = self.s_x[i] + self.m_x[i] * ray.time - ray.origin.x;
+        let oc_y = self.s_y[i] + self.m_y[i] * ray.time - ray.origin.y;
+        let oc_z = self.s_z[i] + self.m_z[i] * ray.time - ray.origin.z;
 
-                let t = (h + sqrtd) * a_inv;
-                if t > tmin && t < tmax && t < closest {
-                    let pos = ray.origin + t * ray.direction;
-                    let normal =
-                        (pos - vec3(self.s_x[i], self.s_y[i], self.s_z[i])) * self.r_inv[i];
-
-                    closest = t;
-                    hit = Some(HitRecord {
-                        pos,
-                        normal,
-                        t,
-                        material: self.materials[i],
-                    })
-                }
+        let h = ray.direction.x * oc_x + ray.direction.y * oc_y + ray.direction.z * oc_z;
+        let c = oc_x * oc_x + oc_y * oc_y + oc_z * oc_z - self.r_squared[i];
+        let disc = h * h - a * c;
+
+        if disc > 0.0 {
+            let sqrtd = disc.sqrt();
+
+            let t = (h - sqrtd) * a_inv;
+            if t > tmin && t < tmax {
+                return Some(t);
+            }
+
+            let t = (h + sqrtd) * a_inv;
+            if t > tmin && t < tmax {
+                return Some(t);
             }
         }
 
-        hit
+        None
+    }
+
+    #[cfg(not(feature = "simd"))]
+    #[inline(always)]
+    pub fn hit_record(&self, i: usize, ray: &Ray, t: f32) -> HitRecord {
+        let pos = ray.origin + t * ray.direction;
+        let normal = (pos - self.center_at(i, ray.time)) * self.r_inv[i];
+
+        HitRecord {
+            pos,
+            normal,
+            t,
+            material: self.materials[i],
+            primitive: Primitive::Sphere(i as u32),
+            color: Vec3::ONE,
+        }
     }
 }
 
@@ -139,6 +205,8 @@ pub struct Triangle {
     e2: Vec3,
     normal: Vec3,
     material: u32,
+    normals: Option<[Vec3; 3]>,
+    colors: Option<[Vec3; 3]>,
 }
 
 impl Triangle {
@@ -152,6 +220,25 @@ impl Triangle {
             e2,
             normal,
             material,
+            normals: None,
+            colors: None,
+        }
+    }
+
+    /// Interpolates `normals` over the face instead of using its normal
+    pub fn with_normals(self, normals: [Vec3; 3]) -> Self {
+        Self {
+            normals: Some(normals),
+            ..self
+        }
+    }
+
+    /// Interpolates the linear RGB `colors` over the face, multiplied into
+    /// the material reflectance
+    pub fn with_colors(self, colors: [Vec3; 3]) -> Self {
+        Self {
+            colors: Some(colors),
+            ..self
         }
     }
 
@@ -162,46 +249,12 @@ impl Triangle {
         ]
     }
 
-    pub fn intersect(&self, ray: &Ray, tmin: f32, tmax: f32) -> Option<HitRecord> {
-        let ray_cross_e2 = ray.direction.cross(self.e2);
-        let det = self.e1.dot(ray_cross_e2);
-
-        if det > -f32::EPSILON && det < f32::EPSILON {
-            return None;
-        }
-
-        let inv_det = 1.0 / det;
-        let s = ray.origin - self.v0;
-        let u = inv_det * s.dot(ray_cross_e2);
-        if !(0.0..=1.0).contains(&u) {
-            return None;
-        }
-
-        let s_cross_e1 = s.cross(self.e1);
-        let v = inv_det * ray.direction.dot(s_cross_e1);
-        if v < 0.0 || u + v > 1.0 {
-            return None;
-        }
-
-        let t = inv_det * self.e2.dot(s_cross_e1);
-
-        if t > f32::EPSILON && tmin < t && tmax > t {
-            let pos = ray.origin + ray.direction * t;
-            let normal = if ray.direction.dot(self.normal) < 0.0 {
-                self.normal
-            } else {
-                -self.normal
-            };
+    pub fn material(&self) -> u32 {
+        self.material
+    }
 
-            Some(HitRecord {
-                pos,
-                normal,
-                t,
-                material: self.material,
-            })
-        } else {
-            None
-        }
+    pub fn vertices(&self) -> [Vec3; 3] {
+        [self.v0, self.v0 + self.e1, self.v0 + self.e2]
     }
 }
 
@@ -213,9 +266,32 @@ pub struct Triangles {
     e2: Vec<Vec3>,
     normal: Vec<Vec3>,
     material: Vec<u32>,
+
+    /// Index into the vertex attributes per triangle, [`Triangles::FLAT`]
+    /// for triangles without any
+    attributes: Vec<u32>,
+    vertex_normals: Vec<[Vec3; 3]>,
+    vertex_colors: Vec<[Vec3; 3]>,
+
+    /// Change of every triangle from time zero to time one, empty while
+    /// none of them move
+    motion: Vec<TriangleMotion>,
+}
+
+/// Change of a triangle from time zero to time one, vertices move in
+/// straight lines
+#[derive(Clone, Copy, Default, PartialEq)]
+struct TriangleMotion {
+    v0: Vec3,
+    e1: Vec3,
+    e2: Vec3,
+    /// Change of the vertex normals, for triangles with vertex attributes
+    normals: [Vec3; 3],
 }
 
 impl Triangles {
+    const FLAT: u32 = u32::MAX;
+
     pub fn push(&mut self, tri: Triangle) {
         self.count += 1;
         self.v0.push(tri.v0);
@@ -223,61 +299,364 @@ impl Triangles {
         self.e2.push(tri.e2);
         self.normal.push(tri.normal);
         self.material.push(tri.material);
-    }
-
-    #[inline(always)]
-    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
-    pub fn intersect(&self, ray: &Ray, tmin: f32, tmax: f32) -> Option<HitRecord> {
-        let mut closest = tmax;
-        let mut idx = None;
-        let mut pos = Vec3::ZERO;
+        if !self.motion.is_empty() {
+            self.motion.push(TriangleMotion::default());
+        }
 
-        for i in 0..self.count {
-            let ray_cross_e2 = ray.direction.cross(self.e2[i]);
-            let det = self.e1[i].dot(ray_cross_e2);
+        if tri.normals.is_none() && tri.colors.is_none() {
+            self.attributes.push(Self::FLAT);
+            return;
+        }
+        self.attributes.push(self.vertex_normals.len() as u32);
+        self.vertex_normals
+            .push(tri.normals.unwrap_or([tri.normal; 3]));
+        self.vertex_colors
+            .push(tri.colors.unwrap_or([Vec3::ONE; 3]));
+    }
 
-            if det > -f32::EPSILON && det < f32::EPSILON {
+    /// Adds the faces of `mesh`, skipping degenerate ones. Faces get vertex
+    /// attributes when the mesh has normals or colors, missing normals fall
+    /// back to the face normal and missing colors to white.
+    pub fn extend(&mut self, mesh: &Mesh) {
+        let smooth = !mesh.normals.is_empty() || !mesh.colors.is_empty();
+
+        for (face, &material) in mesh.faces.iter().zip(&mesh.materials) {
+            let [v0, v1, v2] = face.map(|i| mesh.positions[i as usize]);
+            let e1 = v1 - v0;
+            let e2 = v2 - v0;
+            let cross = e1.cross(e2);
+            if cross.length_squared() == 0.0 {
                 continue;
             }
-
-            let inv_det = 1.0 / det;
-            let s = ray.origin - self.v0[i];
-            let u = inv_det * s.dot(ray_cross_e2);
-            if !(0.0..=1.0).contains(&u) {
-                continue;
+            let normal = cross.normalize();
+
+            self.count += 1;
+            self.v0.push(v0);
+            self.e1.push(e1);
+            self.e2.push(e2);
+            self.normal.push(normal);
+            self.material.push(material);
+            if !self.motion.is_empty() {
+                self.motion.push(TriangleMotion::default());
             }
 
-            let s_cross_e1 = s.cross(self.e1[i]);
-            let v = inv_det * ray.direction.dot(s_cross_e1);
-            if v < 0.0 || u + v > 1.0 {
+            if !smooth {
+                self.attributes.push(Self::FLAT);
                 continue;
             }
 
-            let t = inv_det * self.e2[i].dot(s_cross_e1);
+            self.attributes.push(self.vertex_normals.len() as u32);
+            self.vertex_normals.push(match mesh.normals.is_empty() {
+                true => [normal; 3],
+                false => face.map(|i| mesh.normals[i as usize]),
+            });
+            self.vertex_colors.push(match mesh.colors.is_empty() {
+                true => [Vec3::ONE; 3],
+                false => face.map(|i| mesh.colors[i as usize]),
+            });
+        }
+    }
 
-            if t > f32::EPSILON && tmin < t && closest > t {
-                closest = t;
-                idx = Some(i);
-                pos = ray.origin + ray.direction * t;
+    /// Removes the triangles in `range` and their vertex attributes, the
+    /// ones after it move down
+    pub fn remove(&mut self, range: Range<usize>) {
+        // Attributes are pushed in triangle order, so those of `range` are
+        // one block as well
+        let attributes: Vec<_> = self.attributes[range.clone()]
+            .iter()
+            .copied()
+            .filter(|&a| a != Self::FLAT)
+            .collect();
+        if let (Some(&first), Some(&last)) = (attributes.first(), attributes.last()) {
+            let removed = last - first + 1;
+            self.vertex_normals.drain(first as usize..=last as usize);
+            self.vertex_colors.drain(first as usize..=last as usize);
+            for a in &mut self.attributes[range.end..] {
+                if *a != Self::FLAT {
+                    *a -= removed;
+                }
             }
         }
 
-        if let Some(i) = idx {
-            let normal = if ray.direction.dot(self.normal[i]) < 0.0 {
-                self.normal[i]
+        self.count -= range.len();
+        self.v0.drain(range.clone());
+        self.e1.drain(range.clone());
+        self.e2.drain(range.clone());
+        self.normal.drain(range.clone());
+        self.material.drain(range.clone());
+        if !self.motion.is_empty() {
+            self.motion.drain(range.clone());
+        }
+        self.attributes.drain(range);
+    }
+
+    /// Moves the triangles in `range` out into their own list, with their
+    /// vertex attributes and motion
+    pub fn split_off(&mut self, range: Range<usize>) -> Triangles {
+        let mut split = Triangles::default();
+        for i in range.clone() {
+            split.count += 1;
+            split.v0.push(self.v0[i]);
+            split.e1.push(self.e1[i]);
+            split.e2.push(self.e2[i]);
+            split.normal.push(self.normal[i]);
+            split.material.push(self.material[i]);
+
+            let a = self.attributes[i];
+            if a == Self::FLAT {
+                split.attributes.push(Self::FLAT);
             } else {
-                -self.normal[i]
-            };
-            Some(HitRecord {
-                pos,
-                normal,
-                t: closest,
-                material: self.material[i],
+                split.attributes.push(split.vertex_normals.len() as u32);
+                split.vertex_normals.push(self.vertex_normals[a as usize]);
+                split.vertex_colors.push(self.vertex_colors[a as usize]);
+            }
+        }
+        if !self.motion.is_empty()
+            && self.motion[range.clone()]
+                .iter()
+                .any(|m| *m != TriangleMotion::default())
+        {
+            split.motion = self.motion[range.clone()].to_vec();
+        }
+
+        self.remove(range);
+        split
+    }
+
+    /// Makes triangle `i` move in a straight line from its vertices at time
+    /// zero to `end` at time one
+    pub fn set_motion(&mut self, i: usize, end: [Vec3; 3]) {
+        let [v0, v1, v2] = end;
+        let (start_v0, start_e1, start_e2) = self.edges(i);
+        let motion = &mut self.motion_mut()[i];
+        motion.v0 = v0 - start_v0;
+        motion.e1 = (v1 - v0) - start_e1;
+        motion.e2 = (v2 - v0) - start_e2;
+    }
+
+    /// Makes the triangles in `range` move to where `matrix` maps their
+    /// vertices at time zero, turning their vertex normals along. Vertices
+    /// move in straight lines, so rotations pass through smaller shapes.
+    pub fn set_motion_transform(&mut self, range: Range<usize>, matrix: Affine3A) {
+        let normal_matrix = matrix.matrix3.inverse().transpose();
+        for i in range {
+            let end = self.vertices(i).map(|v| matrix.transform_point3(v));
+            self.set_motion(i, end);
+
+            let a = self.attributes[i];
+            if a != Self::FLAT {
+                let normals = self.vertex_normals[a as usize];
+                self.motion[i].normals =
+                    normals.map(|n| normal_matrix.mul_vec3(n).normalize_or_zero() - n);
+            }
+        }
+    }
+
+    /// Per triangle motion, allocated on first use
+    fn motion_mut(&mut self) -> &mut [TriangleMotion] {
+        if self.motion.is_empty() {
+            self.motion.resize(self.count, TriangleMotion::default());
+        }
+        &mut self.motion
+    }
+
+    /// Vertices of triangle `i` at time one, `None` if it doesn't move
+    pub fn vertices_end(&self, i: usize) -> Option<[Vec3; 3]> {
+        let motion = self.motion.get(i)?;
+        if *motion == TriangleMotion::default() {
+            return None;
+        }
+        let (v0, e1, e2) = self.edges_at(i, 1.0);
+        Some([v0, v0 + e1, v0 + e2])
+    }
+
+    /// Vertex normals of triangle `i` at time zero, `None` if it is flat
+    /// shaded
+    pub fn vertex_normals(&self, i: usize) -> Option<[Vec3; 3]> {
+        match self.attributes[i] {
+            Self::FLAT => None,
+            a => Some(self.vertex_normals[a as usize]),
+        }
+    }
+
+    /// Vertex colors of triangle `i`, `None` if it is white
+    pub fn vertex_colors(&self, i: usize) -> Option<[Vec3; 3]> {
+        match self.attributes[i] {
+            Self::FLAT => None,
+            a => Some(self.vertex_colors[a as usize]).filter(|c| *c != [Vec3::ONE; 3]),
+        }
+    }
+
+    /// Moves the triangles in `range` by `offset`, at all times
+    pub fn translate(&mut self, range: Range<usize>, offset: Vec3) {
+        for v0 in &mut self.v0[range] {
+            *v0 += offset;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Area of triangle `i` at time zero
+    pub fn area(&self, i: usize) -> f32 {
+        0.5 * self.e1[i].cross(self.e2[i]).length()
+    }
+
+    /// Face normal of triangle `i` at time zero
+    pub fn normal(&self, i: usize) -> Vec3 {
+        self.normal[i]
+    }
+
+    #[inline(always)]
+    pub fn normal_at(&self, i: usize, time: f32) -> Vec3 {
+        match self.motion.get(i) {
+            Some(m) if m.e1 != Vec3::ZERO || m.e2 != Vec3::ZERO => {
+                let (_, e1, e2) = self.edges_at(i, time);
+                e1.cross(e2).try_normalize().unwrap_or(self.normal[i])
+            }
+            _ => self.normal[i],
+        }
+    }
+
+    pub fn material(&self, i: usize) -> u32 {
+        self.material[i]
+    }
+
+    /// Returns `v0` and the two edges of triangle `i` at time zero
+    pub fn edges(&self, i: usize) -> (Vec3, Vec3, Vec3) {
+        (self.v0[i], self.e1[i], self.e2[i])
+    }
+
+    /// Returns `v0` and the two edges of triangle `i` at `time`
+    #[inline(always)]
+    pub fn edges_at(&self, i: usize, time: f32) -> (Vec3, Vec3, Vec3) {
+        match self.motion.get(i) {
+            Some(m) => (
+                self.v0[i] + time * m.v0,
+                self.e1[i] + time * m.e1,
+                self.e2[i] + time * m.e2,
+            ),
+            None => self.edges(i),
+        }
+    }
+
+    /// Vertices of triangle `i` at time zero
+    pub fn vertices(&self, i: usize) -> [Vec3; 3] {
+        let v0 = self.v0[i];
+        [v0, v0 + self.e1[i], v0 + self.e2[i]]
+    }
+
+    /// Bounds of each triangle over its whole motion, its vertices move in
+    /// straight lines so the boxes at both ends cover it
+    pub fn bounds(&self) -> Vec<Aabb> {
+        (0..self.count)
+            .map(|i| {
+                let [v0, v1, v2] = self.vertices(i);
+                let start = Aabb::new(v0.min(v1).min(v2), v0.max(v1).max(v2));
+                match self.vertices_end(i) {
+                    Some(end) => end.into_iter().fold(start, Aabb::grow),
+                    None => start,
+                }
             })
+            .collect()
+    }
+
+    #[inline(always)]
+    pub fn intersect(&self, i: usize, ray: &Ray, tmin: f32, tmax: f32) -> Option<f32> {
+        let (v0, e1, e2) = self.edges_at(i, ray.time);
+        let ray_cross_e2 = ray.direction.cross(e2);
+        let det = e1.dot(ray_cross_e2);
+
+        if det > -f32::EPSILON && det < f32::EPSILON {
+            return None;
+        }
+
+        let inv_det = 1.0 / det;
+        let s = ray.origin - v0;
+        let u = inv_det * s.dot(ray_cross_e2);
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let s_cross_e1 = s.cross(e1);
+        let v = inv_det * ray.direction.dot(s_cross_e1);
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = inv_det * e2.dot(s_cross_e1);
+
+        if t > f32::EPSILON && tmin < t && tmax > t {
+            Some(t)
         } else {
             None
         }
     }
+
+    #[cfg(not(feature = "simd"))]
+    #[inline(always)]
+    pub fn hit_record(&self, i: usize, ray: &Ray, t: f32) -> HitRecord {
+        let face_normal = self.normal_at(i, ray.time);
+        let normal = if ray.direction.dot(face_normal) < 0.0 {
+            face_normal
+        } else {
+            -face_normal
+        };
+
+        let mut hit = HitRecord {
+            pos: ray.origin + ray.direction * t,
+            normal,
+            t,
+            material: self.material[i],
+            primitive: Primitive::Triangle(i as u32),
+            color: Vec3::ONE,
+        };
+        self.shade(i, ray.time, &mut hit);
+        hit
+    }
+
+    /// Replaces the face normal of a hit on triangle `i` at `time` with the
+    /// interpolated vertex normal, on the same side, and sets its color
+    #[inline(always)]
+    pub fn shade(&self, i: usize, time: f32, hit: &mut HitRecord) {
+        let a = self.attributes[i];
+        if a == Self::FLAT {
+            return;
+        }
+
+        let (v0, e1, e2) = self.edges_at(i, time);
+        let p = hit.pos - v0;
+        let (d11, d12, d22) = (e1.dot(e1), e1.dot(e2), e2.dot(e2));
+        let (p1, p2) = (p.dot(e1), p.dot(e2));
+        let denom = d11 * d22 - d12 * d12;
+        let b1 = ((d22 * p1 - d12 * p2) / denom).clamp(0.0, 1.0);
+        let b2 = ((d11 * p2 - d12 * p1) / denom).clamp(0.0, 1.0 - b1);
+        let b0 = 1.0 - b1 - b2;
+
+        let mut normals = self.vertex_normals[a as usize];
+        if let Some(motion) = self.motion.get(i) {
+            for (n, dn) in normals.iter_mut().zip(motion.normals) {
+                *n += time * dn;
+            }
+        }
+        let [n0, n1, n2] = normals;
+        let normal = (b0 * n0 + b1 * n1 + b2 * n2).normalize_or_zero();
+        if normal != Vec3::ZERO {
+            hit.normal = if normal.dot(hit.normal) < 0.0 {
+                -normal
+            } else {
+                normal
+            };
+        }
+
+        let [c0, c1, c2] = self.vertex_colors[a as usize];
+        hit.color = b0 * c0 + b1 * c1 + b2 * c2;
+    }
 }
 
 #[cfg(feature = "simd")]
@@ -299,40 +678,43 @@ pub struct TrianglesSIMD {
 
     normal: Vec<Vec3>,
     material: Vec<u32>,
+    /// Index into [`Triangles`] per slot, [`Bvh::PADDING`] for padding
+    index: Vec<u32>,
+
+    /// Change of `v0`, `e1` and `e2` from time zero to one per packet,
+    /// empty while no triangle moves
+    motion: Vec<[Vec3x8; 3]>,
 }
 
+/// Gathers one lane per index, lanes with [`Bvh::PADDING`] are zeroed
 #[cfg(feature = "simd")]
 macro_rules! pack_f32x8 {
-    ($src:expr, $field:tt, $i:ident) => {
-        f32x8::from_array([
-            $src[$i].$field,
-            $src[$i + 1].$field,
-            $src[$i + 2].$field,
-            $src[$i + 3].$field,
-            $src[$i + 4].$field,
-            $src[$i + 5].$field,
-            $src[$i + 6].$field,
-            $src[$i + 7].$field,
-        ])
+    ($src:expr, $field:tt, $idx:expr) => {
+        f32x8::from_array(std::array::from_fn(|lane| match $idx[lane] {
+            Bvh::PADDING => 0.0,
+            i => $src[i as usize].$field,
+        }))
     };
 }
 
+#[cfg(feature = "simd")]
 macro_rules! push8 {
-    ($dst:expr, $src:expr, $i:ident) => {
-        $dst.push($src[$i]);
-        $dst.push($src[$i + 1]);
-        $dst.push($src[$i + 2]);
-        $dst.push($src[$i + 3]);
-        $dst.push($src[$i + 4]);
-        $dst.push($src[$i + 5]);
-        $dst.push($src[$i + 6]);
-        $dst.push($src[$i + 7]);
+    ($dst:expr, $src:expr, $idx:expr) => {
+        for &i in $idx {
+            $dst.push(match i {
+                Bvh::PADDING => Default::default(),
+                i => $src[i as usize],
+            });
+        }
     };
 }
 
 #[cfg(feature = "simd")]
 impl TrianglesSIMD {
-    pub fn from_tris(tris: Triangles) -> Self {
+    /// Packs the triangles in `indices` into groups of eight, in order.
+    /// [`Bvh::PADDING`] entries and the lanes past the end of a short last
+    /// group become degenerate triangles that never hit.
+    pub fn from_tris(tris: &Triangles, indices: &[u32]) -> Self {
         let mut packed_count = 0;
         let mut v0_x = vec![];
         let mut v0_y = vec![];
@@ -348,29 +730,45 @@ impl TrianglesSIMD {
 
         let mut normal = vec![];
         let mut material = vec![];
-
-        let count = tris.count / 8;
-        let mut i = 0;
-        while i < count {
-            let base = i * 8;
-            v0_x.push(pack_f32x8!(tris.v0, x, base));
-            v0_y.push(pack_f32x8!(tris.v0, y, base));
-            v0_z.push(pack_f32x8!(tris.v0, z, base));
-
-            e1_x.push(pack_f32x8!(tris.e1, x, base));
-            e1_y.push(pack_f32x8!(tris.e1, y, base));
-            e1_z.push(pack_f32x8!(tris.e1, z, base));
-
-            e2_x.push(pack_f32x8!(tris.e2, x, base));
-            e2_y.push(pack_f32x8!(tris.e2, y, base));
-            e2_z.push(pack_f32x8!(tris.e2, z, base));
-
-            push8!(normal, tris.normal, base);
-            push8!(material, tris.material, base);
+        let mut index = vec![];
+        let mut motion = vec![];
+
+        for chunk in indices.chunks(8) {
+            let mut idx = [Bvh::PADDING; 8];
+            idx[..chunk.len()].copy_from_slice(chunk);
+
+            v0_x.push(pack_f32x8!(tris.v0, x, idx));
+            v0_y.push(pack_f32x8!(tris.v0, y, idx));
+            v0_z.push(pack_f32x8!(tris.v0, z, idx));
+
+            e1_x.push(pack_f32x8!(tris.e1, x, idx));
+            e1_y.push(pack_f32x8!(tris.e1, y, idx));
+            e1_z.push(pack_f32x8!(tris.e1, z, idx));
+
+            e2_x.push(pack_f32x8!(tris.e2, x, idx));
+            e2_y.push(pack_f32x8!(tris.e2, y, idx));
+            e2_z.push(pack_f32x8!(tris.e2, z, idx));
+
+            push8!(normal, tris.normal, &idx);
+            push8!(material, tris.material, &idx);
+            index.extend_from_slice(&idx);
+
+            if !tris.motion.is_empty() {
+                let pack = |field: fn(&TriangleMotion) -> Vec3| {
+                    let lanes: [Vec3; 8] = std::array::from_fn(|lane| match idx[lane] {
+                        Bvh::PADDING => Vec3::ZERO,
+                        i => field(&tris.motion[i as usize]),
+                    });
+                    Vec3x8 {
+                        x: f32x8::from_array(lanes.map(|v| v.x)),
+                        y: f32x8::from_array(lanes.map(|v| v.y)),
+                        z: f32x8::from_array(lanes.map(|v| v.z)),
+                    }
+                };
+                motion.push([pack(|m| m.v0), pack(|m| m.e1), pack(|m| m.e2)]);
+            }
 
             packed_count += 1;
-
-            i += 1;
         }
 
         Self {
@@ -386,108 +784,190 @@ impl TrianglesSIMD {
             e2_z,
             normal,
             material,
+            index,
+            motion,
         }
     }
 
+    /// Triangles of `tris` packed with the vertex data they have there.
+    /// Padding lanes, lanes naming a triangle `tris` doesn't have or one
+    /// already counted, and lanes whose data differs aren't counted.
+    pub fn triangle_count(&self, tris: &Triangles) -> usize {
+        let mut seen = vec![false; tris.len()];
+
+        (0..self.index.len())
+            .filter(|&slot| {
+                let i = self.index[slot] as usize;
+                let (p, lane) = (slot / 8, slot % 8);
+                if seen.get(i) != Some(&false) {
+                    return false;
+                }
+                seen[i] = true;
+
+                // Bitwise, so NaN vertices still match themselves
+                let packed = [
+                    [&self.v0_x, &self.v0_y, &self.v0_z],
+                    [&self.e1_x, &self.e1_y, &self.e1_z],
+                    [&self.e2_x, &self.e2_y, &self.e2_z],
+                ]
+                .map(|v| v.map(|c| c[p][lane].to_bits()));
+                let expected =
+                    [tris.v0[i], tris.e1[i], tris.e2[i]].map(|v| v.to_array().map(f32::to_bits));
+                packed == expected
+            })
+            .count()
+    }
+
+    /// Intersects the ray with all eight lanes of packet `i` at `time`,
+    /// returning the closest `t`, its slot and the determinant sign needed
+    /// for the normal
     #[inline(always)]
     #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
     pub fn intersect(
         &self,
-        ray: &Ray,
+        i: usize,
         tmin: f32,
         tmax: f32,
         r_o: Vec3x8,
         r_d: Vec3x8,
-    ) -> Option<HitRecord> {
-        let tmin = f32x8::splat(tmin);
+        time: f32,
+    ) -> Option<(f32, usize, f32)> {
+        let (t, det, misses) = self.lanes(i, tmin, tmax, r_o, r_d, time);
 
-        let mut closest = f32x8::splat(tmax);
-        let mut closest_idx = i32x8::splat(-1);
-        let mut closest_det = f32x8::ZERO;
+        let closest_idx = i32x8::select(
+            i32x8::from_array([0, 1, 2, 3, 4, 5, 6, 7]),
+            i32x8::splat(-1),
+            misses,
+        );
 
-        let mut tri_idx = i32x8::from_array([0, 1, 2, 3, 4, 5, 6, 7]);
-        let stride = i32x8::splat(8);
-
-        for i in 0..self.packed_count {
-            let v0 = Vec3x8 {
-                x: self.v0_x[i],
-                y: self.v0_y[i],
-                z: self.v0_z[i],
-            };
-            let e1 = Vec3x8 {
-                x: self.e1_x[i],
-                y: self.e1_y[i],
-                z: self.e1_z[i],
-            };
-            let e2 = Vec3x8 {
-                x: self.e2_x[i],
-                y: self.e2_y[i],
-                z: self.e2_z[i],
-            };
-
-            let ray_cross_e2 = r_d.cross(e2);
-
-            let det = e1.dot(ray_cross_e2);
+        if closest_idx.transmute_f32x8().movemask() == 0xFF {
+            return None;
+        }
 
-            let inv_det = f32x8::ONE / det;
+        let closest = f32x8::blend(t, f32x8::splat(tmax), misses);
 
-            let s = r_o - v0;
+        let min = closest.horizontal_min();
 
-            let s_cross_e1 = s.cross(e1);
+        let t = min[0];
 
-            let u = inv_det * s.dot(ray_cross_e2);
-            let v = inv_det * r_d.dot(s_cross_e1);
+        let mask = min.cmp_eq(closest).as_f32x8().movemask();
+        let lane = mask.trailing_zeros() as usize;
 
-            let t = inv_det * e2.dot(s_cross_e1);
+        Some((t, i * 8 + closest_idx[lane] as usize, det[lane]))
+    }
 
-            let mut misses = det.cmp_gt(f32x8::NEGATIVE_EPSILON) & det.cmp_lt(f32x8::EPSILON);
-            misses |= u.cmp_lt(f32x8::ZERO);
-            misses |= v.cmp_lt(f32x8::ZERO);
-            misses |= (u + v).cmp_gt(f32x8::ONE);
-            misses |= t.cmp_lt(f32x8::EPSILON);
-            misses |= t.cmp_lt(tmin);
-            misses |= t.cmp_gt(closest);
+    /// Whether any lane of packet `i` is hit at `time` between `tmin` and
+    /// `tmax`, without finding the closest
+    #[inline(always)]
+    pub fn occludes(
+        &self,
+        i: usize,
+        tmin: f32,
+        tmax: f32,
+        r_o: Vec3x8,
+        r_d: Vec3x8,
+        time: f32,
+    ) -> bool {
+        let (_, _, misses) = self.lanes(i, tmin, tmax, r_o, r_d, time);
+        misses.as_f32x8().movemask() != 0xFF
+    }
 
-            closest = f32x8::blend(t, closest, misses);
-            closest_idx = i32x8::select(tri_idx, closest_idx, misses);
-            closest_det = f32x8::blend(det, closest_det, misses);
+    /// Distance and determinant per lane of packet `i`, and the lanes that
+    /// miss
+    #[inline(always)]
+    fn lanes(
+        &self,
+        i: usize,
+        tmin: f32,
+        tmax: f32,
+        r_o: Vec3x8,
+        r_d: Vec3x8,
+        time: f32,
+    ) -> (f32x8, f32x8, Bitmask) {
+        debug_assert!(i < self.packed_count);
 
-            tri_idx += stride;
+        let tmin = f32x8::splat(tmin);
+        let closest = f32x8::splat(tmax);
+
+        let mut v0 = Vec3x8 {
+            x: self.v0_x[i],
+            y: self.v0_y[i],
+            z: self.v0_z[i],
+        };
+        let mut e1 = Vec3x8 {
+            x: self.e1_x[i],
+            y: self.e1_y[i],
+            z: self.e1_z[i],
+        };
+        let mut e2 = Vec3x8 {
+            x: self.e2_x[i],
+            y: self.e2_y[i],
+            z: self.e2_z[i],
+        };
+
+        if let Some(&[dv0, de1, de2]) = self.motion.get(i) {
+            let time = f32x8::splat(time);
+            v0 = dv0.mul_add(time, v0);
+            e1 = de1.mul_add(time, e1);
+            e2 = de2.mul_add(time, e2);
         }
 
-        let mask = closest_idx.transmute_f32x8().movemask();
+        let ray_cross_e2 = r_d.cross(e2);
 
-        if mask == 0xFF {
-            None
-        } else {
-            let v1 = f32x8::min(closest, closest.permute::<0b10_11_00_01>());
-            let v2 = f32x8::min(v1, v1.permute::<0b01_00_11_10>());
-            let v3 = f32x8::min(v2, f32x8::permute_2f128::<0b0000_0001>(v2, v2));
+        let det = e1.dot(ray_cross_e2);
 
-            let t = v3[0];
+        let inv_det = f32x8::ONE / det;
 
-            let mask = v3.cmp_eq(closest).as_f32x8().movemask();
-            let i = mask.trailing_zeros() as usize;
+        let s = r_o - v0;
 
-            let tri_idx = closest_idx[i] as usize;
-            let det = closest_det[i];
+        let s_cross_e1 = s.cross(e1);
 
-            let pos = ray.origin + t * ray.direction;
+        let u = inv_det * s.dot(ray_cross_e2);
+        let v = inv_det * r_d.dot(s_cross_e1);
 
-            let mut normal = self.normal[tri_idx];
-            if det < 0.0 {
-                normal = -normal;
-            }
+        let t = inv_det * e2.dot(s_cross_e1);
 
-            let material = self.material[tri_idx];
+        let mut misses = det.cmp_gt(f32x8::NEGATIVE_EPSILON) & det.cmp_lt(f32x8::EPSILON);
+        misses |= u.cmp_lt(f32x8::ZERO);
+        misses |= v.cmp_lt(f32x8::ZERO);
+        misses |= (u + v).cmp_gt(f32x8::ONE);
+        misses |= t.cmp_lt(f32x8::EPSILON);
+        misses |= t.cmp_lt(tmin);
+        // Strict like the scalar test, so a miss lane can't tie with a hit
+        misses |= t.cmp_nlt(closest);
 
-            Some(HitRecord {
-                pos,
-                normal,
-                t,
-                material,
-            })
+        (t, det, misses)
+    }
+
+    /// `tris` are the triangles this was packed from, for the vertex
+    /// attributes
+    #[inline(always)]
+    pub fn hit_record(
+        &self,
+        slot: usize,
+        ray: &Ray,
+        t: f32,
+        det: f32,
+        tris: &Triangles,
+    ) -> HitRecord {
+        let mut normal = match self.motion.is_empty() {
+            true => self.normal[slot],
+            false => tris.normal_at(self.index[slot] as usize, ray.time),
+        };
+        if det < 0.0 {
+            normal = -normal;
         }
+
+        let mut hit = HitRecord {
+            pos: ray.origin + t * ray.direction,
+            normal,
+            t,
+            material: self.material[slot],
+            primitive: Primitive::Triangle(self.index[slot]),
+            color: Vec3::ONE,
+        };
+        tris.shade(self.index[slot] as usize, ray.time, &mut hit);
+        hit
     }
 }
 
@@ -500,56 +980,83 @@ pub struct SpheresSIMD {
     pos_y: Vec<f32x8>,
     pos_z: Vec<f32x8>,
 
+    /// Displacement from time zero to time one
+    motion_x: Vec<f32x8>,
+    motion_y: Vec<f32x8>,
+    motion_z: Vec<f32x8>,
+
     r_squared: Vec<f32x8>,
 
     r_inv: Vec<f32>,
     material: Vec<u32>,
+    /// Index into [`Spheres`] per slot, [`Bvh::PADDING`] for padding
+    index: Vec<u32>,
 }
 
 #[cfg(feature = "simd")]
 impl SpheresSIMD {
-    pub fn from_spheres(spheres: Spheres) -> SpheresSIMD {
+    /// Packs the spheres in `indices` into groups of eight, in order. Partial
+    /// packets and [`Bvh::PADDING`] entries get a negative squared radius so
+    /// their discriminant is always negative.
+    pub fn from_spheres(spheres: &Spheres, indices: &[u32]) -> SpheresSIMD {
         let mut pos_x = Vec::new();
         let mut pos_y = Vec::new();
         let mut pos_z = Vec::new();
 
+        let mut motion_x = Vec::new();
+        let mut motion_y = Vec::new();
+        let mut motion_z = Vec::new();
+
         let mut r_squared = Vec::new();
 
         let mut r_inv = Vec::new();
         let mut material = Vec::new();
+        let mut index = Vec::new();
 
-        let count = spheres.s_x.len();
-        let mut i = 0;
-
-        while i < count {
+        for idx in indices.chunks(8) {
             let mut x = [0.0; 8];
             let mut y = [0.0; 8];
             let mut z = [0.0; 8];
-            let mut rsq = [0.0; 8];
-
-            let max_j = (count - i).min(8);
-
-            for j in 0..max_j {
-                x[j] = spheres.s_x[i + j];
-                y[j] = spheres.s_y[i + j];
-                z[j] = spheres.s_z[i + j];
-                rsq[j] = spheres.r_squared[i + j];
+            let mut m_x = [0.0; 8];
+            let mut m_y = [0.0; 8];
+            let mut m_z = [0.0; 8];
+            let mut rsq = [-1.0; 8];
+
+            for (j, &i) in idx.iter().enumerate() {
+                if i == Bvh::PADDING {
+                    r_inv.push(0.0);
+                    material.push(0);
+                    index.push(Bvh::PADDING);
+                    continue;
+                }
 
-                r_inv.push(spheres.r_inv[i + j]);
-                material.push(spheres.materials[i + j]);
+                let i = i as usize;
+                x[j] = spheres.s_x[i];
+                y[j] = spheres.s_y[i];
+                z[j] = spheres.s_z[i];
+                m_x[j] = spheres.m_x[i];
+                m_y[j] = spheres.m_y[i];
+                m_z[j] = spheres.m_z[i];
+                rsq[j] = spheres.r_squared[i];
+
+                r_inv.push(spheres.r_inv[i]);
+                material.push(spheres.materials[i]);
+                index.push(i as u32);
             }
 
-            for _ in max_j..8 {
+            for _ in idx.len()..8 {
                 r_inv.push(0.0);
                 material.push(0);
+                index.push(Bvh::PADDING);
             }
 
             pos_x.push(f32x8::from_array(x));
             pos_y.push(f32x8::from_array(y));
             pos_z.push(f32x8::from_array(z));
+            motion_x.push(f32x8::from_array(m_x));
+            motion_y.push(f32x8::from_array(m_y));
+            motion_z.push(f32x8::from_array(m_z));
             r_squared.push(f32x8::from_array(rsq));
-
-            i += 8;
         }
 
         let packed_count = pos_x.len();
@@ -559,95 +1066,889 @@ impl SpheresSIMD {
             pos_x,
             pos_y,
             pos_z,
+            motion_x,
+            motion_y,
+            motion_z,
             r_squared,
             r_inv,
             material,
+            index,
         }
     }
 
+    /// Spheres of `spheres` packed with their center and radius, counted
+    /// like [`TrianglesSIMD::triangle_count`]
+    pub fn sphere_count(&self, spheres: &Spheres) -> usize {
+        let mut seen = vec![false; spheres.len()];
+
+        (0..self.index.len())
+            .filter(|&slot| {
+                let i = self.index[slot] as usize;
+                let (p, lane) = (slot / 8, slot % 8);
+                if seen.get(i) != Some(&false) {
+                    return false;
+                }
+                seen[i] = true;
+
+                let packed = [&self.pos_x, &self.pos_y, &self.pos_z, &self.r_squared]
+                    .map(|c| c[p][lane].to_bits());
+                let expected = [
+                    spheres.s_x[i],
+                    spheres.s_y[i],
+                    spheres.s_z[i],
+                    spheres.r_squared[i],
+                ]
+                .map(f32::to_bits);
+                packed == expected
+            })
+            .count()
+    }
+
+    /// Intersects the ray with all eight lanes of packet `i` at the ray's
+    /// time, returning the closest `t` and its slot
     #[inline(always)]
     #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
     pub fn intersect(
         &self,
+        i: usize,
+        ray: &Ray,
+        tmin: f32,
+        tmax: f32,
+        r_o: Vec3x8,
+        r_d: Vec3x8,
+    ) -> Option<(f32, usize)> {
+        let (t, miss) = self.lanes(i, ray, tmin, tmax, r_o, r_d);
+
+        let closest_idx = i32x8::select(
+            i32x8::from_array([0, 1, 2, 3, 4, 5, 6, 7]),
+            i32x8::splat(-1),
+            miss,
+        );
+
+        if closest_idx.transmute_f32x8().movemask() == 0xFF {
+            return None;
+        }
+
+        let closest_t = f32x8::blend(t, f32x8::splat(tmax), miss);
+
+        let min = closest_t.horizontal_min();
+
+        let t = min[0];
+
+        let mask = min.cmp_eq(closest_t).as_f32x8().movemask();
+        let lane = mask.trailing_zeros() as usize;
+
+        Some((t, i * 8 + closest_idx[lane] as usize))
+    }
+
+    /// Whether any lane of packet `i` is hit between `tmin` and `tmax`,
+    /// without finding the closest
+    #[inline(always)]
+    pub fn occludes(
+        &self,
+        i: usize,
         ray: &Ray,
         tmin: f32,
         tmax: f32,
         r_o: Vec3x8,
         r_d: Vec3x8,
-    ) -> Option<HitRecord> {
+    ) -> bool {
+        let (_, miss) = self.lanes(i, ray, tmin, tmax, r_o, r_d);
+        miss.as_f32x8().movemask() != 0xFF
+    }
+
+    /// Distance per lane of packet `i` and the lanes that miss
+    #[inline(always)]
+    fn lanes(
+        &self,
+        i: usize,
+        ray: &Ray,
+        tmin: f32,
+        tmax: f32,
+        r_o: Vec3x8,
+        r_d: Vec3x8,
+    ) -> (f32x8, Bitmask) {
+        debug_assert!(i < self.packed_count);
+
         let a = f32x8::splat(ray.direction.length_squared());
         let a_inv = f32x8::splat(1.0 / a[0]);
 
         let tmin = f32x8::splat(tmin);
+        let closest_t = f32x8::splat(tmax);
+
+        let pos = Vec3x8 {
+            x: self.pos_x[i],
+            y: self.pos_y[i],
+            z: self.pos_z[i],
+        };
+        let motion = Vec3x8 {
+            x: self.motion_x[i],
+            y: self.motion_y[i],
+            z: self.motion_z[i],
+        };
+        let pos = motion.mul_add(f32x8::splat(ray.time), pos);
+
+        let oc = pos - r_o;
+        let h = r_d.dot(oc);
+        let c = oc.dot(oc) - self.r_squared[i];
+        let disc = h * h - a * c;
+
+        let sqrtd = disc.sqrt();
+
+        // The far root where the near one is behind `tmin`, so rays leaving
+        // a sphere from inside hit it like in the scalar test
+        let near = (h - sqrtd) * a_inv;
+        let far = (h + sqrtd) * a_inv;
+        let t = f32x8::blend(near, far, near.cmp_le(tmin));
+
+        let mut miss = disc.cmp_lt(f32x8::ZERO);
+        miss |= t.cmp_le(tmin);
+        // Strict like the scalar test, so a miss lane can't tie with a hit
+        miss |= t.cmp_nlt(closest_t);
+
+        (t, miss)
+    }
+
+    #[inline(always)]
+    pub fn hit_record(&self, slot: usize, ray: &Ray, t: f32) -> HitRecord {
+        let (s_pack, s_lane) = (slot / 8, slot % 8);
+        let s_pos = vec3(
+            self.pos_x[s_pack][s_lane],
+            self.pos_y[s_pack][s_lane],
+            self.pos_z[s_pack][s_lane],
+        );
+        let s_motion = vec3(
+            self.motion_x[s_pack][s_lane],
+            self.motion_y[s_pack][s_lane],
+            self.motion_z[s_pack][s_lane],
+        );
+        let s_pos = s_pos + ray.time * s_motion;
+
+        let pos = ray.origin + t * ray.direction;
+
+        HitRecord {
+            pos,
+            normal: (pos - s_pos) * self.r_inv[slot],
+            t,
+            material: self.material[slot],
+            primitive: Primitive::Sphere(self.index[slot]),
+            color: Vec3::ONE,
+        }
+    }
+}
+
+/// Scales the far slab distance to make box tests conservative despite
+/// rounding, otherwise rays grazing a flat box can miss primitives on its
+/// boundary (Ize, "Robust BVH Ray Traversal")
+const ROBUST_FAR_SCALE: f32 =
+    1.0 + 2.0 * (3.0 * f32::EPSILON * 0.5) / (1.0 - 3.0 * f32::EPSILON * 0.5);
+
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub const EMPTY: Aabb = Aabb {
+        min: Vec3::INFINITY,
+        max: Vec3::NEG_INFINITY,
+    };
+
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    #[inline(always)]
+    pub fn union(self, other: Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    #[inline(always)]
+    pub fn grow(self, p: Vec3) -> Aabb {
+        Aabb {
+            min: self.min.min(p),
+            max: self.max.max(p),
+        }
+    }
+
+    #[inline(always)]
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    #[inline(always)]
+    pub fn surface_area(&self) -> f32 {
+        let d = (self.max - self.min).max(Vec3::ZERO);
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    /// Slab test, returns the entry distance clamped to `tmin`
+    #[cfg(not(feature = "simd"))]
+    #[inline(always)]
+    pub fn intersect(&self, origin: Vec3, inv_dir: Vec3, tmin: f32, tmax: f32) -> Option<f32> {
+        let t0 = (self.min - origin) * inv_dir;
+        let t1 = (self.max - origin) * inv_dir;
+
+        let near = t0.min(t1).max_element().max(tmin);
+        let far = (t0.max(t1).min_element() * ROBUST_FAR_SCALE).min(tmax);
+
+        (near <= far).then_some(near)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BvhNode {
+    pub bounds: Aabb,
+    /// Left child for interior nodes (the right child follows it), start in
+    /// [`Bvh::indices`] for leaves
+    pub first: u32,
+    /// Number of primitives in a leaf, zero for interior nodes
+    pub count: u32,
+}
+
+impl BvhNode {
+    #[inline(always)]
+    pub fn is_leaf(&self) -> bool {
+        self.count > 0
+    }
+}
+
+/// Binary bounding volume hierarchy built with the binned surface area
+/// heuristic. Leaves hold at most [`Bvh::MAX_LEAF_SIZE`] primitives and, in
+/// SIMD builds, start on a multiple of eight in `indices` so every leaf maps to
+/// exactly one packet.
+#[derive(Clone, Default)]
+pub struct Bvh {
+    pub nodes: Vec<BvhNode>,
+    pub indices: Vec<u32>,
+    depth: usize,
+}
+
+impl Bvh {
+    /// Marks unused slots in `indices` after a leaf
+    pub const PADDING: u32 = u32::MAX;
+
+    pub const MAX_LEAF_SIZE: usize = 8;
+
+    /// Nodes this deep are split at the median instead of by SAH, which
+    /// bounds the depth of degenerate inputs
+    const SAH_DEPTH: usize = 64;
+
+    /// Deepest a tree gets: median splits halve the primitives, whose count
+    /// fits in 32 bits
+    pub const MAX_DEPTH: usize = Self::SAH_DEPTH + 32;
+
+    #[cfg(feature = "simd")]
+    const LEAF_ALIGN: usize = 8;
+    #[cfg(not(feature = "simd"))]
+    const LEAF_ALIGN: usize = 1;
+
+    const BINS: usize = 16;
+    const TRAVERSAL_COST: f32 = 1.0;
+    const INTERSECTION_COST: f32 = 1.0;
 
-        let mut closest_t = f32x8::splat(tmax);
-        let mut closest_idx = i32x8::splat(-1);
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub fn build(bounds: &[Aabb]) -> Bvh {
+        if bounds.is_empty() {
+            return Bvh::default();
+        }
+
+        let centroids = bounds.iter().map(Aabb::centroid).collect::<Vec<_>>();
+        let mut order = (0..bounds.len() as u32).collect::<Vec<_>>();
+
+        let mut nodes = vec![BvhNode {
+            bounds: Aabb::EMPTY,
+            first: 0,
+            count: bounds.len() as u32,
+        }];
+        let mut depth = 0;
+
+        let mut stack = vec![(0, 1)];
+        while let Some((node_idx, node_depth)) = stack.pop() {
+            depth = depth.max(node_depth);
+
+            let first = nodes[node_idx].first as usize;
+            let count = nodes[node_idx].count as usize;
+            let prims = &mut order[first..first + count];
+
+            let node_bounds = prims
+                .iter()
+                .fold(Aabb::EMPTY, |b, &i| b.union(bounds[i as usize]));
+            nodes[node_idx].bounds = node_bounds;
+
+            if count == 1 {
+                continue;
+            }
 
-        let mut s_idx = i32x8::from_array([0, 1, 2, 3, 4, 5, 6, 7]);
-        let stride = i32x8::splat(8);
+            let left_count = match Self::find_split(prims, bounds, &centroids) {
+                _ if node_depth >= Self::SAH_DEPTH => {
+                    if count <= Self::MAX_LEAF_SIZE {
+                        continue;
+                    }
+                    Self::median_split(prims, &centroids)
+                }
+                Some((cost, axis, split)) => {
+                    let leaf_cost = Self::INTERSECTION_COST * count as f32;
+                    if count <= Self::MAX_LEAF_SIZE && leaf_cost <= cost {
+                        continue;
+                    }
 
-        for i in 0..self.packed_count {
-            let pos = Vec3x8 {
-                x: self.pos_x[i],
-                y: self.pos_y[i],
-                z: self.pos_z[i],
+                    partition(prims, |&i| centroids[i as usize][axis] < split)
+                }
+                None if count <= Self::MAX_LEAF_SIZE => continue,
+                // All centroids coincide, split the range in half
+                None => count / 2,
             };
 
-            let oc = pos - r_o;
-            let h = r_d.dot(oc);
-            let c = oc.dot(oc) - self.r_squared[i];
-            let disc = h * h - a * c;
+            let left_count = if left_count == 0 || left_count == count {
+                count / 2
+            } else {
+                left_count
+            };
 
-            let sqrtd = disc.sqrt();
+            let left = nodes.len();
+            nodes.push(BvhNode {
+                bounds: Aabb::EMPTY,
+                first: first as u32,
+                count: left_count as u32,
+            });
+            nodes.push(BvhNode {
+                bounds: Aabb::EMPTY,
+                first: (first + left_count) as u32,
+                count: (count - left_count) as u32,
+            });
+
+            nodes[node_idx].first = left as u32;
+            nodes[node_idx].count = 0;
+
+            stack.push((left + 1, node_depth + 1));
+            stack.push((left, node_depth + 1));
+        }
 
-            let t = f32x8::min((h - sqrtd) * a_inv, (h + sqrtd) * a_inv);
+        let mut indices = Vec::with_capacity(order.len());
+        for node in nodes.iter_mut().filter(|n| n.is_leaf()) {
+            let first = node.first as usize;
+            node.first = indices.len() as u32;
 
-            let mut miss = disc.cmp_lt(f32x8::ZERO);
-            miss |= t.cmp_lt(tmin);
-            miss |= t.cmp_gt(closest_t);
+            indices.extend_from_slice(&order[first..first + node.count as usize]);
+            indices.resize(
+                indices.len().next_multiple_of(Self::LEAF_ALIGN),
+                Self::PADDING,
+            );
+        }
 
-            closest_t = f32x8::blend(t, closest_t, miss);
-            closest_idx = i32x8::select(s_idx, closest_idx, miss);
+        debug_assert!(depth <= Self::MAX_DEPTH);
 
-            s_idx += stride;
+        Bvh {
+            nodes,
+            indices,
+            depth,
         }
+    }
+
+    /// Moves the half of `prims` with the smaller centroids along the axis
+    /// they spread the most in to the front, returning its size
+    fn median_split(prims: &mut [u32], centroids: &[Vec3]) -> usize {
+        let centroid_bounds = prims
+            .iter()
+            .fold(Aabb::EMPTY, |b, &i| b.grow(centroids[i as usize]));
+        let axis = (centroid_bounds.max - centroid_bounds.min).max_position();
+
+        let half = prims.len() / 2;
+        prims.select_nth_unstable_by(half, |&a, &b| {
+            centroids[a as usize][axis].total_cmp(&centroids[b as usize][axis])
+        });
+        half
+    }
 
-        let mask = closest_idx.transmute_f32x8().movemask();
+    /// Returns the cheapest binned SAH split as (cost, axis, position), with
+    /// the cost relative to the surface area of `prims`
+    fn find_split(prims: &[u32], bounds: &[Aabb], centroids: &[Vec3]) -> Option<(f32, usize, f32)> {
+        let centroid_bounds = prims
+            .iter()
+            .fold(Aabb::EMPTY, |b, &i| b.grow(centroids[i as usize]));
+        let parent_area = prims
+            .iter()
+            .fold(Aabb::EMPTY, |b, &i| b.union(bounds[i as usize]))
+            .surface_area();
+
+        let mut best = None;
+        let mut best_cost = f32::INFINITY;
+
+        for axis in 0..3 {
+            let min = centroid_bounds.min[axis];
+            let extent = centroid_bounds.max[axis] - min;
+            if extent <= f32::EPSILON * min.abs().max(1.0) {
+                continue;
+            }
 
-        if mask == 0xFF {
-            None
+            let scale = Self::BINS as f32 / extent;
+            let bin_of = |c: Vec3| (((c[axis] - min) * scale) as usize).min(Self::BINS - 1);
+
+            let mut bin_bounds = [Aabb::EMPTY; Self::BINS];
+            let mut bin_counts = [0usize; Self::BINS];
+            for &i in prims {
+                let bin = bin_of(centroids[i as usize]);
+                bin_bounds[bin] = bin_bounds[bin].union(bounds[i as usize]);
+                bin_counts[bin] += 1;
+            }
+
+            let mut right_areas = [0.0; Self::BINS];
+            let mut right_counts = [0usize; Self::BINS];
+            let mut acc_bounds = Aabb::EMPTY;
+            let mut acc_count = 0;
+            for bin in (1..Self::BINS).rev() {
+                acc_bounds = acc_bounds.union(bin_bounds[bin]);
+                acc_count += bin_counts[bin];
+                right_areas[bin] = acc_bounds.surface_area();
+                right_counts[bin] = acc_count;
+            }
+
+            let mut acc_bounds = Aabb::EMPTY;
+            let mut acc_count = 0;
+            for bin in 0..Self::BINS - 1 {
+                acc_bounds = acc_bounds.union(bin_bounds[bin]);
+                acc_count += bin_counts[bin];
+
+                if acc_count == 0 || right_counts[bin + 1] == 0 {
+                    continue;
+                }
+
+                let cost = acc_count as f32 * acc_bounds.surface_area()
+                    + right_counts[bin + 1] as f32 * right_areas[bin + 1];
+                if cost < best_cost {
+                    best_cost = cost;
+                    best = Some((axis, min + (bin + 1) as f32 / scale));
+                }
+            }
+        }
+
+        let area = parent_area.max(f32::MIN_POSITIVE);
+        best.map(|(axis, split)| {
+            let cost = Self::TRAVERSAL_COST + Self::INTERSECTION_COST * best_cost / area;
+            (cost, axis, split)
+        })
+    }
+
+    /// Visits the leaves hit by the ray front to back. `intersect_leaf` gets
+    /// the leaf range in `indices` and the current `tmax` and returns the new
+    /// `tmax`, so nodes behind the closest hit are skipped. Returning
+    /// `f32::NEG_INFINITY` skips all remaining nodes.
+    #[cfg(not(feature = "simd"))]
+    #[inline(always)]
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub fn traverse(
+        &self,
+        ray: &Ray,
+        tmin: f32,
+        tmax: f32,
+        mut intersect_leaf: impl FnMut(usize, usize, f32) -> f32,
+    ) {
+        let Some(root) = self.nodes.first() else {
+            return;
+        };
+
+        let inv_dir = ray.direction.recip();
+        let mut tmax = tmax;
+
+        if root
+            .bounds
+            .intersect(ray.origin, inv_dir, tmin, tmax)
+            .is_none()
+        {
+            return;
+        }
+
+        // Every level below the root pushes at most one node
+        let mut stack = [(0u32, 0.0f32); Bvh::MAX_DEPTH];
+        let mut sp = 0;
+        let mut node = root;
+
+        loop {
+            if node.is_leaf() {
+                tmax = intersect_leaf(node.first as usize, node.count as usize, tmax);
+            } else {
+                let left = node.first as usize;
+                let t_left = self.nodes[left]
+                    .bounds
+                    .intersect(ray.origin, inv_dir, tmin, tmax);
+                let t_right = self.nodes[left + 1]
+                    .bounds
+                    .intersect(ray.origin, inv_dir, tmin, tmax);
+
+                match (t_left, t_right) {
+                    (Some(l), Some(r)) => {
+                        let (near, far, t_far) = if l <= r {
+                            (left, left + 1, r)
+                        } else {
+                            (left + 1, left, l)
+                        };
+                        stack[sp] = (far as u32, t_far);
+                        sp += 1;
+                        node = &self.nodes[near];
+                        continue;
+                    }
+                    (Some(_), None) => {
+                        node = &self.nodes[left];
+                        continue;
+                    }
+                    (None, Some(_)) => {
+                        node = &self.nodes[left + 1];
+                        continue;
+                    }
+                    (None, None) => {}
+                }
+            }
+
+            loop {
+                if sp == 0 {
+                    return;
+                }
+                sp -= 1;
+
+                let (next, t_next) = stack[sp];
+                if t_next <= tmax {
+                    node = &self.nodes[next as usize];
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Whether `hit_leaf` finds a hit in any leaf the ray reaches, stopping
+    /// at the first. It gets the leaf range in `indices` and `tmax`.
+    #[cfg(not(feature = "simd"))]
+    #[inline(always)]
+    pub fn any_leaf(
+        &self,
+        ray: &Ray,
+        tmin: f32,
+        tmax: f32,
+        mut hit_leaf: impl FnMut(usize, usize, f32) -> bool,
+    ) -> bool {
+        let mut hit = false;
+        self.traverse(ray, tmin, tmax, |first, count, tmax| {
+            hit = hit_leaf(first, count, tmax);
+            if hit { f32::NEG_INFINITY } else { tmax }
+        });
+        hit
+    }
+
+    /// Primitives referenced by the leaves, padding aside
+    pub fn primitive_count(&self) -> usize {
+        self.indices.iter().filter(|&&i| i != Self::PADDING).count()
+    }
+
+    pub fn metrics(&self, primitives: usize, build_time: Duration) -> BvhMetrics {
+        BvhMetrics {
+            build_time,
+            primitives,
+            nodes: self.nodes.len(),
+            leaves: self.nodes.iter().filter(|n| n.is_leaf()).count(),
+            depth: self.depth,
+            wide_nodes: 0,
+        }
+    }
+}
+
+/// Eight-wide BVH node, child `i` occupies lane `i` of every bounds vector
+#[cfg(feature = "simd")]
+#[derive(Clone, Copy)]
+pub struct Bvh8Node {
+    min_x: f32x8,
+    min_y: f32x8,
+    min_z: f32x8,
+    max_x: f32x8,
+    max_y: f32x8,
+    max_z: f32x8,
+    children: [u32; 8],
+}
+
+/// BVH with eight children per node, collapsed from a binary [`Bvh`]. Leaf
+/// children refer to the SIMD packet holding the leaf's primitives.
+#[cfg(feature = "simd")]
+#[derive(Clone, Default)]
+pub struct Bvh8 {
+    nodes: Vec<Bvh8Node>,
+}
+
+#[cfg(feature = "simd")]
+impl Bvh8 {
+    const LEAF: u32 = 1 << 31;
+    const EMPTY: u32 = u32::MAX;
+    /// Clears the low mantissa bits of an entry distance for the lane index
+    const KEY_MASK: f32x8 = f32x8 {
+        lanes: [f32::from_bits(!7); 8],
+    };
+    const LANES: f32x8 = f32x8 {
+        lanes: [
+            f32::from_bits(0),
+            f32::from_bits(1),
+            f32::from_bits(2),
+            f32::from_bits(3),
+            f32::from_bits(4),
+            f32::from_bits(5),
+            f32::from_bits(6),
+            f32::from_bits(7),
+        ],
+    };
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub fn from_bvh(bvh: &Bvh) -> Bvh8 {
+        let mut bvh8 = Bvh8::default();
+        if !bvh.nodes.is_empty() {
+            bvh8.collapse(bvh, 0);
+        }
+        bvh8
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Creates a node for the binary subtree at `node`, pulling in the
+    /// largest interior descendants until all eight lanes are used
+    fn collapse(&mut self, bvh: &Bvh, node: usize) -> u32 {
+        let mut children = if bvh.nodes[node].is_leaf() {
+            vec![node]
         } else {
-            let v1 = f32x8::min(closest_t, closest_t.permute::<0b10_11_00_01>());
-            let v2 = f32x8::min(v1, v1.permute::<0b01_00_11_10>());
-            let v3 = f32x8::min(v2, f32x8::permute_2f128::<0b0000_0001>(v2, v2));
+            let left = bvh.nodes[node].first as usize;
+            vec![left, left + 1]
+        };
+
+        while children.len() < 8 {
+            let largest = children
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| !bvh.nodes[**c].is_leaf())
+                .max_by(|(_, a), (_, b)| {
+                    let a = bvh.nodes[**a].bounds.surface_area();
+                    let b = bvh.nodes[**b].bounds.surface_area();
+                    a.total_cmp(&b)
+                })
+                .map(|(i, _)| i);
+
+            let Some(i) = largest else {
+                break;
+            };
 
-            let t = v3[0];
+            let left = bvh.nodes[children[i]].first as usize;
+            children[i] = left;
+            children.push(left + 1);
+        }
 
-            let mask = v3.cmp_eq(closest_t).as_f32x8().movemask();
-            let i = mask.trailing_zeros() as usize;
+        let idx = self.nodes.len();
+        self.nodes.push(Bvh8Node {
+            min_x: f32x8::INFINITY,
+            min_y: f32x8::INFINITY,
+            min_z: f32x8::INFINITY,
+            max_x: f32x8::splat(f32::NEG_INFINITY),
+            max_y: f32x8::splat(f32::NEG_INFINITY),
+            max_z: f32x8::splat(f32::NEG_INFINITY),
+            children: [Self::EMPTY; 8],
+        });
+
+        let mut min = [[f32::INFINITY; 8]; 3];
+        let mut max = [[f32::NEG_INFINITY; 8]; 3];
+        let mut refs = [Self::EMPTY; 8];
+
+        for (lane, &child) in children.iter().enumerate() {
+            let child_node = bvh.nodes[child];
+            for axis in 0..3 {
+                min[axis][lane] = child_node.bounds.min[axis];
+                max[axis][lane] = child_node.bounds.max[axis];
+            }
 
-            let s_idx = closest_idx[i] as usize;
+            refs[lane] = if child_node.is_leaf() {
+                debug_assert!(child_node.count as usize <= Bvh::MAX_LEAF_SIZE);
+                Self::LEAF | (child_node.first / 8)
+            } else {
+                self.collapse(bvh, child)
+            };
+        }
+
+        let node = &mut self.nodes[idx];
+        node.min_x = f32x8::from_array(min[0]);
+        node.min_y = f32x8::from_array(min[1]);
+        node.min_z = f32x8::from_array(min[2]);
+        node.max_x = f32x8::from_array(max[0]);
+        node.max_y = f32x8::from_array(max[1]);
+        node.max_z = f32x8::from_array(max[2]);
+        node.children = refs;
+
+        idx as u32
+    }
+
+    /// Visits the leaf packets hit by the ray front to back. `intersect_leaf`
+    /// gets the packet index and the current `tmax` and returns the new
+    /// `tmax`, so children behind the closest hit are skipped. Returning
+    /// `f32::NEG_INFINITY` skips all remaining children.
+    #[inline(always)]
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub fn traverse(
+        &self,
+        ray: &Ray,
+        tmin: f32,
+        tmax: f32,
+        mut intersect_leaf: impl FnMut(usize, f32) -> f32,
+    ) {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let inv_dir = ray.direction.recip();
+
+        let inv_x = f32x8::splat(inv_dir.x);
+        let inv_y = f32x8::splat(inv_dir.y);
+        let inv_z = f32x8::splat(inv_dir.z);
+        let o_x = f32x8::splat(ray.origin.x);
+        let o_y = f32x8::splat(ray.origin.y);
+        let o_z = f32x8::splat(ray.origin.z);
+        let far_scale = f32x8::splat(ROBUST_FAR_SCALE);
+
+        let tmin = f32x8::splat(tmin);
+        let mut tmax = tmax;
 
-            let s_pack = s_idx / 8;
-            let s_lane = s_idx % 8;
-            let s_pos = vec3(
-                self.pos_x[s_pack][s_lane],
-                self.pos_y[s_pack][s_lane],
-                self.pos_z[s_pack][s_lane],
+        let mut stack = TraversalStack::default();
+        stack.push((0, 0.0));
+
+        while let Some((child, t)) = stack.pop() {
+            if t > tmax {
+                continue;
+            }
+
+            if child & Self::LEAF != 0 {
+                tmax = intersect_leaf((child & !Self::LEAF) as usize, tmax);
+                continue;
+            }
+
+            let node = &self.nodes[child as usize];
+
+            // Selecting the near and far planes per axis from the ray
+            // direction makes empty lanes (min > max) always miss
+            let (near_x, far_x) = if inv_dir.x >= 0.0 {
+                (node.min_x, node.max_x)
+            } else {
+                (node.max_x, node.min_x)
+            };
+            let (near_y, far_y) = if inv_dir.y >= 0.0 {
+                (node.min_y, node.max_y)
+            } else {
+                (node.max_y, node.min_y)
+            };
+            let (near_z, far_z) = if inv_dir.z >= 0.0 {
+                (node.min_z, node.max_z)
+            } else {
+                (node.max_z, node.min_z)
+            };
+
+            let t_near = f32x8::max(
+                f32x8::max((near_x - o_x) * inv_x, (near_y - o_y) * inv_y),
+                f32x8::max((near_z - o_z) * inv_z, tmin),
+            );
+            let t_far = f32x8::min(
+                f32x8::min((far_x - o_x) * inv_x, (far_y - o_y) * inv_y),
+                (far_z - o_z) * inv_z,
             );
+            let t_far = f32x8::min(t_far * far_scale, f32x8::splat(tmax));
+
+            let hit = t_near.cmp_le(t_far);
+            let count = hit.as_f32x8().movemask().count_ones() as usize;
+
+            // Sort the lanes by entry distance with the missed ones last,
+            // keeping the lane index in the low mantissa bits of the key,
+            // and push the hit children farthest first so the nearest is
+            // popped next
+            let keys = f32x8::blend(f32x8::splat(f32::MAX), t_near, hit);
+            let sorted = ((keys & Self::KEY_MASK) | Self::LANES).sort();
+            for i in (0..count).rev() {
+                let lane = (sorted[i].to_bits() & 7) as usize;
+                stack.push((node.children[lane], t_near[lane]));
+            }
+        }
+    }
 
-            let pos = ray.origin + t * ray.direction;
+    /// Whether `hit_leaf` finds a hit in any leaf packet the ray reaches,
+    /// stopping at the first. It gets the packet index and `tmax`.
+    #[inline(always)]
+    pub fn any_leaf(
+        &self,
+        ray: &Ray,
+        tmin: f32,
+        tmax: f32,
+        mut hit_leaf: impl FnMut(usize, f32) -> bool,
+    ) -> bool {
+        let mut hit = false;
+        self.traverse(ray, tmin, tmax, |packet, tmax| {
+            hit = hit_leaf(packet, tmax);
+            if hit { f32::NEG_INFINITY } else { tmax }
+        });
+        hit
+    }
+}
 
-            let normal = (pos - s_pos) * self.r_inv[s_idx];
+/// [`Bvh8`] traversal stack of children and their entry distances. Each node
+/// can push seven more entries than it pops, so deep trees spill past the
+/// inline entries into a `Vec`.
+#[cfg(feature = "simd")]
+struct TraversalStack {
+    inline: [(u32, f32); Self::INLINE],
+    spilled: Vec<(u32, f32)>,
+    len: usize,
+}
 
-            let material = self.material[s_idx];
+#[cfg(feature = "simd")]
+impl TraversalStack {
+    const INLINE: usize = 128;
 
-            Some(HitRecord {
-                pos,
-                normal,
-                t,
-                material,
-            })
+    #[inline(always)]
+    fn push(&mut self, entry: (u32, f32)) {
+        if self.len < Self::INLINE {
+            self.inline[self.len] = entry;
+        } else {
+            self.spilled.push(entry);
+        }
+        self.len += 1;
+    }
+
+    #[inline(always)]
+    fn pop(&mut self) -> Option<(u32, f32)> {
+        self.len = self.len.checked_sub(1)?;
+        if self.len < Self::INLINE {
+            Some(self.inline[self.len])
+        } else {
+            self.spilled.pop()
+        }
+    }
+}
+
+#[cfg(feature = "simd")]
+impl Default for TraversalStack {
+    fn default() -> Self {
+        TraversalStack {
+            inline: [(0, 0.0); Self::INLINE],
+            spilled: Vec::new(),
+            len: 0,
+        }
+    }
+}
+
+/// Moves the elements matching `pred` to the front, returning their count
+fn partition<T>(slice: &mut [T], pred: impl Fn(&T) -> bool) -> usize {
+    let mut left = 0;
+    for i in 0..slice.len() {
+        if pred(&slice[i]) {
+            slice.swap(left, i);
+            left += 1;
         }
     }
+    left
 }
@@ -0,0 +1,328 @@
+//! Two-level instancing: a [`SharedMesh`] holds triangles and their BVH once,
+//! any number of [`Instance`]s place it in the scene with a transform
+
+use std::{path::PathBuf, time::Instant};
+
+use glam::{Affine3A, BVec3, Mat3A, Vec3};
+
+#[cfg(feature = "simd")]
+use crate::geometry::{Bvh8, TrianglesSIMD};
+use crate::{
+    HitRecord, Primitive, Ray,
+    geometry::{Aabb, Bvh, Triangles},
+    metrics::BvhMetrics,
+    scene::ValidationError,
+};
+
+#[cfg(feature = "simd")]
+use crate::simd::*;
+
+/// Triangles in object space with their own BVH, built once when added
+#[derive(Clone)]
+pub struct SharedMesh {
+    pub name: String,
+    /// File the triangles were loaded from, as written in the scene file
+    pub source: Option<PathBuf>,
+    triangles: Triangles,
+    #[cfg(not(feature = "simd"))]
+    bvh: Bvh,
+    /// Object space bounds of all triangles over their motion
+    bounds: Aabb,
+
+    #[cfg(feature = "simd")]
+    triangles_simd: TrianglesSIMD,
+    #[cfg(feature = "simd")]
+    bvh8: Bvh8,
+
+    pub metrics: BvhMetrics,
+}
+
+impl SharedMesh {
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub fn new(name: impl Into<String>, triangles: Triangles) -> SharedMesh {
+        let start = Instant::now();
+        let bounds = triangles.bounds();
+        let bvh = Bvh::build(&bounds);
+        #[cfg(feature = "simd")]
+        let bvh8 = Bvh8::from_bvh(&bvh);
+        let metrics = BvhMetrics {
+            #[cfg(feature = "simd")]
+            wide_nodes: bvh8.len(),
+            ..bvh.metrics(triangles.len(), start.elapsed())
+        };
+
+        SharedMesh {
+            name: name.into(),
+            source: None,
+            bounds: bounds.into_iter().fold(Aabb::EMPTY, Aabb::union),
+            #[cfg(feature = "simd")]
+            triangles_simd: TrianglesSIMD::from_tris(&triangles, &bvh.indices),
+            #[cfg(feature = "simd")]
+            bvh8,
+            triangles,
+            #[cfg(not(feature = "simd"))]
+            bvh,
+            metrics,
+        }
+    }
+
+    pub fn triangles(&self) -> &Triangles {
+        &self.triangles
+    }
+
+    /// Checks the BVH or the SIMD packets hold every triangle, see
+    /// [`Scene::validate`](crate::scene::Scene::validate)
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        #[cfg(not(feature = "simd"))]
+        let found = self.bvh.primitive_count();
+        #[cfg(feature = "simd")]
+        let found = self.triangles_simd.triangle_count(&self.triangles);
+
+        ValidationError::check(
+            format_args!("mesh `{}`", self.name),
+            self.triangles.len(),
+            found,
+        )
+    }
+
+    /// Closest hit of a ray in object space, its `t` and which triangle
+    /// it hit for [`SharedMesh::hit_record`]
+    #[cfg(not(feature = "simd"))]
+    #[inline(always)]
+    pub fn intersect(&self, ray: &Ray, tmin: f32, tmax: f32) -> Option<(f32, MeshHit)> {
+        let mut closest = tmax;
+        let mut triangle = None;
+
+        self.bvh.traverse(ray, tmin, closest, |first, count, tmax| {
+            let mut tmax = tmax;
+            for &i in &self.bvh.indices[first..first + count] {
+                if let Some(t) = self.triangles.intersect(i as usize, ray, tmin, tmax) {
+                    tmax = t;
+                    triangle = Some(i as usize);
+                }
+            }
+            closest = tmax;
+            tmax
+        });
+
+        triangle.map(|i| (closest, MeshHit(i)))
+    }
+
+    /// Closest hit of a ray in object space, its `t` and which triangle
+    /// it hit for [`SharedMesh::hit_record`]
+    #[cfg(feature = "simd")]
+    #[inline(always)]
+    pub fn intersect(&self, ray: &Ray, tmin: f32, tmax: f32) -> Option<(f32, MeshHit)> {
+        let r_o = Vec3x8::from(ray.origin);
+        let r_d = Vec3x8::from(ray.direction);
+
+        let mut closest = tmax;
+        let mut triangle = None;
+
+        self.bvh8.traverse(ray, tmin, closest, |packet, tmax| {
+            if let Some((t, slot, det)) = self
+                .triangles_simd
+                .intersect(packet, tmin, tmax, r_o, r_d, ray.time)
+            {
+                triangle = Some(MeshHit(slot, det));
+                closest = t;
+            }
+            closest
+        });
+
+        triangle.map(|hit| (closest, hit))
+    }
+
+    /// Whether a ray in object space hits any triangle between `tmin` and
+    /// `tmax`
+    #[cfg(not(feature = "simd"))]
+    #[inline(always)]
+    pub fn occluded(&self, ray: &Ray, tmin: f32, tmax: f32) -> bool {
+        self.bvh.any_leaf(ray, tmin, tmax, |first, count, tmax| {
+            self.bvh.indices[first..first + count].iter().any(|&i| {
+                self.triangles
+                    .intersect(i as usize, ray, tmin, tmax)
+                    .is_some()
+            })
+        })
+    }
+
+    /// Whether a ray in object space hits any triangle between `tmin` and
+    /// `tmax`
+    #[cfg(feature = "simd")]
+    #[inline(always)]
+    pub fn occluded(&self, ray: &Ray, tmin: f32, tmax: f32) -> bool {
+        let r_o = Vec3x8::from(ray.origin);
+        let r_d = Vec3x8::from(ray.direction);
+
+        self.bvh8.any_leaf(ray, tmin, tmax, |packet, tmax| {
+            self.triangles_simd
+                .occludes(packet, tmin, tmax, r_o, r_d, ray.time)
+        })
+    }
+
+    /// Hit record in object space, the primitive is the triangle's index in
+    /// the mesh
+    #[inline(always)]
+    pub fn hit_record(&self, hit: MeshHit, ray: &Ray, t: f32) -> HitRecord {
+        #[cfg(not(feature = "simd"))]
+        {
+            self.triangles.hit_record(hit.0, ray, t)
+        }
+        #[cfg(feature = "simd")]
+        {
+            self.triangles_simd
+                .hit_record(hit.0, ray, t, hit.1, &self.triangles)
+        }
+    }
+}
+
+/// Whether `transform` keeps volumes from collapsing, so it has an inverse.
+/// Relative to the lengths of its axes, so any uniform scale passes.
+pub fn is_invertible(transform: Affine3A) -> bool {
+    let Mat3A {
+        x_axis,
+        y_axis,
+        z_axis,
+    } = transform.matrix3;
+    let volume = x_axis.length() * y_axis.length() * z_axis.length();
+    let det = transform.matrix3.determinant();
+    det.is_finite() && det.abs() > 1e-6 * volume
+}
+
+/// Triangle a ray hit in a [`SharedMesh`], its index
+#[cfg(not(feature = "simd"))]
+#[derive(Clone, Copy)]
+pub struct MeshHit(usize);
+
+/// Triangle a ray hit in a [`SharedMesh`], its packet slot and the
+/// determinant sign the normal needs
+#[cfg(feature = "simd")]
+#[derive(Clone, Copy)]
+pub struct MeshHit(usize, f32);
+
+/// A [`SharedMesh`] placed in the scene
+#[derive(Clone, Copy)]
+pub struct Instance {
+    /// Index of the shared mesh in the scene
+    pub mesh: u32,
+    /// Replaces the materials of the mesh's triangles
+    pub material: Option<u32>,
+    transform: Affine3A,
+    inverse: Affine3A,
+    /// Inverse transpose of the transform, for normals
+    normal_matrix: Mat3A,
+}
+
+impl Instance {
+    pub fn new(mesh: u32, transform: Affine3A, material: Option<u32>) -> Instance {
+        Instance {
+            mesh,
+            material,
+            transform,
+            inverse: transform.inverse(),
+            normal_matrix: transform.matrix3.inverse().transpose(),
+        }
+    }
+
+    /// Object to world space transform
+    pub fn transform(&self) -> Affine3A {
+        self.transform
+    }
+
+    /// World space bounds of `mesh` placed by this instance
+    pub fn bounds(&self, mesh: &SharedMesh) -> Aabb {
+        let Aabb { min, max } = mesh.bounds;
+        if min.cmpgt(max).any() {
+            // Nothing to hit, a point keeps the BVH build finite
+            let origin = self.transform.transform_point3(Vec3::ZERO);
+            return Aabb::new(origin, origin);
+        }
+
+        (0..8)
+            .map(|corner| {
+                Vec3::select(
+                    BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0),
+                    max,
+                    min,
+                )
+            })
+            .fold(Aabb::EMPTY, |b, p| {
+                b.grow(self.transform.transform_point3(p))
+            })
+    }
+
+    /// World space vertices of triangle `i` of `mesh` at time zero and at
+    /// time one if it moves
+    pub fn vertices(&self, mesh: &SharedMesh, i: usize) -> ([Vec3; 3], Option<[Vec3; 3]>) {
+        let place = |v: [Vec3; 3]| v.map(|p| self.transform.transform_point3(p));
+        (
+            place(mesh.triangles.vertices(i)),
+            mesh.triangles.vertices_end(i).map(place),
+        )
+    }
+
+    /// Material of triangle `i` of `mesh` as placed by this instance
+    pub fn material(&self, mesh: &SharedMesh, i: usize) -> u32 {
+        self.material.unwrap_or_else(|| mesh.triangles.material(i))
+    }
+
+    /// Ray in `mesh`'s object space. The direction isn't renormalized, so
+    /// `t` is the same in both spaces.
+    #[inline(always)]
+    fn local_ray(&self, ray: &Ray) -> Ray {
+        Ray::new(
+            self.inverse.transform_point3(ray.origin),
+            self.inverse.transform_vector3(ray.direction),
+            ray.time,
+        )
+    }
+
+    /// Closest hit of the ray on `mesh` placed by this instance
+    #[inline(always)]
+    pub fn intersect(
+        &self,
+        mesh: &SharedMesh,
+        ray: &Ray,
+        tmin: f32,
+        tmax: f32,
+    ) -> Option<(f32, MeshHit)> {
+        mesh.intersect(&self.local_ray(ray), tmin, tmax)
+    }
+
+    /// Whether the ray hits `mesh` placed by this instance between `tmin`
+    /// and `tmax`
+    #[inline(always)]
+    pub fn occluded(&self, mesh: &SharedMesh, ray: &Ray, tmin: f32, tmax: f32) -> bool {
+        mesh.occluded(&self.local_ray(ray), tmin, tmax)
+    }
+
+    /// World space hit record for a hit [`Instance::intersect`] found, this
+    /// is instance `index` of the scene
+    #[inline(always)]
+    pub fn hit_record(
+        &self,
+        index: u32,
+        mesh: &SharedMesh,
+        hit: MeshHit,
+        ray: &Ray,
+        t: f32,
+    ) -> HitRecord {
+        let mut hit = mesh.hit_record(hit, &self.local_ray(ray), t);
+        let Primitive::Triangle(triangle) = hit.primitive else {
+            unreachable!("shared meshes only hold triangles");
+        };
+
+        // The inverse transpose keeps the normal on the side facing the ray
+        hit.pos = ray.origin + t * ray.direction;
+        hit.normal = self.normal_matrix.mul_vec3(hit.normal).normalize();
+        hit.primitive = Primitive::Instance {
+            instance: index,
+            triangle,
+        };
+        if let Some(material) = self.material {
+            hit.material = material;
+        }
+        hit
+    }
+}
@@ -1,18 +1,36 @@
-use std::{thread, time::Instant};
-
-use camera::Camera;
+use std::{
+    num::NonZeroUsize,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use aov::Aovs;
+use camera::{Camera, CameraSettings};
 use crossbeam_channel::{Receiver, Sender, TryRecvError};
-use glam::{UVec2, Vec3, Vec4, uvec2, vec3};
-use metrics::RenderPassMetrics;
-use scene::Scene;
+use denoise::Denoiser;
+use film::{AovPixel, Pixel};
+use glam::{UVec2, Vec3, uvec2, vec3};
+use metrics::{RenderPassMetrics, SceneMetrics};
+use scene::{Scene, SceneEdit, SceneSummary};
 
 use crate::renderer::Renderer;
 
-mod camera;
-mod geometry;
-mod material;
+pub mod aov;
+pub mod camera;
+pub mod denoise;
+pub mod display;
+pub mod environment;
+mod film;
+pub mod geometry;
+pub mod instance;
+mod light;
+pub mod material;
+pub mod mesh;
 pub mod renderer;
-mod scene;
+mod rng;
+pub mod scene;
+pub mod scene_file;
 
 #[cfg(feature = "metrics")]
 pub mod metrics;
@@ -20,26 +38,165 @@ pub mod metrics;
 #[cfg(feature = "simd")]
 mod simd;
 
-#[derive(Clone, Copy, PartialEq)]
+/// Commands for the render thread, applied between passes. Any change to
+/// the camera or the scene restarts the accumulation with a few
+/// [`PREVIEW_PASSES`].
+#[derive(Clone)]
 pub enum RendererCmd {
     Stop,
-    Resize { width: u32, height: u32 },
+    Resize {
+        width: u32,
+        height: u32,
+    },
+    SetCamera(CameraSettings),
+    /// Changes the scene, invalid edits are skipped and reported in
+    /// [`RenderResult::scene`]
+    Edit(SceneEdit),
+    /// Replaces the scene and the camera with a built scene
+    SetScene(Box<Scene>),
+    /// Starts or stops filtering the image into [`RenderResult::denoised`],
+    /// the accumulation goes on unless it lacked the AOVs
+    SetDenoise(Option<Denoiser>),
+    /// Starts accumulating [`RenderResult::aovs`], restarting the
+    /// accumulation if they were off
+    EnableAovs,
+}
+
+/// Passes rendered at reduced resolution after the camera moved, so the
+/// image follows the camera while it keeps moving
+pub const PREVIEW_PASSES: u32 = 4;
+
+/// Factor the image size is divided by for [`PREVIEW_PASSES`]
+const PREVIEW_SCALE: u32 = 4;
+
+/// The render loop denoises again once this many times the last denoise
+/// took has passed, so filtering takes a fixed share of the render thread
+const DENOISE_INTERVAL: u32 = 4;
+
+#[derive(Clone, Copy)]
+pub struct RenderSettings {
+    /// Number of worker threads used per render pass
+    pub threads: usize,
+    /// Height of the horizontal tiles the image is split into
+    pub tile_rows: usize,
+    /// Seed for every random decision, a random seed is used when unset
+    pub seed: Option<u64>,
+    pub bounces: Bounces,
+    /// Stop sampling pixels once they converge and give their samples to
+    /// the noisier ones, every pixel gets one sample each pass when unset
+    pub adaptive: Option<Adaptive>,
+    /// Accumulate the first hit AOVs next to the image
+    pub aovs: bool,
+    /// Filter the result of [`RenderSystem::render_image`], which then
+    /// accumulates the AOVs the denoiser needs even without
+    /// [`RenderSettings::aovs`]
+    pub denoise: Option<Denoiser>,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            threads: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            tile_rows: 8,
+            seed: None,
+            bounces: Bounces::default(),
+            adaptive: None,
+            aovs: false,
+            denoise: None,
+        }
+    }
+}
+
+/// A pixel has converged once the standard error of its mean luminance,
+/// relative to that mean, drops below `threshold`. Each pass the samples
+/// converged pixels skip are handed to the others in proportion to their
+/// error, so a pass takes about one sample per pixel in total.
+#[derive(Clone, Copy)]
+pub struct Adaptive {
+    pub threshold: f32,
+    /// Samples taken before the error estimate is trusted
+    pub min_samples: u32,
+}
+
+impl Default for Adaptive {
+    fn default() -> Self {
+        Self {
+            threshold: 0.01,
+            min_samples: 32,
+        }
+    }
+}
+
+/// When [`RenderSystem::render_image`] stops adding passes. Rendering also
+/// stops once every pixel has converged under adaptive sampling.
+#[derive(Clone, Copy)]
+pub struct StopCondition {
+    /// Number of passes, the samples per pixel without adaptive sampling.
+    /// With it converged pixels hand their samples to noisier ones, which
+    /// keeps about the same total.
+    pub samples: u32,
+    /// Wall clock budget
+    pub time: Option<Duration>,
+}
+
+/// Path length limits, each kind of bounce is counted separately
+#[derive(Clone, Copy)]
+pub struct Bounces {
+    pub diffuse: u32,
+    pub specular: u32,
+    pub transmission: u32,
+    /// Bounces before Russian roulette starts terminating paths
+    pub roulette_depth: u32,
+}
+
+impl Bounces {
+    /// Longest possible path, in bounces
+    pub fn max_depth(&self) -> usize {
+        (self.diffuse + self.specular + self.transmission) as usize
+    }
+}
+
+impl Default for Bounces {
+    fn default() -> Self {
+        Self {
+            diffuse: 8,
+            specular: 8,
+            transmission: 8,
+            roulette_depth: 3,
+        }
+    }
 }
 
 #[derive(Clone, Default)]
 pub struct RenderResult {
     pub image_data: Vec<[f32; 4]>,
+    /// Empty unless [`RenderSettings::aovs`] is set or
+    /// [`RendererCmd::EnableAovs`] was sent
+    pub aovs: Aovs,
+    /// `image_data` filtered a few passes ago, empty unless enabled with
+    /// [`RendererCmd::SetDenoise`] and done since the accumulation restarted
+    pub denoised: Vec<[f32; 4]>,
     pub image_size: UVec2,
     pub render_pass_metrics: RenderPassMetrics,
+    pub scene_metrics: SceneMetrics,
+    /// The scene being rendered, replaced whenever commands change it
+    pub scene: Arc<SceneSummary>,
 }
 
 pub struct RenderSystem<R: Renderer> {
     camera: Camera,
     scene: Scene,
+    /// Published with every pass
+    summary: Arc<SceneSummary>,
     size: UVec2,
 
     renderer: R,
+    seed: u64,
     samples: usize,
+    /// Preview passes left before rendering at full resolution again
+    preview_passes: u32,
+    aovs: bool,
+    denoise: Option<Denoiser>,
 
     cmd_rx: Receiver<RendererCmd>,
     input: triple_buffer::Input<RenderResult>,
@@ -49,6 +206,8 @@ impl<R: Renderer> RenderSystem<R> {
     pub fn new(
         width: u32,
         height: u32,
+        scene: Scene,
+        settings: RenderSettings,
     ) -> (
         Self,
         Sender<RendererCmd>,
@@ -56,10 +215,7 @@ impl<R: Renderer> RenderSystem<R> {
     ) {
         let size = uvec2(width, height);
 
-        let camera = Camera::new(vec3(0.0, 15.0, -2.5), vec3(0.0, 15.0, -5.5), 90.0, size);
-
-        let mut scene = Scene::default();
-        scene::test_scene(&mut scene);
+        let camera = Camera::new(&scene.camera, size);
 
         let (input, output) = triple_buffer::triple_buffer(&RenderResult::default());
 
@@ -68,11 +224,16 @@ impl<R: Renderer> RenderSystem<R> {
         (
             Self {
                 camera,
+                summary: Arc::new(scene.summary()),
                 scene,
                 size,
 
-                renderer: R::new(),
+                renderer: R::new(&settings),
+                seed: settings.seed.unwrap_or_else(|| fastrand::u64(..)),
                 samples: 0,
+                preview_passes: 0,
+                aovs: settings.aovs || settings.denoise.is_some(),
+                denoise: settings.denoise,
 
                 cmd_rx,
                 input,
@@ -82,17 +243,51 @@ impl<R: Renderer> RenderSystem<R> {
         )
     }
 
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
     pub fn start_thread(self) {
         thread::spawn(|| self.run_render_loop());
     }
 
-    fn receive_commands(&mut self) -> bool {
+    /// Applies pending commands, waiting for one first if `block` is set.
+    /// Returns false once the loop should stop.
+    fn receive_commands(&mut self, block: bool) -> bool {
         let mut last_resize = None;
+        let mut last_camera = None;
+        let mut edited = false;
+        let mut errors = Vec::new();
+        let mut block = block;
         loop {
-            match self.cmd_rx.try_recv() {
+            let cmd = if block {
+                block = false;
+                self.cmd_rx.recv().map_err(|_| TryRecvError::Disconnected)
+            } else {
+                self.cmd_rx.try_recv()
+            };
+
+            match cmd {
                 Ok(RendererCmd::Resize { width, height }) => {
                     last_resize = Some(uvec2(width, height));
                 }
+                Ok(RendererCmd::SetCamera(camera)) => last_camera = Some(camera),
+                Ok(RendererCmd::Edit(edit)) => match self.scene.edit(edit) {
+                    Ok(()) => edited = true,
+                    Err(err) => errors.push(format!("edit failed: {err}")),
+                },
+                Ok(RendererCmd::SetScene(scene)) => {
+                    self.scene = *scene;
+                    last_camera = Some(self.scene.camera);
+                    edited = true;
+                }
+                Ok(RendererCmd::SetDenoise(denoise)) => {
+                    if denoise.is_some() {
+                        self.enable_aovs();
+                    }
+                    self.denoise = denoise;
+                }
+                Ok(RendererCmd::EnableAovs) => self.enable_aovs(),
                 Ok(RendererCmd::Stop) => return false,
                 Err(TryRecvError::Disconnected) => return false,
                 Err(TryRecvError::Empty) => break,
@@ -105,89 +300,280 @@ impl<R: Renderer> RenderSystem<R> {
             self.samples = 0;
         }
 
+        if let Some(camera) = last_camera {
+            self.scene.camera = camera;
+            self.camera = Camera::new(&camera, self.size);
+            self.samples = 0;
+            self.preview_passes = PREVIEW_PASSES;
+        }
+
+        // Batched so a stream of edits only rebuilds once
+        if edited {
+            if let Err(err) = self.scene.rebuild() {
+                errors.push(format!("rebuild failed: {err}"));
+            }
+            self.samples = 0;
+            self.preview_passes = PREVIEW_PASSES;
+        }
+
+        if edited || !errors.is_empty() {
+            self.summary = Arc::new(SceneSummary {
+                errors,
+                ..self.scene.summary()
+            });
+        }
+
         true
     }
 
+    /// Accumulates the AOVs from the next pass on, which starts over if
+    /// they were off
+    fn enable_aovs(&mut self) {
+        if !self.aovs {
+            self.aovs = true;
+            self.samples = 0;
+        }
+    }
+
     fn run_render_loop(mut self) {
         println!("TID: {}", unsafe { libc::syscall(libc::SYS_gettid) });
 
-        let mut rng = fastrand::Rng::new();
-        let mut acc = vec![Vec4::ZERO; (self.size.x * self.size.y) as usize];
+        let mut acc = vec![Pixel::default(); (self.size.x * self.size.y) as usize];
+        let mut aov_acc = Vec::new();
+        let mut preview_acc = Vec::new();
+        // Latest filtered image and when it was done, republished with every
+        // pass in between
+        let mut denoised = Vec::new();
+        let mut next_denoise = Instant::now();
+
+        // Set once a pass found every pixel converged, rendering then waits
+        // for the next command instead of spinning
+        let mut converged = false;
 
         loop {
-            if !self.receive_commands() {
+            if !self.receive_commands(converged) {
                 return;
             }
 
             let RenderResult {
                 image_data,
+                aovs,
+                denoised: published,
                 image_size,
                 render_pass_metrics,
+                scene_metrics,
+                scene: summary,
             } = self.input.input_buffer_mut();
 
             let len = (self.size.x * self.size.y) as usize;
-            if image_data.len() != len {
+            *image_size = self.size;
+
+            if self.preview_passes > 0 {
+                let preview_size = (self.size + PREVIEW_SCALE - 1) / PREVIEW_SCALE;
+                let preview_len = (preview_size.x * preview_size.y) as usize;
+                let preview_sample = PREVIEW_PASSES - self.preview_passes;
+                if preview_sample == 0 || preview_acc.len() != preview_len {
+                    preview_acc.clear();
+                    preview_acc.resize(preview_len, Pixel::default());
+                }
+
+                let mut camera = self.camera.clone();
+                camera.resize(preview_size);
+
+                let start = Instant::now();
+                let mut metrics = self.renderer.render_pass(
+                    &camera,
+                    &self.scene,
+                    &mut preview_acc,
+                    &mut [],
+                    self.seed,
+                    preview_sample,
+                );
+                metrics.render_time = start.elapsed();
+                *render_pass_metrics = metrics;
+                *scene_metrics = self.scene.metrics;
+                summary.clone_from(&self.summary);
+
+                // Blown up with nearest neighbor filtering, the AOVs are
+                // left out until the full resolution passes
                 image_data.resize(len, [0.0; 4]);
-                acc.clear();
-                acc.resize(len, Vec4::ZERO);
+                let width = self.size.x as usize;
+                let preview_width = preview_size.x as usize;
+                let scale = PREVIEW_SCALE as usize;
+                for (i, pixel) in image_data.iter_mut().enumerate() {
+                    let (x, y) = (i % width / scale, i / width / scale);
+                    *pixel = preview_acc[y * preview_width + x].mean().to_array();
+                }
+                *aovs = Aovs::default();
+                denoised.clear();
+                published.clear();
+
+                self.preview_passes -= 1;
+                converged = false;
+                self.input.publish();
+                continue;
             }
 
-            *image_size = self.size;
-
-            self.samples += 1;
+            if image_data.len() != len || self.samples == 0 {
+                image_data.resize(len, [0.0; 4]);
+                acc.clear();
+                acc.resize(len, Pixel::default());
+                if self.aovs {
+                    aov_acc.clear();
+                    aov_acc.resize(len, AovPixel::default());
+                }
+                denoised.clear();
+            }
 
             let start = Instant::now();
 
-            let mut metrics =
-                self.renderer
-                    .render_pass(&self.camera, &self.scene, &mut acc, &mut rng);
+            let mut metrics = self.renderer.render_pass(
+                &self.camera,
+                &self.scene,
+                &mut acc,
+                &mut aov_acc,
+                self.seed,
+                self.samples as u32,
+            );
+
+            self.samples += 1;
+            converged = metrics.samples == 0;
 
             metrics.render_time = start.elapsed();
             *render_pass_metrics = metrics;
+            *scene_metrics = self.scene.metrics;
+            summary.clone_from(&self.summary);
+
+            for (i, pixel) in acc.iter().enumerate() {
+                image_data[i] = pixel.mean().to_array();
+            }
+            if self.aovs {
+                aovs.resolve(&aov_acc, &acc);
+            }
 
-            for (i, acc_sample) in acc.iter().enumerate() {
-                image_data[i] = (acc_sample / self.samples as f32).to_array();
+            match self.denoise {
+                Some(denoiser)
+                    if denoised.is_empty() || converged || Instant::now() >= next_denoise =>
+                {
+                    let start = Instant::now();
+                    denoised = denoiser.denoise(image_data, aovs, self.size.x);
+                    next_denoise = start + start.elapsed() * DENOISE_INTERVAL;
+                }
+                Some(_) => {}
+                None => denoised.clear(),
             }
+            published.clone_from(&denoised);
 
             self.input.publish();
         }
     }
 
-    pub fn render_image(mut self, samples_per_pixel: u32) -> Vec<[f32; 4]> {
-        let mut rng = fastrand::Rng::new();
-        let mut acc = vec![Vec4::ZERO; (self.size.x * self.size.y) as usize];
+    /// Renders until `stop`, returning the image, denoised if enabled, and
+    /// its AOVs, which are empty unless enabled or needed for denoising
+    pub fn render_image(mut self, stop: StopCondition) -> (Vec<[f32; 4]>, Aovs) {
+        let len = (self.size.x * self.size.y) as usize;
+        let mut acc = vec![Pixel::default(); len];
+        let mut aov_acc = vec![AovPixel::default(); if self.aovs { len } else { 0 }];
+
+        let start = Instant::now();
+        let mut samples = 0;
+        let mut traced = 0;
+
+        for i in 0..stop.samples {
+            if stop.time.is_some_and(|t| start.elapsed() >= t) {
+                println!("time limit reached");
+                break;
+            }
+
+            let metrics = self.renderer.render_pass(
+                &self.camera,
+                &self.scene,
+                &mut acc,
+                &mut aov_acc,
+                self.seed,
+                i,
+            );
+
+            if metrics.samples == 0 {
+                println!("all pixels converged");
+                break;
+            }
+
+            samples = i + 1;
+            traced += metrics.samples;
 
-        for i in 0..samples_per_pixel {
             if i % 10 == 0 {
-                println!("{i}/{samples_per_pixel}");
+                println!("{i}/{}: {} samples", stop.samples, metrics.samples);
             }
+        }
+
+        println!(
+            "{samples} passes, {:.1} samples per pixel on average, {:.2?}",
+            traced as f32 / acc.len().max(1) as f32,
+            start.elapsed()
+        );
 
-            let _metrics = self
-                .renderer
-                .render_pass(&self.camera, &self.scene, &mut acc, &mut rng);
+        let mut aovs = Aovs::default();
+        if self.aovs {
+            aovs.resolve(&aov_acc, &acc);
         }
 
-        acc.into_iter()
-            .map(|p| (p / samples_per_pixel as f32).to_array())
-            .collect()
+        let image: Vec<_> = acc.into_iter().map(|p| p.mean().to_array()).collect();
+        match self.denoise {
+            Some(denoiser) => {
+                let start = Instant::now();
+                let image = denoiser.denoise(&image, &aovs, self.size.x);
+                println!("denoised in {:.2?}", start.elapsed());
+                (image, aovs)
+            }
+            None => (image, aovs),
+        }
     }
 }
 
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    /// Moment the ray travels at, moving geometry is at its start at zero
+    /// and at its end at one
+    pub time: f32,
 }
 
 impl Ray {
-    pub fn new(origin: Vec3, direction: Vec3) -> Self {
-        Self { origin, direction }
+    pub fn new(origin: Vec3, direction: Vec3, time: f32) -> Self {
+        Self {
+            origin,
+            direction,
+            time,
+        }
     }
 }
 
+/// Identifies the primitive a ray hit, indices are in insertion order
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Primitive {
+    Triangle(u32),
+    Sphere(u32),
+    /// Triangle of the shared mesh an instance places
+    Instance {
+        instance: u32,
+        triangle: u32,
+    },
+}
+
 #[derive(Clone)]
 pub struct HitRecord {
     pub pos: Vec3,
     pub normal: Vec3,
     pub t: f32,
     pub material: u32,
+    pub primitive: Primitive,
+    /// Vertex color scaling the material's reflectance, white without one
+    pub color: Vec3,
+}
+
+/// Relative luminance of a linear Rec. 709 color
+#[inline(always)]
+pub(crate) fn luminance(c: Vec3) -> f32 {
+    c.dot(vec3(0.2126, 0.7152, 0.0722))
 }
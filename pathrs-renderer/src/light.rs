@@ -0,0 +1,359 @@
+use std::{collections::HashMap, f32::consts::PI};
+
+use fastrand::Rng;
+use glam::{Vec3, vec3};
+
+use crate::{
+    HitRecord, Primitive,
+    geometry::{Spheres, Triangles},
+    instance::{Instance, SharedMesh},
+    luminance,
+    material::{Frame, Material},
+};
+
+/// A point on a light as seen from a shading point
+pub struct LightSample {
+    /// Unit direction from the shading point towards the light
+    pub dir: Vec3,
+    /// Distance to the sampled point
+    pub dist: f32,
+    pub emitted: Vec3,
+    /// Solid angle density, including the probability of picking the light
+    pub pdf: f32,
+}
+
+#[derive(Clone, Copy)]
+enum Shape {
+    Triangle {
+        v0: Vec3,
+        e1: Vec3,
+        e2: Vec3,
+        normal: Vec3,
+    },
+    Sphere {
+        center: Vec3,
+        r_squared: f32,
+    },
+}
+
+#[derive(Clone, Copy)]
+struct Light {
+    /// Shape at time zero
+    shape: Shape,
+    /// Change of the shape's points from time zero to time one, `None` for
+    /// lights that don't move
+    motion: Option<Shape>,
+    emitted: Vec3,
+    /// Probability of picking this light
+    pmf: f32,
+    /// Area at time zero
+    area: f32,
+}
+
+impl Light {
+    /// Emitted luminance times area, what lights are picked by
+    fn power(&self) -> f32 {
+        luminance(self.emitted) * self.area
+    }
+
+    /// Shape and area of the light at `time`
+    #[inline(always)]
+    fn at(&self, time: f32) -> (Shape, f32) {
+        match (self.shape, self.motion) {
+            (
+                Shape::Triangle { v0, e1, e2, .. },
+                Some(Shape::Triangle {
+                    v0: dv0,
+                    e1: de1,
+                    e2: de2,
+                    ..
+                }),
+            ) => {
+                let (e1, e2) = (e1 + time * de1, e2 + time * de2);
+                let cross = e1.cross(e2);
+                let shape = Shape::Triangle {
+                    v0: v0 + time * dv0,
+                    e1,
+                    e2,
+                    normal: cross.normalize_or_zero(),
+                };
+                (shape, 0.5 * cross.length())
+            }
+            (Shape::Sphere { center, r_squared }, Some(Shape::Sphere { center: d, .. })) => {
+                let shape = Shape::Sphere {
+                    center: center + time * d,
+                    r_squared,
+                };
+                (shape, self.area)
+            }
+            _ => (self.shape, self.area),
+        }
+    }
+}
+
+/// Emissive primitives of a scene, picked proportionally to their power
+#[derive(Clone, Default)]
+pub struct Lights {
+    lights: Vec<Light>,
+    cdf: Vec<f32>,
+    index: HashMap<Primitive, usize>,
+}
+
+impl Lights {
+    pub fn collect(
+        materials: &[Material],
+        triangles: &Triangles,
+        spheres: &Spheres,
+        instances: &[Instance],
+        meshes: &[SharedMesh],
+    ) -> Lights {
+        let mut lights = Vec::new();
+        let mut index = HashMap::new();
+
+        // Lights without power, like degenerate triangles, are never picked
+        // and would leave the pmf undefined if no light had any
+        let mut add = |primitive: Primitive, light: Light| {
+            if light.power() > 0.0 {
+                index.insert(primitive, lights.len());
+                lights.push(light);
+            }
+        };
+
+        for i in 0..triangles.len() {
+            let emitted = materials[triangles.material(i) as usize].emission();
+            if emitted == Vec3::ZERO {
+                continue;
+            }
+
+            let (v0, e1, e2) = triangles.edges(i);
+            let motion = triangles.vertices_end(i).map(|[a, b, c]| Shape::Triangle {
+                v0: a - v0,
+                e1: (b - a) - e1,
+                e2: (c - a) - e2,
+                normal: Vec3::ZERO,
+            });
+
+            add(
+                Primitive::Triangle(i as u32),
+                Light {
+                    shape: Shape::Triangle {
+                        v0,
+                        e1,
+                        e2,
+                        normal: triangles.normal(i),
+                    },
+                    motion,
+                    emitted,
+                    pmf: 0.0,
+                    area: triangles.area(i),
+                },
+            );
+        }
+
+        for i in 0..spheres.len() {
+            let emitted = materials[spheres.material(i) as usize].emission();
+            if emitted == Vec3::ZERO {
+                continue;
+            }
+
+            let r_squared = spheres.radius_squared(i);
+            let motion = spheres.motion(i);
+
+            add(
+                Primitive::Sphere(i as u32),
+                Light {
+                    shape: Shape::Sphere {
+                        center: spheres.center(i),
+                        r_squared,
+                    },
+                    motion: (motion != Vec3::ZERO).then_some(Shape::Sphere {
+                        center: motion,
+                        r_squared: 0.0,
+                    }),
+                    emitted,
+                    pmf: 0.0,
+                    area: 4.0 * PI * r_squared,
+                },
+            );
+        }
+
+        // Every instance of an emissive mesh is a light of its own, in world
+        // space
+        for (i, instance) in instances.iter().enumerate() {
+            let mesh = &meshes[instance.mesh as usize];
+            for t in 0..mesh.triangles().len() {
+                let emitted = materials[instance.material(mesh, t) as usize].emission();
+                if emitted == Vec3::ZERO {
+                    continue;
+                }
+
+                let ([v0, v1, v2], end) = instance.vertices(mesh, t);
+                let (e1, e2) = (v1 - v0, v2 - v0);
+                let cross = e1.cross(e2);
+                let motion = end.map(|[a, b, c]| Shape::Triangle {
+                    v0: a - v0,
+                    e1: (b - a) - e1,
+                    e2: (c - a) - e2,
+                    normal: Vec3::ZERO,
+                });
+
+                let primitive = Primitive::Instance {
+                    instance: i as u32,
+                    triangle: t as u32,
+                };
+                add(
+                    primitive,
+                    Light {
+                        shape: Shape::Triangle {
+                            v0,
+                            e1,
+                            e2,
+                            normal: cross.normalize_or_zero(),
+                        },
+                        motion,
+                        emitted,
+                        pmf: 0.0,
+                        area: 0.5 * cross.length(),
+                    },
+                );
+            }
+        }
+
+        let total: f32 = lights.iter().map(Light::power).sum();
+
+        let mut cdf = Vec::with_capacity(lights.len());
+        let mut acc = 0.0;
+        for light in &mut lights {
+            light.pmf = light.power() / total;
+            acc += light.pmf;
+            cdf.push(acc);
+        }
+
+        Lights { lights, cdf, index }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    /// Picks a light by power and a point on it at `time` visible from `p`.
+    /// Triangles are sampled uniformly by area, spheres by the cone they
+    /// subtend.
+    #[inline(always)]
+    pub fn sample(&self, p: Vec3, time: f32, rng: &mut Rng) -> Option<LightSample> {
+        if self.lights.is_empty() {
+            return None;
+        }
+
+        let u = rng.f32();
+        let i = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .min(self.lights.len() - 1);
+        let light = &self.lights[i];
+        let (shape, area) = light.at(time);
+
+        match shape {
+            Shape::Triangle { v0, e1, e2, normal } => {
+                let su = rng.f32().sqrt();
+                let v = rng.f32();
+                let x = v0 + e1 * (su * (1.0 - v)) + e2 * (su * v);
+
+                let d = x - p;
+                let dist_squared = d.length_squared();
+                let dist = dist_squared.sqrt();
+                let dir = d / dist;
+
+                let cos_light = normal.dot(dir).abs();
+                if cos_light < 1e-6 {
+                    return None;
+                }
+
+                Some(LightSample {
+                    dir,
+                    dist,
+                    emitted: light.emitted,
+                    pdf: light.pmf * dist_squared / (area * cos_light),
+                })
+            }
+            Shape::Sphere { center, r_squared } => {
+                let d = center - p;
+                let dist_squared = d.length_squared();
+                let cos_max = cone_cos_max(dist_squared, r_squared)?;
+
+                let cos_theta = 1.0 - rng.f32() * (1.0 - cos_max);
+                let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                let phi = 2.0 * PI * rng.f32();
+
+                let dist_center = dist_squared.sqrt();
+                let frame = Frame::from_normal(d / dist_center);
+                let dir = frame.to_world(vec3(
+                    sin_theta * phi.cos(),
+                    sin_theta * phi.sin(),
+                    cos_theta,
+                ));
+
+                let dist = dist_center * cos_theta
+                    - (r_squared - dist_squared * sin_theta * sin_theta)
+                        .max(0.0)
+                        .sqrt();
+
+                Some(LightSample {
+                    dir,
+                    dist,
+                    emitted: light.emitted,
+                    pdf: light.pmf / (2.0 * PI * (1.0 - cos_max)),
+                })
+            }
+        }
+    }
+
+    /// Density [`Lights::sample`] would have produced `hit` at `time` with
+    /// from `p`, zero if the hit primitive isn't a light
+    #[inline(always)]
+    pub fn pdf(&self, p: Vec3, time: f32, hit: &HitRecord) -> f32 {
+        let Some(&i) = self.index.get(&hit.primitive) else {
+            return 0.0;
+        };
+        let light = &self.lights[i];
+        let (shape, area) = light.at(time);
+
+        match shape {
+            Shape::Triangle { normal, .. } => {
+                let d = hit.pos - p;
+                let dist_squared = d.length_squared();
+                let cos_light = normal.dot(d).abs() / dist_squared.sqrt();
+                if cos_light < 1e-6 {
+                    return 0.0;
+                }
+
+                light.pmf * dist_squared / (area * cos_light)
+            }
+            Shape::Sphere { center, r_squared } => {
+                match cone_cos_max((center - p).length_squared(), r_squared) {
+                    Some(cos_max) => light.pmf / (2.0 * PI * (1.0 - cos_max)),
+                    None => 0.0,
+                }
+            }
+        }
+    }
+}
+
+/// Cosine of the half-angle of the cone a sphere subtends, `None` from inside
+#[inline(always)]
+fn cone_cos_max(dist_squared: f32, r_squared: f32) -> Option<f32> {
+    if dist_squared <= r_squared {
+        return None;
+    }
+
+    let sin_squared = r_squared / dist_squared;
+    Some((1.0 - sin_squared).max(0.0).sqrt())
+}
+
+/// Power heuristic (β = 2) weight for a sample from the strategy with `pdf`
+#[inline(always)]
+pub fn power_heuristic(pdf: f32, other: f32) -> f32 {
+    let a = pdf * pdf;
+    let b = other * other;
+    if a + b == 0.0 { 0.0 } else { a / (a + b) }
+}
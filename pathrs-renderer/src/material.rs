@@ -1,7 +1,58 @@
+use std::f32::consts::{FRAC_1_PI, PI};
+
 use glam::{Vec3, vec3};
 
 use crate::{HitRecord, Ray};
 
+/// Orthonormal basis around a shading normal. Local directions have the
+/// normal along +z, so `cos θ` is just the z component.
+#[derive(Clone, Copy)]
+pub struct Frame {
+    t: Vec3,
+    b: Vec3,
+    n: Vec3,
+}
+
+impl Frame {
+    #[inline(always)]
+    pub fn from_normal(n: Vec3) -> Frame {
+        let (t, b) = n.any_orthonormal_pair();
+        Frame { t, b, n }
+    }
+
+    #[inline(always)]
+    pub fn to_local(self, v: Vec3) -> Vec3 {
+        vec3(v.dot(self.t), v.dot(self.b), v.dot(self.n))
+    }
+
+    #[inline(always)]
+    pub fn to_world(self, v: Vec3) -> Vec3 {
+        self.t * v.x + self.b * v.y + self.n * v.z
+    }
+}
+
+/// Kind of scattering event, path length limits are counted per kind
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Lobe {
+    Diffuse,
+    Specular,
+    Transmission,
+}
+
+/// Direction picked by [`Material::sample`]
+pub struct BsdfSample {
+    /// Unit incident direction, pointing away from the surface
+    pub wi: Vec3,
+    /// BSDF times cosine divided by `pdf`, the path throughput factor
+    pub weight: Vec3,
+    /// Solid angle density of `wi`, only meaningful for non-delta samples
+    pub pdf: f32,
+    /// The sample came from a delta lobe which [`Material::eval`] and
+    /// [`Material::pdf`] can't reproduce
+    pub delta: bool,
+    pub lobe: Lobe,
+}
+
 #[derive(Clone, Copy)]
 pub enum Material {
     Lambertian(Lambertian),
@@ -10,7 +61,12 @@ pub enum Material {
     DiffuseLight(DiffuseLight),
 }
 
+/// Directions passed to and returned from [`Material`] are unit length and in
+/// world space, `wo` points back along the incoming ray. The lobes sample
+/// and evaluate in the local [`Frame`] of the hit.
 impl Material {
+    /// Scattered ray and its attenuation, `None` if the path is absorbed.
+    /// A [`Material::sample`] turned into a ray.
     #[inline(always)]
     pub fn scatter(
         &self,
@@ -18,14 +74,65 @@ impl Material {
         hit: &HitRecord,
         rng: &mut fastrand::Rng,
     ) -> Option<(Ray, Vec3)> {
+        let sample = self.sample(-ray.direction.normalize(), hit, rng)?;
+        Some((Ray::new(hit.pos, sample.wi, ray.time), sample.weight))
+    }
+
+    /// Picks an incident direction for light leaving along `wo`, `None` if
+    /// the path is absorbed
+    #[inline(always)]
+    pub fn sample(&self, wo: Vec3, hit: &HitRecord, rng: &mut fastrand::Rng) -> Option<BsdfSample> {
+        let frame = Frame::from_normal(hit.normal);
+        let wo = frame.to_local(wo);
+
+        let mut sample = match self {
+            Material::Lambertian(l) => l.sample(wo, rng),
+            Material::Metal(m) => m.sample(wo, rng),
+            Material::Dielectric(d) => d.sample(wo, rng),
+            Material::DiffuseLight(dl) => dl.sample(wo, rng),
+        }?;
+
+        sample.wi = frame.to_world(sample.wi);
+        sample.weight *= hit.color;
+        Some(sample)
+    }
+
+    /// BSDF times cosine for light arriving from `wi` and leaving along `wo`,
+    /// zero for delta lobes
+    #[inline(always)]
+    pub fn eval(&self, wo: Vec3, wi: Vec3, hit: &HitRecord) -> Vec3 {
+        let frame = Frame::from_normal(hit.normal);
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+
+        let f = match self {
+            Material::Lambertian(l) => l.eval(wo, wi),
+            Material::Metal(_) | Material::Dielectric(_) | Material::DiffuseLight(_) => Vec3::ZERO,
+        };
+        f * hit.color
+    }
+
+    /// Solid angle density of [`Material::sample`] returning `wi`, zero for
+    /// delta lobes
+    #[inline(always)]
+    pub fn pdf(&self, wo: Vec3, wi: Vec3, hit: &HitRecord) -> f32 {
+        let frame = Frame::from_normal(hit.normal);
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+
         match self {
-            Material::Lambertian(l) => l.scatter(ray, hit, rng),
-            Material::Metal(m) => m.scatter(ray, hit, rng),
-            Material::Dielectric(d) => d.scatter(ray, hit, rng),
-            Material::DiffuseLight(dl) => dl.scatter(ray, hit, rng),
+            Material::Lambertian(l) => l.pdf(wo, wi),
+            Material::Metal(_) | Material::Dielectric(_) | Material::DiffuseLight(_) => 0.0,
         }
     }
 
+    /// All lobes are delta distributions, light sampling can't contribute.
+    /// Fuzzy metals count as delta as well: their perturbed lobe has no
+    /// closed form density for [`Material::eval`] and [`Material::pdf`], so
+    /// they only pick up light their own samples hit.
+    #[inline(always)]
+    pub fn is_delta(&self) -> bool {
+        matches!(self, Material::Metal(_) | Material::Dielectric(_))
+    }
+
     #[inline(always)]
     pub fn emitted(&self, ray: &Ray, hit: &HitRecord) -> Vec3 {
         match self {
@@ -35,11 +142,32 @@ impl Material {
             Material::DiffuseLight(dl) => dl.emitted(ray, hit),
         }
     }
+
+    /// Radiance emitted by the material regardless of direction, used to
+    /// find and weight the lights of a scene
+    pub fn emission(&self) -> Vec3 {
+        match self {
+            Material::DiffuseLight(dl) => dl.emitted,
+            _ => Vec3::ZERO,
+        }
+    }
+
+    /// Reflectance color for the albedo AOV. Glass is white and lights show
+    /// their emission clamped to one.
+    pub fn albedo(&self, hit: &HitRecord) -> Vec3 {
+        let albedo = match self {
+            Material::Lambertian(l) => l.albedo,
+            Material::Metal(m) => m.albedo,
+            Material::Dielectric(_) => Vec3::ONE,
+            Material::DiffuseLight(dl) => dl.emitted.min(Vec3::ONE),
+        };
+        albedo * hit.color
+    }
 }
 
 #[derive(Clone, Copy)]
 pub struct Lambertian {
-    albedo: Vec3,
+    pub albedo: Vec3,
 }
 
 impl Lambertian {
@@ -49,24 +177,32 @@ impl Lambertian {
 
     #[inline(always)]
     #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
-    fn scatter(&self, _ray: &Ray, hit: &HitRecord, rng: &mut fastrand::Rng) -> Option<(Ray, Vec3)> {
-        let mut scatter_dir = hit.normal + random_unit_vec(rng);
+    fn sample(&self, wo: Vec3, rng: &mut fastrand::Rng) -> Option<BsdfSample> {
+        let mut wi = Vec3::Z + random_unit_vec(rng);
 
         let epsilon = 1e-8;
-        if scatter_dir.x.abs() < epsilon
-            && scatter_dir.y.abs() < epsilon
-            && scatter_dir.z.abs() < epsilon
-        {
-            scatter_dir = hit.normal;
+        if wi.x.abs() < epsilon && wi.y.abs() < epsilon && wi.z.abs() < epsilon {
+            wi = Vec3::Z;
         }
+        let wi = wi.normalize();
+
+        Some(BsdfSample {
+            wi,
+            weight: self.albedo,
+            pdf: self.pdf(wo, wi),
+            delta: false,
+            lobe: Lobe::Diffuse,
+        })
+    }
 
-        let scattered = Ray {
-            origin: hit.pos,
-            direction: scatter_dir,
-        };
-        let attenuation = self.albedo;
+    #[inline(always)]
+    fn eval(&self, _wo: Vec3, wi: Vec3) -> Vec3 {
+        self.albedo * FRAC_1_PI * wi.z.max(0.0)
+    }
 
-        Some((scattered, attenuation))
+    #[inline(always)]
+    fn pdf(&self, _wo: Vec3, wi: Vec3) -> f32 {
+        wi.z.max(0.0) * FRAC_1_PI
     }
 
     #[inline(always)]
@@ -84,8 +220,8 @@ impl From<Lambertian> for Material {
 
 #[derive(Clone, Copy)]
 pub struct Metal {
-    albedo: Vec3,
-    fuzz: f32,
+    pub albedo: Vec3,
+    pub fuzz: f32,
 }
 
 impl Metal {
@@ -93,18 +229,21 @@ impl Metal {
         Self { albedo, fuzz }
     }
 
+    /// Mirror reflection perturbed by `fuzz`, the perturbed lobe has no
+    /// closed form density so it is treated as a delta lobe
     #[inline(always)]
     #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
-    fn scatter(&self, ray: &Ray, hit: &HitRecord, rng: &mut fastrand::Rng) -> Option<(Ray, Vec3)> {
-        let reflected = ray.direction.reflect(hit.normal);
-        let reflected = reflected.normalize() + self.fuzz * random_unit_vec(rng);
-        let scattered = Ray {
-            origin: hit.pos,
-            direction: reflected,
-        };
-        let attenuation = self.albedo;
-
-        Some((scattered, attenuation))
+    fn sample(&self, wo: Vec3, rng: &mut fastrand::Rng) -> Option<BsdfSample> {
+        let reflected = vec3(-wo.x, -wo.y, wo.z);
+        let wi = (reflected + self.fuzz * random_unit_vec(rng)).normalize();
+
+        Some(BsdfSample {
+            wi,
+            weight: self.albedo,
+            pdf: 1.0,
+            delta: true,
+            lobe: Lobe::Specular,
+        })
     }
 
     #[inline(always)]
@@ -122,7 +261,7 @@ impl From<Metal> for Material {
 
 #[derive(Clone, Copy)]
 pub struct Dielectric {
-    refraction_index: f32,
+    pub refraction_index: f32,
 }
 
 impl Dielectric {
@@ -132,32 +271,31 @@ impl Dielectric {
 
     #[inline(always)]
     #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
-    fn scatter(&self, ray: &Ray, hit: &HitRecord, rng: &mut fastrand::Rng) -> Option<(Ray, Vec3)> {
-        let attenuation = vec3(1.0, 1.0, 1.0);
-
-        let (ri, n) = if ray.direction.dot(hit.normal) < 0.0 {
-            (1.0 / self.refraction_index, hit.normal)
+    fn sample(&self, wo: Vec3, rng: &mut fastrand::Rng) -> Option<BsdfSample> {
+        let (ri, n) = if wo.z > 0.0 {
+            (1.0 / self.refraction_index, Vec3::Z)
         } else {
-            (self.refraction_index, -hit.normal)
+            (self.refraction_index, Vec3::NEG_Z)
         };
 
-        let unit_dir = ray.direction.normalize();
+        let unit_dir = -wo;
 
-        let cos_theta = -unit_dir.dot(n).min(1.0);
+        let cos_theta = wo.dot(n).min(1.0);
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
-        let direction = if ri * sin_theta > 1.0 || reflectance(cos_theta, ri) > rng.f32() {
-            unit_dir.reflect(n)
+        let (wi, lobe) = if ri * sin_theta > 1.0 || reflectance(cos_theta, ri) > rng.f32() {
+            (unit_dir.reflect(n), Lobe::Specular)
         } else {
-            unit_dir.refract(n, ri)
-        };
-
-        let scattered = Ray {
-            origin: hit.pos,
-            direction,
+            (unit_dir.refract(n, ri), Lobe::Transmission)
         };
 
-        Some((scattered, attenuation))
+        Some(BsdfSample {
+            wi,
+            weight: Vec3::ONE,
+            pdf: 1.0,
+            delta: true,
+            lobe,
+        })
     }
 
     #[inline(always)]
@@ -182,7 +320,7 @@ fn reflectance(cos_theta: f32, ri: f32) -> f32 {
 
 #[derive(Clone, Copy)]
 pub struct DiffuseLight {
-    emitted: Vec3,
+    pub emitted: Vec3,
 }
 
 impl DiffuseLight {
@@ -191,12 +329,7 @@ impl DiffuseLight {
     }
 
     #[inline(always)]
-    fn scatter(
-        &self,
-        _ray: &Ray,
-        _hit: &HitRecord,
-        _rng: &mut fastrand::Rng,
-    ) -> Option<(Ray, Vec3)> {
+    fn sample(&self, _wo: Vec3, _rng: &mut fastrand::Rng) -> Option<BsdfSample> {
         None
     }
 
@@ -216,33 +349,12 @@ impl From<DiffuseLight> for Material {
     }
 }
 
-#[inline(always)]
-fn random_on_hemisphere(normal: Vec3, rng: &mut fastrand::Rng) -> Vec3 {
-    let vec = random_on_unit_sphere(rng);
-
-    if vec.dot(normal) > 0.0 { vec } else { -vec }
-}
-
-#[inline(always)]
-fn random_on_unit_sphere(rng: &mut fastrand::Rng) -> Vec3 {
-    loop {
-        let vec = random_unit_vec(rng);
-        let len_squared = vec.length_squared();
-
-        if 1e-30 < len_squared && len_squared <= 1.0 {
-            return vec / len_squared.sqrt();
-        }
-    }
-}
-
+/// Uniform direction on the unit sphere, so `Vec3::Z + random_unit_vec()`
+/// is cosine distributed around the local normal
 #[inline(always)]
 fn random_unit_vec(rng: &mut fastrand::Rng) -> Vec3 {
-    use fastrand_contrib::RngExt;
-
-    vec3(
-        rng.f32_range(-1.0..=1.0),
-        rng.f32_range(-1.0..=1.0),
-        rng.f32_range(-1.0..=1.0),
-    )
-    .normalize()
+    let z = 1.0 - 2.0 * rng.f32();
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * rng.f32();
+    vec3(r * phi.cos(), r * phi.sin(), z)
 }
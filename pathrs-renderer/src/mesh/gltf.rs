@@ -0,0 +1,374 @@
+//! glTF 2.0 scenes, `.gltf` with external or embedded buffers and `.glb`
+
+use std::{collections::HashMap, f32::consts::PI, path::Path};
+
+use ::gltf::{
+    Document, Gltf, Node,
+    camera::Projection,
+    khr_lights_punctual::{Kind, Light},
+    material::AlphaMode,
+    mesh::Mode,
+};
+use glam::{Affine3A, Mat4, Vec3, Vec4};
+
+use super::{ImportError, Imported, Mesh, Transform, default_material, file_material};
+use crate::{
+    camera::{self, CameraSettings},
+    geometry::Sphere,
+    material::{Dielectric, DiffuseLight, Lambertian, Material, Metal},
+    scene::Scene,
+};
+
+/// Radius of the spheres standing in for punctual lights
+const LIGHT_RADIUS: f32 = 0.05;
+
+/// Adds the default scene of the glTF file at `path` to `scene`.
+///
+/// Nodes are flattened with their transforms and then placed with
+/// `transform`. Metallic-roughness materials map to the closest [`Material`]:
+/// emissive ones become lights, transmissive ones glass, mostly metallic ones
+/// metal with their roughness as fuzz and the rest diffuse. Point and spot
+/// lights become small emissive spheres of the same intensity, taking
+/// candela as W/sr. Textures, skins and morph targets are ignored.
+/// Everything that was approximated is listed in the result.
+pub fn load_gltf(
+    scene: &mut Scene,
+    path: impl AsRef<Path>,
+    transform: &Transform,
+    material: Option<u32>,
+) -> Result<Imported, ImportError> {
+    let path = path.as_ref();
+    let Gltf { document, blob } = Gltf::open(path).map_err(|err| gltf_error(path, err))?;
+    let buffers = ::gltf::import_buffers(&document, path.parent(), blob)
+        .map_err(|err| gltf_error(path, err))?;
+
+    let root = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| ImportError::invalid(path, "no scene"))?;
+
+    let mut import = Import {
+        scene,
+        path,
+        buffers: &buffers,
+        material,
+        names: material_names(&document),
+        materials: HashMap::new(),
+        imported: Imported::default(),
+    };
+    for node in root.nodes() {
+        import.node(&node, transform.matrix())?;
+    }
+
+    Ok(import.imported)
+}
+
+fn gltf_error(path: &Path, err: ::gltf::Error) -> ImportError {
+    match err {
+        ::gltf::Error::Io(error) => ImportError::io(path, error),
+        err => ImportError::invalid(path, err.to_string()),
+    }
+}
+
+/// Material names, unnamed and repeated ones are told apart by their index
+fn material_names(document: &Document) -> Vec<String> {
+    let names: Vec<Option<&str>> = document.materials().map(|m| m.name()).collect();
+    names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| match name {
+            Some(name) if names.iter().filter(|n| **n == Some(*name)).count() == 1 => {
+                name.to_string()
+            }
+            Some(name) => format!("{name}#{i}"),
+            None => format!("material{i}"),
+        })
+        .collect()
+}
+
+struct Import<'a> {
+    scene: &'a mut Scene,
+    path: &'a Path,
+    buffers: &'a [::gltf::buffer::Data],
+    /// Replaces every glTF material when set
+    material: Option<u32>,
+    names: Vec<String>,
+    /// Scene material per glTF material, `None` is the default material
+    materials: HashMap<Option<usize>, u32>,
+    imported: Imported,
+}
+
+impl Import<'_> {
+    fn node(&mut self, node: &Node, parent: Affine3A) -> Result<(), ImportError> {
+        let local = Mat4::from_cols_array_2d(&node.transform().matrix());
+        let world = parent * Affine3A::from_mat4(local);
+
+        if let Some(mesh) = node.mesh() {
+            let name = mesh
+                .name()
+                .map_or_else(|| format!("mesh{}", mesh.index()), str::to_string);
+            for primitive in mesh.primitives() {
+                self.primitive(&primitive, world, &name)?;
+            }
+            if node.skin().is_some() {
+                self.approximate(format!("mesh `{name}`: skin ignored"));
+            }
+        }
+
+        if let Some(camera) = node.camera() {
+            self.camera(&camera, world);
+        }
+
+        if let Some(light) = node.light() {
+            self.light(&light, world);
+        }
+
+        for child in node.children() {
+            self.node(&child, world)?;
+        }
+        Ok(())
+    }
+
+    fn primitive(
+        &mut self,
+        primitive: &::gltf::Primitive,
+        world: Affine3A,
+        name: &str,
+    ) -> Result<(), ImportError> {
+        if primitive.mode() != Mode::Triangles {
+            self.approximate(format!(
+                "mesh `{name}`: {:?} primitive skipped",
+                primitive.mode()
+            ));
+            return Ok(());
+        }
+
+        let buffers = self.buffers;
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        let Some(positions) = reader.read_positions() else {
+            self.approximate(format!(
+                "mesh `{name}`: primitive without positions skipped"
+            ));
+            return Ok(());
+        };
+
+        let mut mesh = Mesh {
+            positions: positions.map(Vec3::from).collect(),
+            ..Default::default()
+        };
+        if let Some(normals) = reader.read_normals() {
+            mesh.normals = normals.map(Vec3::from).collect();
+        }
+        if let Some(colors) = reader.read_colors(0) {
+            mesh.colors = colors.into_rgb_f32().map(Vec3::from).collect();
+        }
+
+        let count = mesh.positions.len();
+        if (!mesh.normals.is_empty() && mesh.normals.len() != count)
+            || (!mesh.colors.is_empty() && mesh.colors.len() != count)
+        {
+            return Err(ImportError::invalid(
+                self.path,
+                format!("mesh `{name}`: attribute counts differ"),
+            ));
+        }
+
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..count as u32).collect(),
+        };
+        if indices.iter().any(|&i| i as usize >= count) {
+            return Err(ImportError::invalid(
+                self.path,
+                format!("mesh `{name}`: index out of range, {count} vertices defined"),
+            ));
+        }
+        mesh.faces = indices
+            .chunks_exact(3)
+            .map(|face| [face[0], face[1], face[2]])
+            .collect();
+
+        if primitive.morph_targets().len() > 0 {
+            self.approximate(format!("mesh `{name}`: morph targets ignored"));
+        }
+
+        let material = self.material(primitive.material());
+        mesh.materials = vec![material; mesh.faces.len()];
+        mesh.transform(world);
+
+        let before = self.scene.triangles().len();
+        self.scene.add_mesh(name, &mesh);
+        self.imported.triangles += self.scene.triangles().len() - before;
+        Ok(())
+    }
+
+    fn material(&mut self, material: ::gltf::Material) -> u32 {
+        if let Some(material) = self.material {
+            return material;
+        }
+
+        let key = material.index();
+        if let Some(&i) = self.materials.get(&key) {
+            return i;
+        }
+
+        let i = match key {
+            None => default_material(self.scene, self.path),
+            Some(index) => {
+                let name = &self.names[index];
+                let (converted, notes) = convert_material(&material);
+                for note in notes {
+                    self.imported
+                        .approximations
+                        .push(format!("material `{name}`: {note}"));
+                }
+                file_material(self.scene, self.path, name, || converted)
+            }
+        };
+
+        self.materials.insert(key, i);
+        i
+    }
+
+    /// Uses the first camera, looking down its -z axis
+    fn camera(&mut self, camera: &::gltf::Camera, world: Affine3A) {
+        let name = camera
+            .name()
+            .map_or_else(|| format!("camera{}", camera.index()), str::to_string);
+
+        if self.imported.camera.is_some() {
+            self.approximate(format!("camera `{name}` ignored, only the first is used"));
+            return;
+        }
+
+        let look_from = world.transform_point3(Vec3::ZERO);
+        let forward = world.transform_vector3(Vec3::NEG_Z).normalize();
+        let up = world.transform_vector3(Vec3::Y).normalize();
+
+        let mut settings = CameraSettings {
+            look_from,
+            look_at: look_from + forward,
+            up,
+            ..Default::default()
+        };
+        match camera.projection() {
+            Projection::Perspective(perspective) => {
+                settings.vfov = perspective.yfov().to_degrees();
+            }
+            Projection::Orthographic(orthographic) => {
+                // The height follows from the image aspect, not `ymag`
+                let scale = world.transform_vector3(Vec3::X).length();
+                settings.projection = camera::Projection::Orthographic {
+                    width: 2.0 * orthographic.xmag() * scale,
+                };
+            }
+        }
+
+        self.imported.camera = Some(settings);
+    }
+
+    fn light(&mut self, light: &Light, world: Affine3A) {
+        let name = light
+            .name()
+            .map_or_else(|| format!("light{}", light.index()), str::to_string);
+
+        match light.kind() {
+            Kind::Directional => {
+                self.approximate(format!("directional light `{name}` skipped"));
+                return;
+            }
+            Kind::Spot { .. } => {
+                self.approximate(format!("spot light `{name}`: cone ignored"));
+            }
+            Kind::Point => {}
+        }
+        self.approximate(format!(
+            "light `{name}` rendered as a sphere of radius {LIGHT_RADIUS}"
+        ));
+
+        // A sphere with radiance L has intensity L π r² in every direction
+        let intensity = Vec3::from(light.color()) * light.intensity();
+        let radiance = intensity / (PI * LIGHT_RADIUS * LIGHT_RADIUS);
+
+        let material = file_material(
+            self.scene,
+            self.path,
+            &format!("light{}", light.index()),
+            || DiffuseLight::new(radiance).into(),
+        );
+        self.scene.add_sphere(Sphere::new(
+            world.transform_point3(Vec3::ZERO),
+            LIGHT_RADIUS,
+            material,
+        ));
+    }
+
+    fn approximate(&mut self, note: String) {
+        self.imported.approximations.push(note);
+    }
+}
+
+/// Closest [`Material`] for a metallic-roughness material, with notes on
+/// what it doesn't capture
+fn convert_material(material: &::gltf::Material) -> (Material, Vec<String>) {
+    let mut notes = Vec::new();
+
+    let pbr = material.pbr_metallic_roughness();
+    let base = Vec4::from(pbr.base_color_factor());
+    let color = base.truncate();
+    let metallic = pbr.metallic_factor();
+    let roughness = pbr.roughness_factor();
+    let emission =
+        Vec3::from(material.emissive_factor()) * material.emissive_strength().unwrap_or(1.0);
+    let transmission = material
+        .transmission()
+        .map_or(0.0, |t| t.transmission_factor());
+
+    if pbr.base_color_texture().is_some()
+        || pbr.metallic_roughness_texture().is_some()
+        || material.normal_texture().is_some()
+        || material.occlusion_texture().is_some()
+        || material.emissive_texture().is_some()
+    {
+        notes.push("textures ignored".to_string());
+    }
+
+    if material.alpha_mode() != AlphaMode::Opaque && base.w < 1.0 {
+        notes.push(format!("alpha {} ignored, rendered opaque", base.w));
+    }
+
+    let converted = if emission != Vec3::ZERO {
+        notes.push("emissive, rendered as a light without reflectance".to_string());
+        DiffuseLight::new(emission).into()
+    } else if transmission > 0.0 {
+        if transmission < 1.0 {
+            notes.push(format!(
+                "transmission {transmission} rendered as clear glass"
+            ));
+        }
+        if color != Vec3::ONE {
+            notes.push("glass tint dropped".to_string());
+        }
+        if roughness > 0.0 {
+            notes.push(format!("glass roughness {roughness} rendered smooth"));
+        }
+        Dielectric::new(material.ior().unwrap_or(1.5)).into()
+    } else if metallic >= 0.5 {
+        if metallic < 1.0 {
+            notes.push(format!("metallic {metallic} rendered as metal"));
+        }
+        Metal::new(color, roughness).into()
+    } else {
+        if metallic > 0.0 {
+            notes.push(format!("metallic {metallic} rendered as diffuse"));
+        }
+        if roughness < 0.5 {
+            notes.push(format!(
+                "specular highlight of roughness {roughness} dropped"
+            ));
+        }
+        Lambertian::new(color).into()
+    };
+
+    (converted, notes)
+}
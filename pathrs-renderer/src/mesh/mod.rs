@@ -0,0 +1,228 @@
+//! Triangle mesh import
+
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use glam::{Affine3A, EulerRot, Quat, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    camera::CameraSettings,
+    material::{Lambertian, Material},
+    scene::Scene,
+};
+
+mod gltf;
+mod obj;
+mod ply;
+
+pub use gltf::load_gltf;
+pub use obj::load_obj;
+pub use ply::load_ply;
+
+/// Placement of an imported mesh, scaled then rotated then translated
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Transform {
+    pub scale: Vec3,
+    /// Rotation around the x, y and z axes in degrees, applied in that order
+    pub rotation: Vec3,
+    pub translation: Vec3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            scale: Vec3::ONE,
+            rotation: Vec3::ZERO,
+            translation: Vec3::ZERO,
+        }
+    }
+}
+
+impl Transform {
+    pub fn matrix(&self) -> Affine3A {
+        let rotation = Quat::from_euler(
+            EulerRot::ZYX,
+            self.rotation.z.to_radians(),
+            self.rotation.y.to_radians(),
+            self.rotation.x.to_radians(),
+        );
+        Affine3A::from_scale_rotation_translation(self.scale, rotation, self.translation)
+    }
+
+    /// Transform with the same `matrix`, if it has no shear
+    pub fn from_matrix(matrix: Affine3A) -> Option<Transform> {
+        let (scale, rotation, translation) = matrix.to_scale_rotation_translation();
+        let (z, y, x) = rotation.to_euler(EulerRot::ZYX);
+        let transform = Transform {
+            scale,
+            rotation: Vec3::new(x, y, z).map(f32::to_degrees),
+            translation,
+        };
+        let tolerance = 1e-5 * scale.abs().max_element().max(1.0);
+        transform
+            .matrix()
+            .abs_diff_eq(matrix, tolerance)
+            .then_some(transform)
+    }
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    /// Malformed statement, with the line it is on
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    /// Malformed or unsupported content without a line, like binary data
+    Invalid {
+        path: PathBuf,
+        message: String,
+    },
+}
+
+impl ImportError {
+    fn io(path: &Path, error: io::Error) -> ImportError {
+        ImportError::Io {
+            path: path.to_path_buf(),
+            error,
+        }
+    }
+
+    fn parse(path: &Path, line: usize, message: impl Into<String>) -> ImportError {
+        ImportError::Parse {
+            path: path.to_path_buf(),
+            line,
+            message: message.into(),
+        }
+    }
+
+    fn invalid(path: &Path, message: impl Into<String>) -> ImportError {
+        ImportError::Invalid {
+            path: path.to_path_buf(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::Io { path, error } => write!(f, "{}: {error}", path.display()),
+            ImportError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{line}: {message}", path.display()),
+            ImportError::Invalid { path, message } => write!(f, "{}: {message}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+/// Indexed triangles as read from a file, normals and colors are either
+/// empty or given per position
+#[derive(Clone, Default)]
+pub struct Mesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    /// Linear RGB vertex colors, multiplied into the material reflectance
+    pub colors: Vec<Vec3>,
+    pub faces: Vec<[u32; 3]>,
+    /// Material of each face
+    pub materials: Vec<u32>,
+}
+
+impl Mesh {
+    /// Moves the mesh into place, keeping the face winding consistent with
+    /// the normals when the transform mirrors it
+    pub fn transform(&mut self, matrix: Affine3A) {
+        for p in &mut self.positions {
+            *p = matrix.transform_point3(*p);
+        }
+
+        let normal_matrix = matrix.matrix3.inverse().transpose();
+        for n in &mut self.normals {
+            *n = normal_matrix.mul_vec3(*n).normalize_or_zero();
+        }
+
+        if matrix.matrix3.determinant() < 0.0 {
+            for face in &mut self.faces {
+                face.swap(1, 2);
+            }
+        }
+    }
+}
+
+/// What an import added besides triangles
+#[derive(Default)]
+pub struct Imported {
+    pub triangles: usize,
+    /// First perspective camera of the file
+    pub camera: Option<CameraSettings>,
+    /// Features of the file that were dropped or replaced by something close
+    pub approximations: Vec<String>,
+}
+
+/// Loads a mesh file into `scene` by its extension, `.obj`, `.ply`, `.gltf`
+/// or `.glb`. Faces use `material` if given, otherwise the file's own
+/// materials or a light grey diffuse default.
+pub fn load(
+    scene: &mut Scene,
+    path: impl AsRef<Path>,
+    transform: &Transform,
+    material: Option<u32>,
+) -> Result<Imported, ImportError> {
+    let path = path.as_ref();
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase());
+
+    let triangles = match extension.as_deref() {
+        Some("obj") => return load_obj(scene, path, transform, material),
+        Some("ply") => load_ply(scene, path, transform, material)?,
+        Some("gltf" | "glb") => return load_gltf(scene, path, transform, material),
+        _ => {
+            return Err(ImportError::invalid(
+                path,
+                "unsupported mesh format, expected .obj, .ply, .gltf or .glb",
+            ));
+        }
+    };
+
+    Ok(Imported {
+        triangles,
+        ..Default::default()
+    })
+}
+
+/// Scene material named `<file stem>/<name>`, added with `make` the first
+/// time so importing a file again reuses its materials
+fn file_material(
+    scene: &mut Scene,
+    path: &Path,
+    name: &str,
+    make: impl FnOnce() -> Material,
+) -> u32 {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = format!("{stem}/{name}");
+    match scene.material_index(&name) {
+        Some(i) => i,
+        None => scene.add_material(name, make()),
+    }
+}
+
+fn default_material(scene: &mut Scene, path: &Path) -> u32 {
+    file_material(scene, path, "default", || {
+        Lambertian::new(Vec3::splat(0.8)).into()
+    })
+}
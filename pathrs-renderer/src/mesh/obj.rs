@@ -0,0 +1,290 @@
+//! Wavefront OBJ meshes with MTL material libraries
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    mem,
+    path::Path,
+};
+
+use glam::Vec3;
+
+use super::{ImportError, Imported, Mesh, Transform, default_material, file_material};
+use crate::{
+    material::{Dielectric, DiffuseLight, Lambertian, Material, Metal},
+    scene::Scene,
+};
+
+/// Adds the faces of the OBJ file at `path` to `scene`. Only positions are
+/// read, normals and texture coordinates are ignored.
+///
+/// Polygons are fan triangulated, so they should be convex. Each group or
+/// object name becomes a triangle group of the scene, faces outside of one
+/// go into a group named after the file. A `usemtl` stays in effect across
+/// groups. Unless `material` overrides them, the MTL materials are added to
+/// the scene as `<file stem>/<material>`. Faces whose material library is
+/// missing or doesn't define their material use the default material, which
+/// is noted in the approximations.
+pub fn load_obj(
+    scene: &mut Scene,
+    path: impl AsRef<Path>,
+    transform: &Transform,
+    material: Option<u32>,
+) -> Result<Imported, ImportError> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|error| ImportError::io(path, error))?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut mesh = Mesh::default();
+    // Faces and their materials per group name, in order of appearance
+    let mut groups = vec![(stem.to_string(), Vec::new(), Vec::new())];
+    let mut group = 0;
+    let mut library = HashMap::new();
+    let mut approximations = Vec::new();
+    let mut current = material;
+    let mut face = Vec::new();
+
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|error| ImportError::io(path, error))?;
+        let error = |message: String| ImportError::parse(path, i + 1, message);
+
+        let line = line.split('#').next().unwrap_or_default();
+        let mut tokens = line.split_whitespace();
+
+        match tokens.next() {
+            Some("v") => {
+                let p = parse_vec3(&mut tokens)
+                    .ok_or_else(|| error("expected three vertex coordinates".to_string()))?;
+                mesh.positions.push(p);
+            }
+            Some("f") => {
+                face.clear();
+                for token in tokens {
+                    // Texture coordinate and normal indices are ignored
+                    let index = token.split('/').next().unwrap_or_default();
+                    let index: i64 = index
+                        .parse()
+                        .map_err(|_| error(format!("invalid vertex `{token}`")))?;
+
+                    // Negative indices count back from the last vertex
+                    let resolved = match index {
+                        1.. => index - 1,
+                        ..0 => mesh.positions.len() as i64 + index,
+                        0 => -1,
+                    };
+                    if !(0..mesh.positions.len() as i64).contains(&resolved) {
+                        return Err(error(format!(
+                            "vertex index {index} out of range, {} vertices defined",
+                            mesh.positions.len()
+                        )));
+                    }
+                    face.push(resolved as u32);
+                }
+
+                if face.len() < 3 {
+                    return Err(error("face needs at least three vertices".to_string()));
+                }
+
+                let material = match current {
+                    Some(material) => material,
+                    None => *current.insert(default_material(scene, path)),
+                };
+
+                let (_, faces, materials) = &mut groups[group];
+                for k in 1..face.len() - 1 {
+                    faces.push([face[0], face[k], face[k + 1]]);
+                    materials.push(material);
+                }
+            }
+            Some("mtllib") if material.is_none() => {
+                for name in tokens {
+                    match load_mtl(&dir.join(name)) {
+                        Ok(materials) => library.extend(materials),
+                        Err(ImportError::Io { error, .. }) => approximations.push(format!(
+                            "material library `{name}`: {error}, its materials use the default"
+                        )),
+                        Err(err) => return Err(err),
+                    }
+                }
+            }
+            Some("usemtl") if material.is_none() => {
+                let name = tokens
+                    .next()
+                    .ok_or_else(|| error("missing material name".to_string()))?;
+                current = Some(match library.get(name) {
+                    Some(mtl) => file_material(scene, path, name, || mtl.to_material()),
+                    None => {
+                        let note = format!("unknown material `{name}`, using the default");
+                        if !approximations.contains(&note) {
+                            approximations.push(note);
+                        }
+                        default_material(scene, path)
+                    }
+                });
+            }
+            // Groups without a name hold the faces outside of any group
+            Some("g" | "o") => {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                let name = if name.is_empty() { &stem } else { &*name };
+                group = match groups.iter().position(|(n, ..)| n == name) {
+                    Some(group) => group,
+                    None => {
+                        groups.push((name.to_string(), Vec::new(), Vec::new()));
+                        groups.len() - 1
+                    }
+                };
+            }
+            // Normals, texture coordinates, smoothing groups, lines and
+            // points don't affect the triangles
+            _ => {}
+        }
+    }
+
+    // Transformed together, then added one group at a time
+    let mut ranges = Vec::new();
+    for (name, faces, materials) in groups {
+        let start = mesh.faces.len();
+        mesh.faces.extend(faces);
+        mesh.materials.extend(materials);
+        if mesh.faces.len() > start {
+            ranges.push((name, start..mesh.faces.len()));
+        }
+    }
+    mesh.transform(transform.matrix());
+
+    let before = scene.triangles().len();
+    let (faces, materials) = (mem::take(&mut mesh.faces), mem::take(&mut mesh.materials));
+    for (name, range) in ranges {
+        mesh.faces = faces[range.clone()].to_vec();
+        mesh.materials = materials[range].to_vec();
+        scene.add_mesh(name, &mesh);
+    }
+    Ok(Imported {
+        triangles: scene.triangles().len() - before,
+        approximations,
+        ..Default::default()
+    })
+}
+
+/// Material statements of an MTL file, unset ones use the MTL defaults
+#[derive(Default)]
+struct Mtl {
+    diffuse: Option<Vec3>,
+    specular: Option<Vec3>,
+    emission: Option<Vec3>,
+    exponent: Option<f32>,
+    refraction_index: Option<f32>,
+    dissolve: Option<f32>,
+    illum: Option<u32>,
+}
+
+impl Mtl {
+    /// Closest renderer material: emissive (`Ke`) becomes a light, refracting
+    /// or transparent illumination models glass, reflecting ones metal with
+    /// the roughness of the `Ns` highlight, everything else is diffuse
+    fn to_material(&self) -> Material {
+        if let Some(emission) = self.emission
+            && emission != Vec3::ZERO
+        {
+            return DiffuseLight::new(emission).into();
+        }
+
+        let transparent =
+            matches!(self.illum, Some(4 | 6 | 7 | 9)) || self.dissolve.is_some_and(|d| d < 1.0);
+        if transparent {
+            return Dielectric::new(self.refraction_index.unwrap_or(1.5)).into();
+        }
+
+        if matches!(self.illum, Some(3 | 5)) {
+            let exponent = self.exponent.unwrap_or(0.0).max(0.0);
+            let fuzz = (2.0 / (exponent + 2.0)).sqrt();
+            return Metal::new(self.specular.unwrap_or(Vec3::ONE), fuzz).into();
+        }
+
+        Lambertian::new(self.diffuse.unwrap_or(Vec3::splat(0.8))).into()
+    }
+}
+
+fn load_mtl(path: &Path) -> Result<HashMap<String, Mtl>, ImportError> {
+    let file = File::open(path).map_err(|error| ImportError::io(path, error))?;
+
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, Mtl)> = None;
+
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|error| ImportError::io(path, error))?;
+        let error = |message: &str| ImportError::parse(path, i + 1, message);
+
+        let line = line.split('#').next().unwrap_or_default();
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+
+        if keyword == "newmtl" {
+            let name = tokens
+                .next()
+                .ok_or_else(|| error("missing material name"))?;
+            if let Some((name, mtl)) = current.replace((name.to_string(), Mtl::default())) {
+                materials.insert(name, mtl);
+            }
+            continue;
+        }
+
+        let Some((_, mtl)) = &mut current else {
+            continue;
+        };
+
+        let color = |tokens: &mut dyn Iterator<Item = &str>| {
+            parse_color(tokens).ok_or_else(|| error("expected an RGB color"))
+        };
+        let scalar = |tokens: &mut dyn Iterator<Item = &str>| {
+            tokens
+                .next()
+                .and_then(|t| t.parse().ok())
+                .ok_or_else(|| error("expected a number"))
+        };
+
+        match keyword {
+            "Kd" => mtl.diffuse = Some(color(&mut tokens)?),
+            "Ks" => mtl.specular = Some(color(&mut tokens)?),
+            "Ke" => mtl.emission = Some(color(&mut tokens)?),
+            "Ns" => mtl.exponent = Some(scalar(&mut tokens)?),
+            "Ni" => mtl.refraction_index = Some(scalar(&mut tokens)?),
+            "d" => mtl.dissolve = Some(scalar(&mut tokens)?),
+            "Tr" => mtl.dissolve = Some(1.0 - scalar(&mut tokens)?),
+            "illum" => {
+                let illum = tokens
+                    .next()
+                    .and_then(|t| t.parse().ok())
+                    .ok_or_else(|| error("expected an illumination model"))?;
+                mtl.illum = Some(illum);
+            }
+            // Ambient color, texture maps and the like
+            _ => {}
+        }
+    }
+
+    if let Some((name, mtl)) = current {
+        materials.insert(name, mtl);
+    }
+
+    Ok(materials)
+}
+
+fn parse_vec3<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Option<Vec3> {
+    let mut next = || tokens.next()?.parse::<f32>().ok();
+    Some(Vec3::new(next()?, next()?, next()?))
+}
+
+/// An `r [g b]` color, a single value is used for all channels
+fn parse_color(tokens: &mut dyn Iterator<Item = &str>) -> Option<Vec3> {
+    let values: Vec<f32> = tokens.map(|t| t.parse().ok()).collect::<Option<_>>()?;
+    match values[..] {
+        [v] => Some(Vec3::splat(v)),
+        [r, g, b] => Some(Vec3::new(r, g, b)),
+        _ => None,
+    }
+}
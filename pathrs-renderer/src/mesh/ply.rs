@@ -0,0 +1,497 @@
+//! Stanford PLY meshes with ASCII or binary bodies
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+};
+
+use glam::vec3;
+
+use super::{ImportError, Mesh, Transform, default_material};
+use crate::scene::Scene;
+
+/// Adds the faces of the PLY file at `path` to `scene`, returning the number
+/// of triangles added.
+///
+/// Vertices need `x`, `y` and `z`, normals (`nx`, `ny`, `nz`) and colors
+/// (`red`, `green`, `blue`) are used when present. Integer colors are taken
+/// as sRGB encoded, float colors as linear. Faces are fan triangulated from
+/// their `vertex_indices` list, other elements and properties are skipped.
+/// Faces use `material`, or a light grey diffuse default.
+pub fn load_ply(
+    scene: &mut Scene,
+    path: impl AsRef<Path>,
+    transform: &Transform,
+    material: Option<u32>,
+) -> Result<usize, ImportError> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|error| ImportError::io(path, error))?;
+    let mut reader = BufReader::new(file);
+
+    let header = read_header(&mut reader, path)?;
+    let mut body = Body {
+        reader,
+        path,
+        format: header.format,
+        line: header.lines,
+        offset: 0,
+        text: String::new(),
+        pos: 0,
+    };
+
+    let find = |name: &str| header.elements.iter().find(|e| e.name == name);
+    let vertices = find("vertex").ok_or_else(|| ImportError::invalid(path, "no vertex element"))?;
+    let faces = find("face").ok_or_else(|| {
+        ImportError::invalid(path, "no face element, point clouds are not supported")
+    })?;
+    let layout = VertexLayout::new(vertices, path)?;
+    let list = face_list(faces, path)?;
+
+    let material = material.unwrap_or_else(|| default_material(scene, path));
+
+    let mut mesh = Mesh::default();
+    let mut row = Vec::new();
+
+    for element in &header.elements {
+        match element.name.as_str() {
+            "vertex" => {
+                for _ in 0..element.count {
+                    body.read_row(element, &mut row)?;
+                    layout.push(&row, &mut mesh);
+                }
+            }
+            "face" => {
+                for r in 0..element.count {
+                    body.read_row(element, &mut row)?;
+
+                    // Earlier list properties shift where the indices start
+                    let mut start = 0;
+                    for property in &element.properties[..list] {
+                        start += match property.kind {
+                            Kind::Scalar(_) => 1,
+                            Kind::List { .. } => 1 + row[start] as usize,
+                        };
+                    }
+                    let indices = &row[start + 1..start + 1 + row[start] as usize];
+
+                    if indices.len() < 3 {
+                        return Err(body.error(format!("face {r} needs at least three vertices")));
+                    }
+                    if let Some(&i) = indices
+                        .iter()
+                        .find(|&&i| i < 0.0 || i >= vertices.count as f64)
+                    {
+                        return Err(body.error(format!(
+                            "face {r}: vertex index {i} out of range, {} vertices defined",
+                            vertices.count
+                        )));
+                    }
+
+                    for k in 1..indices.len() - 1 {
+                        mesh.faces
+                            .push([indices[0], indices[k], indices[k + 1]].map(|i| i as u32));
+                        mesh.materials.push(material);
+                    }
+                }
+            }
+            _ => {
+                for _ in 0..element.count {
+                    body.read_row(element, &mut row)?;
+                }
+            }
+        }
+    }
+
+    mesh.transform(transform.matrix());
+    let before = scene.triangles().len();
+    let name = path.file_stem().unwrap_or_default().to_string_lossy();
+    scene.add_mesh(name, &mesh);
+    Ok(scene.triangles().len() - before)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Scalar> {
+        Some(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    fn is_integer(self) -> bool {
+        !matches!(self, Scalar::F32 | Scalar::F64)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Scalar(Scalar),
+    List { count: Scalar, item: Scalar },
+}
+
+struct Property {
+    name: String,
+    kind: Kind,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+    /// Number of lines up to and including `end_header`
+    lines: usize,
+}
+
+fn read_header(reader: &mut impl BufRead, path: &Path) -> Result<Header, ImportError> {
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut line = String::new();
+
+    for number in 1.. {
+        let error = |message: &str| ImportError::parse(path, number, message);
+
+        line.clear();
+        if reader
+            .read_line(&mut line)
+            .map_err(|error| ImportError::io(path, error))?
+            == 0
+        {
+            return Err(error("missing end_header"));
+        }
+
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if number == 1 {
+            if tokens != ["ply"] {
+                return Err(error("not a PLY file"));
+            }
+            continue;
+        }
+
+        match tokens.as_slice() {
+            ["format", name, version] => {
+                if *version != "1.0" {
+                    return Err(error("unsupported format version, expected 1.0"));
+                }
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::LittleEndian,
+                    "binary_big_endian" => Format::BigEndian,
+                    _ => return Err(error("unknown format")),
+                });
+            }
+            ["element", name, count] => {
+                let count = count.parse().map_err(|_| error("invalid element count"))?;
+                elements.push(Element {
+                    name: name.to_string(),
+                    count,
+                    properties: Vec::new(),
+                });
+            }
+            ["property", rest @ ..] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| error("property outside of an element"))?;
+                let scalar = |name: &str| {
+                    Scalar::parse(name)
+                        .ok_or_else(|| error(&format!("unknown property type `{name}`")))
+                };
+
+                let (kind, name) = match rest {
+                    ["list", count, item, name] => {
+                        let count = scalar(count)?;
+                        if !count.is_integer() {
+                            return Err(error("list length must be an integer type"));
+                        }
+                        let item = scalar(item)?;
+                        (Kind::List { count, item }, name)
+                    }
+                    [ty, name] => (Kind::Scalar(scalar(ty)?), name),
+                    _ => return Err(error("malformed property")),
+                };
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    kind,
+                });
+            }
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            ["end_header"] => {
+                let format = format.ok_or_else(|| error("missing format before end_header"))?;
+                return Ok(Header {
+                    format,
+                    elements,
+                    lines: number,
+                });
+            }
+            _ => return Err(error("unknown header statement")),
+        }
+    }
+
+    unreachable!()
+}
+
+/// Position of the vertex index list among the face properties
+fn face_list(faces: &Element, path: &Path) -> Result<usize, ImportError> {
+    let list = faces
+        .properties
+        .iter()
+        .position(|p| p.name == "vertex_indices" || p.name == "vertex_index")
+        .ok_or_else(|| ImportError::invalid(path, "face element has no vertex_indices list"))?;
+
+    match faces.properties[list].kind {
+        Kind::List { item, .. } if item.is_integer() => Ok(list),
+        Kind::List { .. } => Err(ImportError::invalid(
+            path,
+            "vertex_indices must be a list of integers",
+        )),
+        Kind::Scalar(_) => Err(ImportError::invalid(path, "vertex_indices is not a list")),
+    }
+}
+
+/// Where the vertex properties sit in a row
+struct VertexLayout {
+    position: [usize; 3],
+    normal: Option<[usize; 3]>,
+    /// Color properties and the factor mapping them to [0, 1], `None` for
+    /// float colors which are already linear
+    color: Option<([usize; 3], Option<f32>)>,
+}
+
+impl VertexLayout {
+    fn new(element: &Element, path: &Path) -> Result<VertexLayout, ImportError> {
+        if let Some(p) = element
+            .properties
+            .iter()
+            .find(|p| matches!(p.kind, Kind::List { .. }))
+        {
+            return Err(ImportError::invalid(
+                path,
+                format!("list property `{}` on vertices is not supported", p.name),
+            ));
+        }
+
+        let find = |name: &str| element.properties.iter().position(|p| p.name == name);
+        let find3 = |names: [&str; 3]| -> Result<Option<[usize; 3]>, ImportError> {
+            match names.map(find) {
+                [Some(a), Some(b), Some(c)] => Ok(Some([a, b, c])),
+                [None, None, None] => Ok(None),
+                _ => Err(ImportError::invalid(
+                    path,
+                    format!("vertex has only some of {}", names.join(", ")),
+                )),
+            }
+        };
+
+        let position = find3(["x", "y", "z"])?
+            .ok_or_else(|| ImportError::invalid(path, "vertex has no x, y, z position"))?;
+        let normal = find3(["nx", "ny", "nz"])?;
+
+        let color = match find3(["red", "green", "blue"])? {
+            Some(color) => {
+                let Kind::Scalar(ty) = element.properties[color[0]].kind else {
+                    unreachable!()
+                };
+                let scale = match ty {
+                    Scalar::U8 => Some(1.0 / 255.0),
+                    Scalar::U16 => Some(1.0 / 65535.0),
+                    Scalar::F32 | Scalar::F64 => None,
+                    _ => {
+                        return Err(ImportError::invalid(
+                            path,
+                            "vertex colors must be unsigned integers or floats",
+                        ));
+                    }
+                };
+                Some((color, scale))
+            }
+            None => None,
+        };
+
+        Ok(VertexLayout {
+            position,
+            normal,
+            color,
+        })
+    }
+
+    fn push(&self, row: &[f64], mesh: &mut Mesh) {
+        let get = |[a, b, c]: [usize; 3]| vec3(row[a] as f32, row[b] as f32, row[c] as f32);
+
+        mesh.positions.push(get(self.position));
+        if let Some(normal) = self.normal {
+            mesh.normals.push(get(normal));
+        }
+        if let Some((color, scale)) = self.color {
+            let color = get(color);
+            mesh.colors.push(match scale {
+                Some(scale) => (color * scale).map(srgb_to_linear),
+                None => color,
+            });
+        }
+    }
+}
+
+#[inline(always)]
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Reader for the element data after the header
+struct Body<'a, R> {
+    reader: R,
+    path: &'a Path,
+    format: Format,
+
+    /// Current line of an ASCII body
+    line: usize,
+    /// Bytes read from a binary body
+    offset: usize,
+
+    /// Current line of an ASCII body and how much of it was consumed
+    text: String,
+    pos: usize,
+}
+
+impl<R: BufRead> Body<'_, R> {
+    /// Reads the next row of `element`, scalars in property order and the
+    /// items of a list after its length
+    fn read_row(&mut self, element: &Element, row: &mut Vec<f64>) -> Result<(), ImportError> {
+        row.clear();
+        for property in &element.properties {
+            match property.kind {
+                Kind::Scalar(ty) => row.push(self.value(ty)?),
+                Kind::List { count, item } => {
+                    let n = self.value(count)?;
+                    if n < 0.0 {
+                        return Err(self.error(format!("negative length for `{}`", property.name)));
+                    }
+                    row.push(n);
+                    for _ in 0..n as usize {
+                        row.push(self.value(item)?);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn value(&mut self, ty: Scalar) -> Result<f64, ImportError> {
+        let big_endian = match self.format {
+            Format::Ascii => return self.ascii_value(),
+            Format::LittleEndian => false,
+            Format::BigEndian => true,
+        };
+
+        let mut buf = [0u8; 8];
+        let bytes = &mut buf[..ty.size()];
+        if let Err(err) = self.reader.read_exact(bytes) {
+            return Err(match err.kind() {
+                io::ErrorKind::UnexpectedEof => self.error("unexpected end of file"),
+                _ => ImportError::io(self.path, err),
+            });
+        }
+        self.offset += ty.size();
+        if big_endian {
+            bytes.reverse();
+        }
+
+        let b = buf;
+        Ok(match ty {
+            Scalar::I8 => b[0] as i8 as f64,
+            Scalar::U8 => b[0] as f64,
+            Scalar::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+            Scalar::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+            Scalar::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::F64 => f64::from_le_bytes(b),
+        })
+    }
+
+    fn ascii_value(&mut self) -> Result<f64, ImportError> {
+        loop {
+            let rest = &self.text[self.pos..];
+            let token = rest.trim_start();
+            if token.is_empty() {
+                self.text.clear();
+                self.pos = 0;
+                let read = self
+                    .reader
+                    .read_line(&mut self.text)
+                    .map_err(|error| ImportError::io(self.path, error))?;
+                if read == 0 {
+                    return Err(self.error("unexpected end of file"));
+                }
+                self.line += 1;
+                continue;
+            }
+
+            let end = token.find(char::is_whitespace).unwrap_or(token.len());
+            let consumed = rest.len() - token.len() + end;
+            let token = &token[..end];
+
+            return match token.parse::<f64>() {
+                Ok(value) => {
+                    self.pos += consumed;
+                    Ok(value)
+                }
+                Err(_) => Err(self.error(format!("invalid number `{token}`"))),
+            };
+        }
+    }
+
+    /// Error at the current position, the line of an ASCII body or the byte
+    /// offset into a binary one
+    fn error(&self, message: impl Into<String>) -> ImportError {
+        match self.format {
+            Format::Ascii => ImportError::parse(self.path, self.line, message),
+            _ => ImportError::invalid(
+                self.path,
+                format!("byte {} of the data: {}", self.offset, message.into()),
+            ),
+        }
+    }
+}
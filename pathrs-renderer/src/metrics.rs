@@ -5,6 +5,7 @@ use ringbuffer::{AllocRingBuffer, RingBuffer};
 pub struct RendererMetrics {
     pub capacity: usize,
     pub passes: AllocRingBuffer<RenderPassMetrics>,
+    pub scene: SceneMetrics,
 }
 
 impl RendererMetrics {
@@ -12,6 +13,7 @@ impl RendererMetrics {
         Self {
             capacity,
             passes: AllocRingBuffer::new(capacity),
+            scene: SceneMetrics::default(),
         }
     }
 
@@ -27,14 +29,36 @@ impl RendererMetrics {
         self.passes.iter().map(|m| m.render_time.as_millis() as f32)
     }
 
+    /// All rays traced per second of each pass, shadow rays included
     pub fn rays_per_second(&self) -> impl Iterator<Item = f32> {
         self.passes
             .iter()
-            .map(|p| p.ray_count as f32 / p.render_time.as_secs_f32())
+            .map(|p| (p.ray_count + p.shadow_ray_count) as f32 / p.render_time.as_secs_f32())
     }
 
-    pub fn average_depth_histogram(&self) -> [f32; 11] {
-        let mut histogram = [0.0f32; 11];
+    /// Share of shadow rays among all rays traced over the kept passes
+    pub fn shadow_ray_share(&self) -> f32 {
+        let (shadow, total) = self.passes.iter().fold((0, 0), |(shadow, total), p| {
+            (
+                shadow + p.shadow_ray_count,
+                total + p.ray_count + p.shadow_ray_count,
+            )
+        });
+        if total == 0 {
+            0.0
+        } else {
+            shadow as f32 / total as f32
+        }
+    }
+
+    pub fn average_depth_histogram(&self) -> Vec<f32> {
+        let len = self
+            .passes
+            .iter()
+            .map(|p| p.ray_depth_histogram.len())
+            .max()
+            .unwrap_or(0);
+        let mut histogram = vec![0.0f32; len];
         let mut total_rays = 0.0;
 
         for pass in &self.passes {
@@ -54,15 +78,29 @@ impl RendererMetrics {
     }
 }
 
-#[derive(Default, Clone, Copy)]
+#[derive(Default, Clone)]
 pub struct RenderPassMetrics {
+    /// Camera and bounce rays
     pub ray_count: usize,
-    pub ray_depth_histogram: [usize; 11],
+    /// Occlusion tests towards sampled lights, counted apart from
+    /// `ray_count`
+    pub shadow_ray_count: usize,
+    /// Pixels sampled this pass, converged pixels are skipped
+    pub samples: usize,
+    /// Number of paths per length in bounces, up to the configured maximum
+    pub ray_depth_histogram: Vec<usize>,
     pub ray_depth_histogram_count: usize,
     pub render_time: Duration,
 }
 
 impl RenderPassMetrics {
+    pub fn new(max_depth: usize) -> Self {
+        Self {
+            ray_depth_histogram: vec![0; max_depth + 1],
+            ..Default::default()
+        }
+    }
+
     #[inline(always)]
     pub fn add_depth(&mut self, depth: usize) {
         self.ray_depth_histogram[depth] += 1;
@@ -71,9 +109,15 @@ impl RenderPassMetrics {
 
     pub fn combine(&mut self, other: &Self) {
         self.ray_count += other.ray_count;
+        self.shadow_ray_count += other.shadow_ray_count;
+        self.samples += other.samples;
 
-        for i in 0..self.ray_depth_histogram.len() {
-            self.ray_depth_histogram[i] += other.ray_depth_histogram[i];
+        if self.ray_depth_histogram.len() < other.ray_depth_histogram.len() {
+            self.ray_depth_histogram
+                .resize(other.ray_depth_histogram.len(), 0);
+        }
+        for (i, &v) in other.ray_depth_histogram.iter().enumerate() {
+            self.ray_depth_histogram[i] += v;
         }
         self.ray_depth_histogram_count += other.ray_depth_histogram_count;
     }
@@ -86,3 +130,36 @@ impl RenderPassMetrics {
         total
     }
 }
+
+#[derive(Default, Clone, Copy)]
+pub struct BvhMetrics {
+    pub build_time: Duration,
+    pub primitives: usize,
+    pub nodes: usize,
+    pub leaves: usize,
+    pub depth: usize,
+    /// Nodes of the eight-wide BVH used for SIMD traversal
+    pub wide_nodes: usize,
+}
+
+impl std::fmt::Display for BvhMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} primitives, {} nodes, {} leaves, depth {}",
+            self.primitives, self.nodes, self.leaves, self.depth
+        )?;
+        if self.wide_nodes > 0 {
+            write!(f, ", {} BVH8 nodes", self.wide_nodes)?;
+        }
+        write!(f, ", built in {:.2?}", self.build_time)
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct SceneMetrics {
+    pub triangle_bvh: BvhMetrics,
+    pub sphere_bvh: BvhMetrics,
+    /// Top level BVH over the instances, their shared meshes have their own
+    pub instance_bvh: BvhMetrics,
+}
@@ -1,87 +1,328 @@
+use std::{iter, sync::Mutex, thread};
+
 use fastrand::Rng;
-use glam::{Vec3, Vec4, vec3};
+use glam::Vec3;
 
-use crate::{Ray, camera::Camera, metrics::RenderPassMetrics, scene::Scene};
+use crate::{
+    Adaptive, Bounces, HitRecord, Primitive, Ray, RenderSettings,
+    camera::Camera,
+    film::{AovPixel, FirstHit, Pixel},
+    light::power_heuristic,
+    material::{Lobe, Material},
+    metrics::RenderPassMetrics,
+    rng::sample_rng,
+    scene::Scene,
+};
 
 use super::Renderer;
 
-pub struct CPURenderer;
+pub struct CPURenderer {
+    threads: usize,
+    tile_rows: usize,
+    bounces: Bounces,
+    adaptive: Option<Adaptive>,
+}
 
 impl Renderer for CPURenderer {
-    fn new() -> CPURenderer {
-        CPURenderer {}
+    fn new(settings: &RenderSettings) -> CPURenderer {
+        CPURenderer {
+            threads: settings.threads.max(1),
+            tile_rows: settings.tile_rows.max(1),
+            bounces: settings.bounces,
+            adaptive: settings.adaptive,
+        }
     }
 
     fn render_pass(
         &mut self,
         camera: &Camera,
         scene: &Scene,
-        acc: &mut [Vec4],
-        rng: &mut Rng,
+        acc: &mut [Pixel],
+        aovs: &mut [AovPixel],
+        seed: u64,
+        sample: u32,
     ) -> RenderPassMetrics {
-        let mut metrics = RenderPassMetrics::default();
+        let width = camera.screen_size.x as usize;
+        if width == 0 || acc.is_empty() {
+            return RenderPassMetrics::new(self.bounces.max_depth());
+        }
 
-        let size = camera.screen_size;
-        let width = size.x as usize;
-        let height = size.y as usize;
+        let extra_samples = self.adaptive.map_or(0.0, |a| extra_sample_rate(acc, &a));
 
-        for y in 0..height {
-            for x in 0..width {
-                acc[x + y * width] += per_pixel(x, y, camera, scene, rng, &mut metrics);
-            }
+        let tile_len = width * self.tile_rows;
+        let tile_count = acc.len().div_ceil(tile_len);
+
+        // Without AOVs every tile gets an empty slice
+        let aov_tiles = aovs
+            .chunks_mut(tile_len)
+            .chain(iter::repeat_with(<&mut [AovPixel]>::default));
+        let tiles = Mutex::new(acc.chunks_mut(tile_len).zip(aov_tiles).enumerate());
+        let workers = self.threads.min(tile_count);
+
+        let pass = Pass {
+            camera,
+            scene,
+            seed,
+            sample,
+            bounces: self.bounces,
+            adaptive: self.adaptive,
+            extra_samples,
+        };
+
+        thread::scope(|s| {
+            let handles = (0..workers)
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut metrics = RenderPassMetrics::new(self.bounces.max_depth());
+
+                        loop {
+                            let Some((i, (tile, aovs))) = tiles.lock().unwrap().next() else {
+                                break;
+                            };
+
+                            render_tile(tile, aovs, i * self.tile_rows, &pass, &mut metrics);
+                        }
+
+                        metrics
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            RenderPassMetrics::combined(handles.into_iter().map(|h| h.join().unwrap()))
+        })
+    }
+}
+
+struct Pass<'a> {
+    camera: &'a Camera,
+    scene: &'a Scene,
+    seed: u64,
+    sample: u32,
+    bounces: Bounces,
+    adaptive: Option<Adaptive>,
+    /// Extra samples per unit of relative error for pixels that haven't
+    /// converged, see [`extra_sample_rate`]
+    extra_samples: f32,
+}
+
+/// Most samples one pixel takes in a single pass
+const MAX_PIXEL_SAMPLES: u32 = 8;
+
+/// Relative errors are clamped to this when handing out extra samples, so a
+/// few pixels far from converging can't take all of them
+const MAX_ERROR: f32 = 1.0;
+
+/// Under adaptive sampling every converged pixel frees one sample per pass.
+/// The freed samples go to the pixels that have enough samples to trust
+/// their error estimate, in proportion to that error, so a pass costs about
+/// as much as one sample for every pixel. Returns the extra samples per
+/// unit of error.
+fn extra_sample_rate(acc: &[Pixel], adaptive: &Adaptive) -> f32 {
+    let mut freed = 0;
+    let mut error = 0.0;
+    for pixel in acc {
+        if pixel.converged(adaptive) {
+            freed += 1;
+        } else if pixel.samples() >= adaptive.min_samples {
+            error += pixel.relative_error().min(MAX_ERROR);
         }
+    }
+
+    if error > 0.0 {
+        freed as f32 / error
+    } else {
+        0.0
+    }
+}
+
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+fn render_tile(
+    tile: &mut [Pixel],
+    aovs: &mut [AovPixel],
+    y0: usize,
+    pass: &Pass,
+    metrics: &mut RenderPassMetrics,
+) {
+    let width = pass.camera.screen_size.x as usize;
 
-        metrics
+    // Fractions of extra samples carry over to the next pixel of the tile
+    let mut carry = 0.0;
+
+    for (i, pixel) in tile.iter_mut().enumerate() {
+        let samples = match pass.adaptive {
+            Some(a) if pixel.converged(&a) => continue,
+            Some(a) if pixel.samples() >= a.min_samples => {
+                let share = pixel.relative_error().min(MAX_ERROR) * pass.extra_samples + carry;
+                let extra = share.floor();
+                carry = share - extra;
+                1 + (extra as u32).min(MAX_PIXEL_SAMPLES - 1)
+            }
+            _ => 1,
+        };
+
+        let (x, y) = (i % width, y0 + i / width);
+        for extra in 0..samples {
+            // Extra samples of a pass get streams no later pass uses
+            let sample = (extra as u64) << 32 | pass.sample as u64;
+            let mut rng = sample_rng(pass.seed, (x + y * width) as u64, sample);
+            let mut first_hit = None;
+            pixel.add(per_pixel(x, y, pass, &mut rng, &mut first_hit, metrics));
+            metrics.samples += 1;
+
+            if let Some(aov) = aovs.get_mut(i) {
+                aov.add(first_hit.as_ref());
+            }
+        }
     }
 }
 
-const MAX_DEPTH: usize = 10;
+/// Shortens shadow rays so they don't hit the light they were aimed at
+const SHADOW_EPSILON: f32 = 1e-3;
+
+/// Upper bound on the Russian roulette survival probability, so paths
+/// through bright or lossless materials still terminate eventually
+const MAX_SURVIVAL: f32 = 0.95;
 
 #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
 fn per_pixel(
     x: usize,
     y: usize,
-    camera: &Camera,
-    scene: &Scene,
-    rng: &mut fastrand::Rng,
+    pass: &Pass,
+    rng: &mut Rng,
+    first_hit: &mut Option<FirstHit>,
     metrics: &mut RenderPassMetrics,
-) -> Vec4 {
-    let ray = camera.get_ray(x, y);
-    trace_ray(&ray, scene, 0, rng, metrics).extend(1.0)
+) -> Vec3 {
+    let ray = pass.camera.get_ray(x, y, rng);
+    trace_path(ray, pass.scene, &pass.bounces, rng, first_hit, metrics)
 }
 
+/// Radiance arriving along `ray`, the surface it hits is stored in
+/// `first_hit` for the AOVs
 #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
-fn trace_ray(
-    ray: &Ray,
+fn trace_path(
+    mut ray: Ray,
     scene: &Scene,
-    depth: usize,
-    rng: &mut fastrand::Rng,
+    bounces: &Bounces,
+    rng: &mut Rng,
+    first_hit: &mut Option<FirstHit>,
     metrics: &mut RenderPassMetrics,
 ) -> Vec3 {
-    metrics.ray_count += 1;
+    let mut radiance = Vec3::ZERO;
+    let mut throughput = Vec3::ONE;
 
-    if depth == MAX_DEPTH {
-        metrics.add_depth(depth);
-        return Vec3::ZERO;
-    }
+    // Density the last bounce sampled `ray` with, `None` for camera rays and
+    // delta bounces which light sampling can't produce
+    let mut bsdf_pdf = None;
+
+    let mut depth = 0;
+    let (mut diffuse, mut specular, mut transmission) = (0, 0, 0);
+
+    loop {
+        metrics.ray_count += 1;
+
+        let Some(hit) = scene.closest_hit(&ray, 0.0001, f32::MAX) else {
+            let mut emitted = scene.environment.radiance(ray.direction);
+            if let Some(pdf) = bsdf_pdf {
+                emitted *= power_heuristic(pdf, scene.environment_pdf(ray.direction));
+            }
+            radiance += throughput * emitted;
+            break;
+        };
 
-    if let Some(hit) = scene.closest_hit(ray, 0.0001, f32::MAX) {
         let mat = scene.materials[hit.material as usize];
-        let emitted = mat.emitted(ray, &hit);
 
-        let scattered = if let Some((scattered, attenuation)) = mat.scatter(ray, &hit, rng) {
-            attenuation * trace_ray(&scattered, scene, depth + 1, rng, metrics)
-        } else {
-            metrics.add_depth(depth);
-            Vec3::ZERO
+        if depth == 0 {
+            *first_hit = Some(FirstHit {
+                albedo: mat.albedo(&hit),
+                normal: hit.normal,
+                depth: hit.t * ray.direction.length(),
+                pos: hit.pos,
+                material: hit.material,
+                primitive: match hit.primitive {
+                    Primitive::Triangle(i) => i,
+                    Primitive::Sphere(i) => scene.triangles().len() as u32 + i,
+                    // One id per instance, its triangles share it
+                    Primitive::Instance { instance, .. } => {
+                        (scene.triangles().len() + scene.spheres().len()) as u32 + instance
+                    }
+                },
+            });
+        }
+
+        let mut emitted = mat.emitted(&ray, &hit);
+        if let Some(pdf) = bsdf_pdf
+            && emitted != Vec3::ZERO
+        {
+            emitted *= power_heuristic(pdf, scene.light_pdf(ray.origin, ray.time, &hit));
+        }
+        radiance += throughput * emitted;
+
+        let wo = -ray.direction.normalize();
+
+        if !mat.is_delta() {
+            radiance += throughput * sample_direct(&hit, wo, ray.time, &mat, scene, rng, metrics);
+        }
+
+        let Some(sample) = mat.sample(wo, &hit, rng) else {
+            break;
         };
 
-        return emitted + scattered;
-    } else {
-        metrics.add_depth(depth);
+        let (count, max) = match sample.lobe {
+            Lobe::Diffuse => (&mut diffuse, bounces.diffuse),
+            Lobe::Specular => (&mut specular, bounces.specular),
+            Lobe::Transmission => (&mut transmission, bounces.transmission),
+        };
+        if *count == max {
+            break;
+        }
+        *count += 1;
+        depth += 1;
+
+        throughput *= sample.weight;
+
+        if depth > bounces.roulette_depth {
+            let survival = throughput.max_element().min(MAX_SURVIVAL);
+            if rng.f32() >= survival {
+                break;
+            }
+            throughput /= survival;
+        }
+
+        ray = Ray::new(hit.pos, sample.wi, ray.time);
+        bsdf_pdf = (!sample.delta).then_some(sample.pdf);
+    }
+
+    metrics.add_depth(depth as usize);
+    radiance
+}
+
+/// Next-event estimation: connects `hit` to a point on a light at `time`,
+/// weighted against the BSDF having sampled the same direction
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+fn sample_direct(
+    hit: &HitRecord,
+    wo: Vec3,
+    time: f32,
+    mat: &Material,
+    scene: &Scene,
+    rng: &mut Rng,
+    metrics: &mut RenderPassMetrics,
+) -> Vec3 {
+    let Some(light) = scene.sample_light(hit.pos, time, rng) else {
+        return Vec3::ZERO;
+    };
+
+    let f = mat.eval(wo, light.dir, hit);
+    if f == Vec3::ZERO || light.pdf <= 0.0 {
+        return Vec3::ZERO;
+    }
+
+    metrics.shadow_ray_count += 1;
+    let shadow = Ray::new(hit.pos, light.dir, time);
+    if scene.occluded(&shadow, 0.0001, light.dist * (1.0 - SHADOW_EPSILON)) {
+        return Vec3::ZERO;
     }
 
-    let dir = ray.direction.normalize();
-    let a = 0.5 * (dir.y + 1.0);
-    (1.0 - a) * vec3(1.0, 1.0, 1.0) + a * vec3(0.5, 0.7, 1.0)
+    let weight = power_heuristic(light.pdf, mat.pdf(wo, light.dir, hit));
+    f * light.emitted * weight / light.pdf
 }
@@ -1,20 +1,29 @@
-use fastrand::Rng;
-use glam::Vec4;
-
-use crate::{camera::Camera, metrics::RenderPassMetrics, scene::Scene};
+use crate::{
+    RenderSettings,
+    camera::Camera,
+    film::{AovPixel, Pixel},
+    metrics::RenderPassMetrics,
+    scene::Scene,
+};
 
 mod cpu_renderer;
 
 pub use cpu_renderer::CPURenderer;
 
 pub trait Renderer: Sync + Send + 'static {
-    fn new() -> Self;
+    fn new(settings: &RenderSettings) -> Self;
 
+    /// Adds one sample to every pixel of `acc` that hasn't converged yet,
+    /// and its first hit to `aovs` unless that is empty. All randomness is
+    /// derived from `seed`, the pixel and the `sample` index so passes are
+    /// reproducible.
     fn render_pass(
         &mut self,
         camera: &Camera,
         scene: &Scene,
-        acc: &mut [Vec4],
-        rng: &mut Rng,
+        acc: &mut [Pixel],
+        aovs: &mut [AovPixel],
+        seed: u64,
+        sample: u32,
     ) -> RenderPassMetrics;
 }
@@ -0,0 +1,21 @@
+use fastrand::Rng;
+
+/// Returns the random stream for one sample of one pixel.
+///
+/// The wyrand generator behind `fastrand::Rng` is counter based: every output
+/// is a hash of the seed advanced by a fixed increment per draw. Keying that
+/// seed on (render seed, pixel, sample index) makes each sample independent
+/// of thread count and tile order, so equal seeds give bit-identical images.
+#[inline(always)]
+pub fn sample_rng(seed: u64, pixel: u64, sample: u64) -> Rng {
+    Rng::with_seed(mix(mix(mix(seed) ^ pixel) ^ sample))
+}
+
+/// SplitMix64 finalizer
+#[inline(always)]
+fn mix(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
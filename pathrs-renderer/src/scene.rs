@@ -1,9 +1,20 @@
-use glam::vec3;
+use std::{fmt, ops::Range, path::PathBuf, time::Instant};
 
+use fastrand::Rng;
+use glam::{Affine3A, Vec3, vec3};
+
+#[cfg(feature = "simd")]
+use crate::geometry::{Bvh8, SpheresSIMD, TrianglesSIMD};
 use crate::{
     HitRecord, Ray,
-    geometry::{Sphere, Spheres, SpheresSIMD, Triangle, Triangles, TrianglesSIMD},
+    camera::CameraSettings,
+    environment::Environment,
+    geometry::{Bvh, Sphere, Spheres, Triangle, Triangles},
+    instance::{self, Instance, MeshHit, SharedMesh},
+    light::{LightSample, Lights},
     material::{Dielectric, DiffuseLight, Lambertian, Material, Metal},
+    mesh::{Mesh, Transform},
+    metrics::SceneMetrics,
 };
 
 #[cfg(feature = "simd")]
@@ -11,166 +22,983 @@ use crate::simd::*;
 
 #[derive(Default, Clone)]
 pub struct Scene {
+    pub camera: CameraSettings,
+
     pub materials: Vec<Material>,
+    /// Name of each material, unique within the scene
+    pub material_names: Vec<String>,
 
     triangles: Triangles,
+    /// Named ranges of `triangles`, in order and covering all of them
+    groups: Vec<TriangleGroup>,
     spheres: Spheres,
 
+    triangle_bvh: Bvh,
+    sphere_bvh: Bvh,
+
+    /// Bottom level of instancing, placed by `instances`
+    meshes: Vec<SharedMesh>,
+    instances: Vec<Instance>,
+    instance_bvh: Bvh,
+
+    lights: Lights,
+    pub environment: Environment,
+
     #[cfg(feature = "simd")]
     spheres_simd: SpheresSIMD,
     #[cfg(feature = "simd")]
     triangles_simd: TrianglesSIMD,
+    #[cfg(feature = "simd")]
+    triangle_bvh8: Bvh8,
+    #[cfg(feature = "simd")]
+    sphere_bvh8: Bvh8,
+    #[cfg(feature = "simd")]
+    instance_bvh8: Bvh8,
+
+    pub metrics: SceneMetrics,
+
+    /// Structures [`Scene::edit`] invalidated, see [`Scene::rebuild`]
+    stale: Stale,
 }
 
-impl Scene {
-    pub fn add_triangle(&mut self, tri: Triangle) {
-        self.triangles.push(tri);
+/// Triangles added together, an object of a scene file or a mesh
+#[derive(Clone, Debug)]
+pub struct TriangleGroup {
+    pub name: String,
+    pub triangles: Range<usize>,
+    /// Mesh file import that added the group, so saving can refer to the
+    /// file. Cleared once the group is edited.
+    pub source: Option<GroupSource>,
+}
+
+/// Mesh file imported into a scene and how it was placed
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeshSource {
+    /// Path as written in the scene file
+    pub path: PathBuf,
+    pub transform: Transform,
+    pub transform_end: Option<Transform>,
+    /// Material replacing the file's own materials
+    pub material: Option<u32>,
+}
+
+/// The part of a [`MeshSource`] import one group is, an import adds one
+/// group per object in the file
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroupSource {
+    pub mesh: MeshSource,
+    pub part: usize,
+    pub parts: usize,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Stale {
+    triangles: bool,
+    spheres: bool,
+    instances: bool,
+    lights: bool,
+}
+
+/// Change to a built scene, applied with [`Scene::edit`]
+#[derive(Clone)]
+pub enum SceneEdit {
+    /// Replaces the parameters of material `index`
+    SetMaterial {
+        index: u32,
+        material: Material,
+    },
+    AddSphere(Sphere),
+    /// Replaces sphere `index`, moving or resizing it
+    SetSphere {
+        index: usize,
+        sphere: Sphere,
+    },
+    /// Removes sphere `index`, the ones after it move down one index
+    RemoveSphere(usize),
+    /// Adds the triangles as a new group
+    AddTriangles {
+        name: String,
+        triangles: Vec<Triangle>,
+    },
+    /// Moves the triangles of group `index` by `offset`
+    MoveTriangles {
+        index: usize,
+        offset: Vec3,
+    },
+    /// Removes group `index` and its triangles, later groups move down one
+    /// index
+    RemoveTriangles(usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum EditError {
+    UnknownMaterial(u32),
+    UnknownSphere(usize),
+    UnknownGroup(usize),
+    UnknownMesh(u32),
+    InvalidRadius(f32),
+    /// Moving triangles got a different number of end positions
+    MotionMismatch {
+        triangles: usize,
+        end: usize,
+    },
+    SingularTransform,
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EditError::UnknownMaterial(index) => write!(f, "no material {index}"),
+            EditError::UnknownSphere(index) => write!(f, "no sphere {index}"),
+            EditError::UnknownGroup(index) => write!(f, "no triangle group {index}"),
+            EditError::UnknownMesh(index) => write!(f, "no shared mesh {index}"),
+            EditError::InvalidRadius(radius) => {
+                write!(f, "sphere radius {radius} must be positive")
+            }
+            EditError::MotionMismatch { triangles, end } => {
+                write!(f, "{end} end positions for {triangles} moving triangles")
+            }
+            EditError::SingularTransform => write!(f, "instance transform is not invertible"),
+        }
     }
+}
+
+impl std::error::Error for EditError {}
+
+/// An acceleration structure holding a different number of primitives than
+/// the list it was built from, found by [`Scene::validate`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValidationError {
+    pub structure: String,
+    pub expected: usize,
+    pub found: usize,
+}
+
+impl ValidationError {
+    pub(crate) fn check(
+        structure: impl fmt::Display,
+        expected: usize,
+        found: usize,
+    ) -> Result<(), ValidationError> {
+        match expected == found {
+            true => Ok(()),
+            false => Err(ValidationError {
+                structure: structure.to_string(),
+                expected,
+                found,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} holds {} primitives, expected {}",
+            self.structure, self.found, self.expected
+        )
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// The parts of a scene an editor shows and changes with [`SceneEdit`]s,
+/// without the geometry of the triangles
+#[derive(Clone, Default)]
+pub struct SceneSummary {
+    pub materials: Vec<Material>,
+    pub material_names: Vec<String>,
+    pub spheres: Vec<Sphere>,
+    /// Name and triangle count of each group
+    pub groups: Vec<(String, usize)>,
+    /// Edits that failed on the way to this state of the scene, left empty
+    /// by [`Scene::summary`]
+    pub errors: Vec<String>,
+}
 
-    pub fn add_triangles(&mut self, tris: &[Triangle]) {
+impl Scene {
+    /// Adds `tris` as a new group named `name`
+    pub fn add_triangles(&mut self, name: impl Into<String>, tris: &[Triangle]) {
+        let start = self.triangles.len();
         for tri in tris {
             self.triangles.push(tri.clone());
         }
+        self.push_group(name.into(), start);
+    }
+
+    /// Adds the faces of `mesh` as a new group named `name`
+    pub fn add_mesh(&mut self, name: impl Into<String>, mesh: &Mesh) {
+        let start = self.triangles.len();
+        self.triangles.extend(mesh);
+        self.push_group(name.into(), start);
+    }
+
+    /// Adds `start` as a new group named `name`, its triangles move in
+    /// straight lines to the vertices of `end` at time one. `end` needs one
+    /// triangle per triangle of `start`.
+    pub fn add_moving_triangles(
+        &mut self,
+        name: impl Into<String>,
+        start: &[Triangle],
+        end: &[Triangle],
+    ) -> Result<(), EditError> {
+        if start.len() != end.len() {
+            return Err(EditError::MotionMismatch {
+                triangles: start.len(),
+                end: end.len(),
+            });
+        }
+        let first = self.triangles.len();
+        self.add_triangles(name, start);
+        for (i, tri) in end.iter().enumerate() {
+            self.triangles.set_motion(first + i, tri.vertices());
+        }
+        Ok(())
+    }
+
+    /// Makes group `index` move from where its triangles are at time zero to
+    /// where `end` maps them at time one
+    pub fn set_motion(&mut self, index: usize, end: Affine3A) -> Result<(), EditError> {
+        let group = self
+            .groups
+            .get(index)
+            .ok_or(EditError::UnknownGroup(index))?;
+        self.triangles
+            .set_motion_transform(group.triangles.clone(), end);
+        self.groups[index].source = None;
+        self.stale.triangles = true;
+        Ok(())
+    }
+
+    /// Records that the groups in `groups` are what importing `mesh` added
+    pub fn set_source(&mut self, groups: Range<usize>, mesh: MeshSource) {
+        let parts = groups.len();
+        for (part, group) in self.groups[groups].iter_mut().enumerate() {
+            group.source = Some(GroupSource {
+                mesh: mesh.clone(),
+                part,
+                parts,
+            });
+        }
+    }
+
+    fn push_group(&mut self, name: String, start: usize) {
+        self.groups.push(TriangleGroup {
+            name,
+            triangles: start..self.triangles.len(),
+            source: None,
+        });
     }
 
     pub fn add_sphere(&mut self, sphere: Sphere) {
         self.spheres.push(sphere);
     }
 
-    pub fn add_material<M: Into<Material>>(&mut self, mat: M) -> u32 {
+    /// Adds the faces of `mesh` as a shared mesh named `name`, returning its
+    /// index for [`Scene::add_instance`]
+    pub fn add_shared_mesh(&mut self, name: impl Into<String>, mesh: &Mesh) -> u32 {
+        let mut triangles = Triangles::default();
+        triangles.extend(mesh);
+        self.meshes.push(SharedMesh::new(name, triangles));
+        (self.meshes.len() - 1) as u32
+    }
+
+    /// Moves the triangles of the groups in `groups` into a shared mesh
+    /// named `name`, returning its index for [`Scene::add_instance`]. Later
+    /// groups move down.
+    pub fn share_groups(&mut self, groups: Range<usize>, name: impl Into<String>) -> u32 {
+        let triangles = match groups.is_empty() {
+            true => 0..0,
+            false => {
+                self.groups[groups.start].triangles.start..self.groups[groups.end - 1].triangles.end
+            }
+        };
+
+        let shared = self.triangles.split_off(triangles.clone());
+        self.groups.drain(groups.clone());
+        for group in &mut self.groups[groups.start..] {
+            group.triangles =
+                group.triangles.start - triangles.len()..group.triangles.end - triangles.len();
+        }
+        self.stale.triangles = true;
+
+        self.meshes.push(SharedMesh::new(name, shared));
+        (self.meshes.len() - 1) as u32
+    }
+
+    /// Records that shared mesh `mesh` holds the triangles of the file at
+    /// `path`
+    pub fn set_mesh_source(&mut self, mesh: u32, path: PathBuf) {
+        self.meshes[mesh as usize].source = Some(path);
+    }
+
+    /// Places shared mesh `instance.mesh`, which must exist. Its transform
+    /// must be invertible to bring rays into the mesh's space.
+    pub fn add_instance(&mut self, instance: Instance) -> Result<(), EditError> {
+        if instance.mesh as usize >= self.meshes.len() {
+            return Err(EditError::UnknownMesh(instance.mesh));
+        }
+        if !instance::is_invertible(instance.transform()) {
+            return Err(EditError::SingularTransform);
+        }
+        self.instances.push(instance);
+        self.stale.instances = true;
+        Ok(())
+    }
+
+    pub fn add_material<M: Into<Material>>(&mut self, name: impl Into<String>, mat: M) -> u32 {
         self.materials.push(mat.into());
+        self.material_names.push(name.into());
         (self.materials.len() - 1) as u32
     }
 
-    #[cfg(feature = "simd")]
+    pub fn material_index(&self, name: &str) -> Option<u32> {
+        self.material_names
+            .iter()
+            .position(|n| n == name)
+            .map(|i| i as u32)
+    }
+
+    pub fn triangles(&self) -> &Triangles {
+        &self.triangles
+    }
+
+    pub fn groups(&self) -> &[TriangleGroup] {
+        &self.groups
+    }
+
+    pub fn spheres(&self) -> &Spheres {
+        &self.spheres
+    }
+
+    pub fn meshes(&self) -> &[SharedMesh] {
+        &self.meshes
+    }
+
+    pub fn instances(&self) -> &[Instance] {
+        &self.instances
+    }
+
+    pub fn summary(&self) -> SceneSummary {
+        let spheres = &self.spheres;
+        SceneSummary {
+            materials: self.materials.clone(),
+            material_names: self.material_names.clone(),
+            spheres: (0..spheres.len())
+                .map(|i| {
+                    let center = spheres.center(i);
+                    let end = center + spheres.motion(i);
+                    Sphere::moving(center, end, spheres.radius(i), spheres.material(i))
+                })
+                .collect(),
+            groups: self
+                .groups
+                .iter()
+                .map(|g| (g.name.clone(), g.triangles.len()))
+                .collect(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Builds the acceleration structures and the light list, must be called
+    /// after adding geometry and before rendering. Fails if a structure lost
+    /// primitives, see [`Scene::validate`].
     #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
-    pub fn collect_simd(&mut self) {
-        self.triangles_simd = TrianglesSIMD::from_tris(self.triangles.clone());
-        self.spheres_simd = SpheresSIMD::from_spheres(self.spheres.clone());
+    pub fn build(&mut self) -> Result<(), ValidationError> {
+        self.stale = Stale {
+            triangles: true,
+            spheres: true,
+            instances: true,
+            lights: true,
+        };
+        self.rebuild()
+    }
+
+    /// Applies `edit`, the scene needs a [`Scene::rebuild`] before rendering
+    /// again. Nothing changes when it fails.
+    pub fn edit(&mut self, edit: SceneEdit) -> Result<(), EditError> {
+        match edit {
+            SceneEdit::SetMaterial { index, material } => {
+                let old = self
+                    .materials
+                    .get_mut(index as usize)
+                    .ok_or(EditError::UnknownMaterial(index))?;
+                // Only emission changes which primitives are lights
+                if old.emission() != Vec3::ZERO || material.emission() != Vec3::ZERO {
+                    self.stale.lights = true;
+                }
+                *old = material;
+            }
+            SceneEdit::AddSphere(sphere) => {
+                self.check_sphere(&sphere)?;
+                self.spheres.push(sphere);
+                self.stale.spheres = true;
+            }
+            SceneEdit::SetSphere { index, sphere } => {
+                self.check_sphere(&sphere)?;
+                if index >= self.spheres.len() {
+                    return Err(EditError::UnknownSphere(index));
+                }
+                self.spheres.set(index, sphere);
+                self.stale.spheres = true;
+            }
+            SceneEdit::RemoveSphere(index) => {
+                if index >= self.spheres.len() {
+                    return Err(EditError::UnknownSphere(index));
+                }
+                self.spheres.remove(index);
+                self.stale.spheres = true;
+            }
+            SceneEdit::AddTriangles { name, triangles } => {
+                if let Some(tri) = triangles
+                    .iter()
+                    .find(|tri| tri.material() as usize >= self.materials.len())
+                {
+                    return Err(EditError::UnknownMaterial(tri.material()));
+                }
+                self.add_triangles(name, &triangles);
+                self.stale.triangles = true;
+            }
+            SceneEdit::MoveTriangles { index, offset } => {
+                let group = self
+                    .groups
+                    .get_mut(index)
+                    .ok_or(EditError::UnknownGroup(index))?;
+                self.triangles.translate(group.triangles.clone(), offset);
+                group.source = None;
+                self.stale.triangles = true;
+            }
+            SceneEdit::RemoveTriangles(index) => {
+                if index >= self.groups.len() {
+                    return Err(EditError::UnknownGroup(index));
+                }
+                let removed = self.groups.remove(index).triangles;
+                self.triangles.remove(removed.clone());
+                for group in &mut self.groups[index..] {
+                    group.triangles =
+                        group.triangles.start - removed.len()..group.triangles.end - removed.len();
+                }
+                self.stale.triangles = true;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_sphere(&self, sphere: &Sphere) -> Result<(), EditError> {
+        if sphere.material() as usize >= self.materials.len() {
+            return Err(EditError::UnknownMaterial(sphere.material()));
+        }
+        if sphere.radius().is_nan() || sphere.radius() <= 0.0 {
+            return Err(EditError::InvalidRadius(sphere.radius()));
+        }
+        Ok(())
     }
 
+    /// Rebuilds what edits since the last build invalidated, the light list
+    /// follows any change to the geometry since it refers to primitives by
+    /// index. Fails like [`Scene::build`].
     #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
-    pub fn closest_hit(&self, ray: &Ray, tmin: f32, tmax: f32) -> Option<HitRecord> {
-        let mut res = None;
-        let mut tmax = tmax;
+    pub fn rebuild(&mut self) -> Result<(), ValidationError> {
+        let stale = std::mem::take(&mut self.stale);
+
+        if stale.triangles {
+            self.build_triangles();
+        }
+        if stale.spheres {
+            self.build_spheres();
+        }
+        if stale.instances {
+            self.build_instances();
+        }
+        if stale.lights || stale.triangles || stale.spheres || stale.instances {
+            self.lights = Lights::collect(
+                &self.materials,
+                &self.triangles,
+                &self.spheres,
+                &self.instances,
+                &self.meshes,
+            );
+        }
+
+        self.validate()
+    }
+
+    /// Checks that the BVHs hold as many primitives as the lists they were
+    /// built from, and with the `simd` feature that the packets hold every
+    /// triangle and sphere once with its data, so no geometry goes missing
+    /// depending on build features. Only meaningful
+    /// on a built scene, [`Scene::rebuild`] returns its result after every
+    /// build.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let triangles = self.triangles.len();
+        let spheres = self.spheres.len();
+        ValidationError::check(
+            "triangle BVH",
+            triangles,
+            self.triangle_bvh.primitive_count(),
+        )?;
+        ValidationError::check("sphere BVH", spheres, self.sphere_bvh.primitive_count())?;
+        ValidationError::check(
+            "instance BVH",
+            self.instances.len(),
+            self.instance_bvh.primitive_count(),
+        )?;
 
         #[cfg(feature = "simd")]
-        let r_o = Vec3x8::from(ray.origin);
+        {
+            ValidationError::check(
+                "packed triangles",
+                triangles,
+                self.triangles_simd.triangle_count(&self.triangles),
+            )?;
+            ValidationError::check(
+                "packed spheres",
+                spheres,
+                self.spheres_simd.sphere_count(&self.spheres),
+            )?;
+        }
+
+        self.meshes.iter().try_for_each(SharedMesh::validate)
+    }
+
+    fn build_triangles(&mut self) {
+        let start = Instant::now();
+        self.triangle_bvh = Bvh::build(&self.triangles.bounds());
         #[cfg(feature = "simd")]
-        let r_d = Vec3x8::from(ray.direction);
+        {
+            self.triangle_bvh8 = Bvh8::from_bvh(&self.triangle_bvh);
+        }
+        self.metrics.triangle_bvh = self
+            .triangle_bvh
+            .metrics(self.triangles.len(), start.elapsed());
 
-        #[cfg(not(feature = "simd"))]
-        if let Some(hit) = self.spheres.intersect_spheres(ray, tmin, tmax) {
-            tmax = hit.t;
-            res = Some(hit);
+        // Packs the triangles in BVH leaf order, one packet per leaf
+        #[cfg(feature = "simd")]
+        {
+            self.metrics.triangle_bvh.wide_nodes = self.triangle_bvh8.len();
+            self.triangles_simd =
+                TrianglesSIMD::from_tris(&self.triangles, &self.triangle_bvh.indices);
         }
+    }
 
+    fn build_spheres(&mut self) {
+        let start = Instant::now();
+        self.sphere_bvh = Bvh::build(&self.spheres.bounds());
         #[cfg(feature = "simd")]
-        if let Some(hit) = self.spheres_simd.intersect(ray, tmin, tmax, r_o, r_d) {
-            tmax = hit.t;
-            res = Some(hit);
+        {
+            self.sphere_bvh8 = Bvh8::from_bvh(&self.sphere_bvh);
         }
+        self.metrics.sphere_bvh = self.sphere_bvh.metrics(self.spheres.len(), start.elapsed());
 
-        #[cfg(not(feature = "simd"))]
-        if let Some(hit) = self.triangles.intersect(ray, tmin, tmax) {
-            res = Some(hit);
+        #[cfg(feature = "simd")]
+        {
+            self.metrics.sphere_bvh.wide_nodes = self.sphere_bvh8.len();
+            self.spheres_simd = SpheresSIMD::from_spheres(&self.spheres, &self.sphere_bvh.indices);
         }
+    }
 
+    fn build_instances(&mut self) {
+        let start = Instant::now();
+        let bounds: Vec<_> = self
+            .instances
+            .iter()
+            .map(|instance| instance.bounds(&self.meshes[instance.mesh as usize]))
+            .collect();
+        self.instance_bvh = Bvh::build(&bounds);
         #[cfg(feature = "simd")]
-        if let Some(hit) = self.triangles_simd.intersect(ray, tmin, tmax, r_o, r_d) {
-            res = Some(hit);
+        {
+            self.instance_bvh8 = Bvh8::from_bvh(&self.instance_bvh);
+        }
+        self.metrics.instance_bvh = self
+            .instance_bvh
+            .metrics(self.instances.len(), start.elapsed());
+
+        #[cfg(feature = "simd")]
+        {
+            self.metrics.instance_bvh.wide_nodes = self.instance_bvh8.len();
+        }
+    }
+
+    /// Closest hit among the instances in `leaf`, a range of the instance
+    /// BVH's indices. Returns the new `tmax`.
+    #[inline(always)]
+    fn intersect_instances(
+        &self,
+        leaf: Range<usize>,
+        ray: &Ray,
+        tmin: f32,
+        tmax: f32,
+        closest: &mut Option<(u32, MeshHit)>,
+    ) -> f32 {
+        let mut tmax = tmax;
+        for &i in &self.instance_bvh.indices[leaf] {
+            if i == Bvh::PADDING {
+                continue;
+            }
+            let instance = &self.instances[i as usize];
+            let mesh = &self.meshes[instance.mesh as usize];
+            if let Some((t, hit)) = instance.intersect(mesh, ray, tmin, tmax) {
+                tmax = t;
+                *closest = Some((i, hit));
+            }
+        }
+        tmax
+    }
+
+    /// Whether any instance in `leaf`, a range of the instance BVH's
+    /// indices, is hit between `tmin` and `tmax`
+    #[inline(always)]
+    fn instances_occlude(&self, leaf: Range<usize>, ray: &Ray, tmin: f32, tmax: f32) -> bool {
+        self.instance_bvh.indices[leaf].iter().any(|&i| {
+            if i == Bvh::PADDING {
+                return false;
+            }
+            let instance = &self.instances[i as usize];
+            instance.occluded(&self.meshes[instance.mesh as usize], ray, tmin, tmax)
+        })
+    }
+
+    #[inline(always)]
+    fn instance_hit_record(&self, index: u32, hit: MeshHit, ray: &Ray, t: f32) -> HitRecord {
+        let instance = &self.instances[index as usize];
+        let mesh = &self.meshes[instance.mesh as usize];
+        instance.hit_record(index, mesh, hit, ray, t)
+    }
+
+    /// Probability of [`Scene::sample_light`] sampling the environment
+    /// instead of an emissive primitive
+    #[inline(always)]
+    fn environment_selection(&self) -> f32 {
+        match (self.environment.is_sampled(), self.lights.is_empty()) {
+            (false, _) => 0.0,
+            (true, true) => 1.0,
+            (true, false) => 0.5,
         }
+    }
+
+    /// Picks a point on an emissive primitive at `time` or a direction
+    /// towards the environment to connect `p` to
+    #[inline(always)]
+    pub fn sample_light(&self, p: Vec3, time: f32, rng: &mut Rng) -> Option<LightSample> {
+        let env = self.environment_selection();
+
+        let (sample, selection) = match env {
+            0.0 => (self.lights.sample(p, time, rng), 1.0),
+            1.0 => (self.environment.sample(rng), 1.0),
+            _ if rng.f32() < env => (self.environment.sample(rng), env),
+            _ => (self.lights.sample(p, time, rng), 1.0 - env),
+        };
+
+        sample.map(|mut s| {
+            s.pdf *= selection;
+            s
+        })
+    }
+
+    /// Density of [`Scene::sample_light`] picking the point `hit` at `time`
+    /// from `p`
+    #[inline(always)]
+    pub fn light_pdf(&self, p: Vec3, time: f32, hit: &HitRecord) -> f32 {
+        self.lights.pdf(p, time, hit) * (1.0 - self.environment_selection())
+    }
+
+    /// Density of [`Scene::sample_light`] picking the environment along `dir`
+    #[inline(always)]
+    pub fn environment_pdf(&self, dir: Vec3) -> f32 {
+        self.environment.pdf(dir) * self.environment_selection()
+    }
 
-        res
+    #[cfg(not(feature = "simd"))]
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub fn closest_hit(&self, ray: &Ray, tmin: f32, tmax: f32) -> Option<HitRecord> {
+        let mut closest = tmax;
+        let mut sphere = None;
+        let mut triangle = None;
+
+        self.sphere_bvh
+            .traverse(ray, tmin, closest, |first, count, tmax| {
+                let mut tmax = tmax;
+                for &i in &self.sphere_bvh.indices[first..first + count] {
+                    if let Some(t) = self.spheres.intersect(i as usize, ray, tmin, tmax) {
+                        tmax = t;
+                        sphere = Some(i as usize);
+                    }
+                }
+                closest = tmax;
+                tmax
+            });
+
+        self.triangle_bvh
+            .traverse(ray, tmin, closest, |first, count, tmax| {
+                let mut tmax = tmax;
+                for &i in &self.triangle_bvh.indices[first..first + count] {
+                    if let Some(t) = self.triangles.intersect(i as usize, ray, tmin, tmax) {
+                        tmax = t;
+                        triangle = Some(i as usize);
+                    }
+                }
+                closest = tmax;
+                tmax
+            });
+
+        let mut instance = None;
+        self.instance_bvh
+            .traverse(ray, tmin, closest, |first, count, tmax| {
+                closest =
+                    self.intersect_instances(first..first + count, ray, tmin, tmax, &mut instance);
+                closest
+            });
+
+        if let Some((i, hit)) = instance {
+            Some(self.instance_hit_record(i, hit, ray, closest))
+        } else if let Some(i) = triangle {
+            Some(self.triangles.hit_record(i, ray, closest))
+        } else {
+            sphere.map(|i| self.spheres.hit_record(i, ray, closest))
+        }
+    }
+
+    #[cfg(feature = "simd")]
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub fn closest_hit(&self, ray: &Ray, tmin: f32, tmax: f32) -> Option<HitRecord> {
+        let r_o = Vec3x8::from(ray.origin);
+        let r_d = Vec3x8::from(ray.direction);
+
+        let mut closest = tmax;
+        let mut sphere = None;
+        let mut triangle = None;
+
+        self.sphere_bvh8
+            .traverse(ray, tmin, closest, |packet, tmax| {
+                if let Some((t, slot)) = self
+                    .spheres_simd
+                    .intersect(packet, ray, tmin, tmax, r_o, r_d)
+                {
+                    sphere = Some(slot);
+                    closest = t;
+                }
+                closest
+            });
+
+        self.triangle_bvh8
+            .traverse(ray, tmin, closest, |packet, tmax| {
+                if let Some((t, slot, det)) = self
+                    .triangles_simd
+                    .intersect(packet, tmin, tmax, r_o, r_d, ray.time)
+                {
+                    triangle = Some((slot, det));
+                    closest = t;
+                }
+                closest
+            });
+
+        // Instance leaves are packets of eight slots like the others
+        let mut instance = None;
+        self.instance_bvh8
+            .traverse(ray, tmin, closest, |packet, tmax| {
+                closest = self.intersect_instances(
+                    packet * 8..packet * 8 + 8,
+                    ray,
+                    tmin,
+                    tmax,
+                    &mut instance,
+                );
+                closest
+            });
+
+        if let Some((i, hit)) = instance {
+            Some(self.instance_hit_record(i, hit, ray, closest))
+        } else if let Some((slot, det)) = triangle {
+            Some(
+                self.triangles_simd
+                    .hit_record(slot, ray, closest, det, &self.triangles),
+            )
+        } else {
+            sphere.map(|slot| self.spheres_simd.hit_record(slot, ray, closest))
+        }
+    }
+
+    /// Whether anything is hit between `tmin` and `tmax`, stopping at the
+    /// first hit without building a [`HitRecord`]. For shadow rays.
+    #[cfg(not(feature = "simd"))]
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub fn occluded(&self, ray: &Ray, tmin: f32, tmax: f32) -> bool {
+        self.sphere_bvh
+            .any_leaf(ray, tmin, tmax, |first, count, tmax| {
+                self.sphere_bvh.indices[first..first + count]
+                    .iter()
+                    .any(|&i| {
+                        self.spheres
+                            .intersect(i as usize, ray, tmin, tmax)
+                            .is_some()
+                    })
+            })
+            || self
+                .triangle_bvh
+                .any_leaf(ray, tmin, tmax, |first, count, tmax| {
+                    self.triangle_bvh.indices[first..first + count]
+                        .iter()
+                        .any(|&i| {
+                            self.triangles
+                                .intersect(i as usize, ray, tmin, tmax)
+                                .is_some()
+                        })
+                })
+            || self
+                .instance_bvh
+                .any_leaf(ray, tmin, tmax, |first, count, tmax| {
+                    self.instances_occlude(first..first + count, ray, tmin, tmax)
+                })
+    }
+
+    /// Whether anything is hit between `tmin` and `tmax`, stopping at the
+    /// first hit without building a [`HitRecord`]. For shadow rays.
+    #[cfg(feature = "simd")]
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub fn occluded(&self, ray: &Ray, tmin: f32, tmax: f32) -> bool {
+        let r_o = Vec3x8::from(ray.origin);
+        let r_d = Vec3x8::from(ray.direction);
+
+        self.sphere_bvh8.any_leaf(ray, tmin, tmax, |packet, tmax| {
+            self.spheres_simd
+                .occludes(packet, ray, tmin, tmax, r_o, r_d)
+        }) || self
+            .triangle_bvh8
+            .any_leaf(ray, tmin, tmax, |packet, tmax| {
+                self.triangles_simd
+                    .occludes(packet, tmin, tmax, r_o, r_d, ray.time)
+            })
+            || self
+                .instance_bvh8
+                .any_leaf(ray, tmin, tmax, |packet, tmax| {
+                    self.instances_occlude(packet * 8..packet * 8 + 8, ray, tmin, tmax)
+                })
     }
 }
 
-pub fn test_scene(scene: &mut Scene) {
-    let red = scene.add_material(Metal::new(vec3(0.85, 0.30, 0.30), 0.2));
-    let white = scene.add_material(Lambertian::new(vec3(0.73, 0.73, 0.73)));
-    let green = scene.add_material(Lambertian::new(vec3(0.12, 0.45, 0.15)));
-    let light = scene.add_material(DiffuseLight::new(vec3(5.0, 5.0, 5.0)));
-
-    scene.add_triangles(&Triangle::quad(
-        vec3(-20.0, 0.0, 0.0),
-        vec3(-20.0, 0.0, -40.0),
-        vec3(-20.0, 40.0, 0.0),
-        vec3(-20.0, 40.0, -400.0),
-        green,
-    ));
-
-    scene.add_triangles(&Triangle::quad(
-        vec3(20.0, 0.0, 0.0),
-        vec3(20.0, 0.0, -40.0),
-        vec3(20.0, 40.0, 0.0),
-        vec3(20.0, 40.0, -40.0),
-        red,
-    ));
-
-    scene.add_triangles(&Triangle::quad(
-        vec3(-20.0, 0.0, 0.0),
-        vec3(20.0, 0.0, 0.0),
-        vec3(-20.0, 0.0, -40.0),
-        vec3(20.0, 0.0, -40.0),
-        white,
-    ));
-
-    scene.add_triangles(&Triangle::quad(
-        vec3(-20.0, 40.0, 0.0),
-        vec3(20.0, 40.0, 0.0),
-        vec3(-20.0, 40.0, -40.0),
-        vec3(20.0, 40.0, -40.0),
-        white,
-    ));
-
-    scene.add_triangles(&Triangle::quad(
-        vec3(-20.0, 0.0, -40.0),
-        vec3(20.0, 0.0, -40.0),
-        vec3(-20.0, 40.0, -40.0),
-        vec3(20.0, 40.0, -40.0),
-        white,
-    ));
-
-    scene.add_triangles(&Triangle::quad(
-        vec3(-20.0, 0.0, 0.0),
-        vec3(20.0, 0.0, 0.0),
-        vec3(-20.0, 40.0, 0.0),
-        vec3(20.0, 40.0, 0.0),
-        white,
-    ));
-
-    scene.add_triangles(&Triangle::quad(
-        vec3(-5.0, 39.99, -15.0),
-        vec3(5.0, 39.99, -15.0),
-        vec3(-5.0, 39.99, -25.0),
-        vec3(5.0, 39.99, -25.0),
-        light,
-    ));
-
-    let sphere = scene.add_material(Dielectric::new(1.50));
+/// The built-in box scene, used when no scene file is given
+pub fn test_scene() -> Scene {
+    let mut scene = Scene {
+        camera: CameraSettings {
+            look_from: vec3(0.0, 15.0, -2.5),
+            look_at: vec3(0.0, 15.0, -5.5),
+            vfov: 90.0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let red = scene.add_material("red", Metal::new(vec3(0.85, 0.30, 0.30), 0.2));
+    let white = scene.add_material("white", Lambertian::new(vec3(0.73, 0.73, 0.73)));
+    let green = scene.add_material("green", Lambertian::new(vec3(0.12, 0.45, 0.15)));
+    let light = scene.add_material("light", DiffuseLight::new(vec3(5.0, 5.0, 5.0)));
+
+    scene.add_triangles(
+        "left wall",
+        &Triangle::quad(
+            vec3(-20.0, 0.0, 0.0),
+            vec3(-20.0, 0.0, -40.0),
+            vec3(-20.0, 40.0, 0.0),
+            vec3(-20.0, 40.0, -400.0),
+            green,
+        ),
+    );
+
+    scene.add_triangles(
+        "right wall",
+        &Triangle::quad(
+            vec3(20.0, 0.0, 0.0),
+            vec3(20.0, 0.0, -40.0),
+            vec3(20.0, 40.0, 0.0),
+            vec3(20.0, 40.0, -40.0),
+            red,
+        ),
+    );
+
+    scene.add_triangles(
+        "floor",
+        &Triangle::quad(
+            vec3(-20.0, 0.0, 0.0),
+            vec3(20.0, 0.0, 0.0),
+            vec3(-20.0, 0.0, -40.0),
+            vec3(20.0, 0.0, -40.0),
+            white,
+        ),
+    );
+
+    scene.add_triangles(
+        "ceiling",
+        &Triangle::quad(
+            vec3(-20.0, 40.0, 0.0),
+            vec3(20.0, 40.0, 0.0),
+            vec3(-20.0, 40.0, -40.0),
+            vec3(20.0, 40.0, -40.0),
+            white,
+        ),
+    );
+
+    scene.add_triangles(
+        "back wall",
+        &Triangle::quad(
+            vec3(-20.0, 0.0, -40.0),
+            vec3(20.0, 0.0, -40.0),
+            vec3(-20.0, 40.0, -40.0),
+            vec3(20.0, 40.0, -40.0),
+            white,
+        ),
+    );
+
+    scene.add_triangles(
+        "front wall",
+        &Triangle::quad(
+            vec3(-20.0, 0.0, 0.0),
+            vec3(20.0, 0.0, 0.0),
+            vec3(-20.0, 40.0, 0.0),
+            vec3(20.0, 40.0, 0.0),
+            white,
+        ),
+    );
+
+    scene.add_triangles(
+        "light",
+        &Triangle::quad(
+            vec3(-5.0, 39.99, -15.0),
+            vec3(5.0, 39.99, -15.0),
+            vec3(-5.0, 39.99, -25.0),
+            vec3(5.0, 39.99, -25.0),
+            light,
+        ),
+    );
+
+    let sphere = scene.add_material("sphere", Dielectric::new(1.50));
     scene.add_sphere(Sphere::new(vec3(-6.0, 8.0, -26.0), 5.0, sphere));
 
-    // let sphere_inner = scene.add_material(Dielectric::new(1.00 / 1.50));
+    // let sphere_inner = scene.add_material("sphere_inner", Dielectric::new(1.00 / 1.50));
     // scene.add_object(Sphere::new(vec3(-7.0, 9.0, -26.0), 4.0, sphere_inner));
 
-    let mirror = scene.add_material(Metal::new(vec3(0.82, 0.82, 0.82), 0.01));
-    scene.add_triangles(&Triangle::quad(
-        vec3(7.5, 0.0, -35.0),
-        vec3(12.5, 0.0, -31.0),
-        vec3(7.5, 20.0, -35.0),
-        vec3(12.5, 20.0, -31.0),
-        mirror,
-    ));
-
-    let metal = scene.add_material(Metal::new(vec3(0.72, 0.45, 0.12), 0.64));
+    let mirror = scene.add_material("mirror", Metal::new(vec3(0.82, 0.82, 0.82), 0.01));
+    scene.add_triangles(
+        "mirror",
+        &Triangle::quad(
+            vec3(7.5, 0.0, -35.0),
+            vec3(12.5, 0.0, -31.0),
+            vec3(7.5, 20.0, -35.0),
+            vec3(12.5, 20.0, -31.0),
+            mirror,
+        ),
+    );
+
+    let metal = scene.add_material("metal", Metal::new(vec3(0.72, 0.45, 0.12), 0.64));
     scene.add_sphere(Sphere::new(vec3(-4.0, 20.0, -24.0), 2.5, metal));
 
-    let glass = scene.add_material(Dielectric::new(1.5));
+    let glass = scene.add_material("glass", Dielectric::new(1.5));
     scene.add_sphere(Sphere::new(vec3(-17.0, 3.0, -37.0), 1.5, glass));
 
-    let light_sphere = scene.add_material(DiffuseLight::new(vec3(3.5, 1.8, 0.2)));
+    let light_sphere = scene.add_material("light_sphere", DiffuseLight::new(vec3(3.5, 1.8, 0.2)));
     scene.add_sphere(Sphere::new(vec3(-17.0, 3.0, -37.0), 1.0, light_sphere));
 
-    #[cfg(feature = "simd")]
-    scene.collect_simd();
+    scene.build().expect("the test scene builds");
+    scene
 }
@@ -0,0 +1,804 @@
+//! Text scene format, a RON document describing the camera, named
+//! materials, the objects and the background:
+//!
+//! ```ron
+//! (
+//!     camera: (look_from: (0.0, 1.0, 5.0), look_at: (0.0, 1.0, 0.0), vfov: 60.0),
+//!     background: Gradient,
+//!     materials: [
+//!         ("white", Lambertian(albedo: (0.73, 0.73, 0.73))),
+//!         ("lamp", DiffuseLight(emitted: (4.0, 4.0, 4.0))),
+//!     ],
+//!     objects: [
+//!         Sphere(center: (0.0, 1.0, 0.0), radius: 1.0, material: "white"),
+//!         Quad(corners: ((-1.0, 3.0, -1.0), (1.0, 3.0, -1.0), (-1.0, 3.0, 1.0), (1.0, 3.0, 1.0)), material: "lamp"),
+//!         Mesh(path: "bunny.ply", transform: (scale: (10.0, 10.0, 10.0)), material: Some("white")),
+//!     ],
+//! )
+//! ```
+//!
+//! The camera is perspective unless it sets a `projection`, e.g.
+//! `projection: Orthographic(width: 20.0)` or
+//! `projection: Fisheye(fov: 180.0, mapping: Equisolid)`.
+//!
+//! Triangles can carry vertex `normals` and `colors`. Consecutive triangles
+//! and quads with the same `name` form one group of the scene, the others
+//! a group each:
+//!
+//! ```ron
+//!     Triangle(name: Some("roof"), vertices: ((0.0, 2.0, 0.0), (1.0, 3.0, 0.0), (2.0, 2.0, 0.0)), normals: Some(((-0.5, 0.8, 0.0), (0.0, 1.0, 0.0), (0.5, 0.8, 0.0))), material: "white"),
+//! ```
+//!
+//! Objects move over the camera's `shutter_open` to `shutter_close` interval
+//! when they give where they are at time one, with `center_end`,
+//! `vertices_end`, `corners_end` or `transform_end`.
+//!
+//! A mesh placed many times is loaded once under `meshes` and placed by
+//! `Instance` objects, which share its triangles:
+//!
+//! ```ron
+//!     meshes: [("rock", (path: "rock.obj"))],
+//!     objects: [
+//!         Instance(mesh: "rock", transform: (translation: (2.0, 0.0, 0.0))),
+//!         Instance(mesh: "rock", transform: (rotation: (0.0, 90.0, 0.0)), material: Some("white")),
+//!     ],
+//! ```
+
+use std::{
+    fmt, fs, io, mem,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    camera::{CameraSettings, Projection},
+    environment::{Environment, EnvironmentMap},
+    geometry::{Sphere, Triangle, Triangles},
+    instance::{self, Instance},
+    material::{Dielectric, DiffuseLight, Lambertian, Material, Metal},
+    mesh::{self, ImportError, Transform},
+    scene::{MeshSource, Scene, TriangleGroup, ValidationError},
+};
+
+#[derive(Serialize, Deserialize)]
+struct SceneDescription {
+    camera: CameraSettings,
+    #[serde(default)]
+    background: Background,
+    materials: Vec<(String, MaterialDescription)>,
+    /// Named meshes for `Instance` objects to place
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    meshes: Vec<(String, SharedMeshDescription)>,
+    #[serde(default)]
+    objects: Vec<Object>,
+}
+
+/// Mesh file loaded once with its own materials, only its triangles are
+/// shared. Relative paths start at the scene file's directory.
+#[derive(Serialize, Deserialize)]
+struct SharedMeshDescription {
+    path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+enum Background {
+    #[default]
+    Gradient,
+    /// Equirectangular Radiance `.hdr`, relative paths start at the scene
+    /// file's directory
+    Hdr {
+        path: PathBuf,
+        #[serde(default)]
+        rotation: f32,
+        #[serde(default = "one")]
+        intensity: f32,
+    },
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+#[derive(Serialize, Deserialize)]
+enum MaterialDescription {
+    Lambertian { albedo: Vec3 },
+    Metal { albedo: Vec3, fuzz: f32 },
+    Dielectric { refraction_index: f32 },
+    DiffuseLight { emitted: Vec3 },
+}
+
+/// Fields ending in `_end` are where the object is at time one, it moves in
+/// a straight line from where it is at time zero. Triangles and quads
+/// without a `name` are named after their index in `objects`.
+#[derive(Serialize, Deserialize)]
+enum Object {
+    Sphere {
+        center: Vec3,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        center_end: Option<Vec3>,
+        radius: f32,
+        material: String,
+    },
+    Triangle {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        vertices: [Vec3; 3],
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        vertices_end: Option<[Vec3; 3]>,
+        /// Interpolated over the face instead of its normal
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        normals: Option<[Vec3; 3]>,
+        /// Linear RGB, multiplied into the material reflectance
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        colors: Option<[Vec3; 3]>,
+        material: String,
+    },
+    /// Two triangles, corners in the order of [`Triangle::quad`]
+    Quad {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        corners: [Vec3; 4],
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        corners_end: Option<[Vec3; 4]>,
+        material: String,
+    },
+    /// `.obj`, `.ply`, `.gltf` or `.glb` file, relative paths start at the
+    /// scene file's directory. `material` replaces the file's own materials,
+    /// glTF cameras are ignored.
+    Mesh {
+        path: PathBuf,
+        #[serde(default)]
+        transform: Transform,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        transform_end: Option<Transform>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<String>,
+    },
+    /// Places a mesh of `meshes` by name, `material` replaces the mesh's
+    /// own materials
+    Instance {
+        mesh: String,
+        #[serde(default)]
+        transform: Transform,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<String>,
+    },
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(io::Error),
+    /// Syntax or structure error, with the position in the file
+    Parse {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    DuplicateMaterial(String),
+    DuplicateMesh(String),
+    /// An object references a material that isn't defined
+    UnknownMaterial {
+        object: usize,
+        material: String,
+    },
+    /// An instance references a mesh that isn't defined
+    UnknownMesh {
+        object: usize,
+        mesh: String,
+    },
+    InvalidValue {
+        object: usize,
+        field: &'static str,
+        message: &'static str,
+    },
+    InvalidCamera {
+        field: &'static str,
+        message: &'static str,
+    },
+    Environment {
+        path: PathBuf,
+        error: io::Error,
+    },
+    Mesh {
+        object: usize,
+        error: ImportError,
+    },
+    /// Loading an entry of `meshes` failed
+    SharedMesh {
+        mesh: String,
+        error: ImportError,
+    },
+    /// Importing a glTF file as the whole scene failed
+    Import(ImportError),
+    /// The built scene lost primitives
+    Build(ValidationError),
+    Serialize(String),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(err) => write!(f, "{err}"),
+            SceneError::Parse {
+                line,
+                column,
+                message,
+            } => write!(f, "{line}:{column}: {message}"),
+            SceneError::DuplicateMaterial(name) => {
+                write!(f, "material `{name}` is defined more than once")
+            }
+            SceneError::UnknownMaterial { object, material } => {
+                write!(
+                    f,
+                    "objects[{object}].material: unknown material `{material}`"
+                )
+            }
+            SceneError::DuplicateMesh(name) => {
+                write!(f, "mesh `{name}` is defined more than once")
+            }
+            SceneError::UnknownMesh { object, mesh } => {
+                write!(f, "objects[{object}].mesh: unknown mesh `{mesh}`")
+            }
+            SceneError::InvalidValue {
+                object,
+                field,
+                message,
+            } => write!(f, "objects[{object}].{field}: {message}"),
+            SceneError::InvalidCamera { field, message } => write!(f, "camera.{field}: {message}"),
+            SceneError::Environment { path, error } => {
+                write!(f, "background: loading {}: {error}", path.display())
+            }
+            SceneError::Mesh { object, error } => write!(f, "objects[{object}]: {error}"),
+            SceneError::SharedMesh { mesh, error } => write!(f, "mesh `{mesh}`: {error}"),
+            SceneError::Import(error) => write!(f, "{error}"),
+            SceneError::Build(error) => write!(f, "building the scene: {error}"),
+            SceneError::Serialize(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<io::Error> for SceneError {
+    fn from(err: io::Error) -> Self {
+        SceneError::Io(err)
+    }
+}
+
+impl From<ValidationError> for SceneError {
+    fn from(err: ValidationError) -> Self {
+        SceneError::Build(err)
+    }
+}
+
+impl From<ron::error::SpannedError> for SceneError {
+    fn from(err: ron::error::SpannedError) -> Self {
+        SceneError::Parse {
+            line: err.position.line,
+            column: err.position.col,
+            message: err.code.to_string(),
+        }
+    }
+}
+
+/// A built scene and the approximations made importing its meshes
+pub struct LoadedScene {
+    pub scene: Scene,
+    pub approximations: Vec<String>,
+}
+
+/// Loads and builds the scene in `path`. `.gltf` and `.glb` files are
+/// imported as a whole scene with their first camera, anything else is read
+/// as the text format.
+pub fn load(path: impl AsRef<Path>) -> Result<LoadedScene, SceneError> {
+    let path = path.as_ref();
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase());
+    if matches!(extension.as_deref(), Some("gltf" | "glb")) {
+        return load_gltf(path);
+    }
+
+    let text = fs::read_to_string(path)?;
+    from_str(&text, path.parent().unwrap_or(Path::new("")))
+}
+
+fn load_gltf(path: &Path) -> Result<LoadedScene, SceneError> {
+    let mut scene = Scene::default();
+    let mut imported = mesh::load_gltf(&mut scene, path, &Transform::default(), None)
+        .map_err(SceneError::Import)?;
+
+    match imported.camera {
+        Some(camera) => scene.camera = camera,
+        None => imported
+            .approximations
+            .push("no camera, using the default".to_string()),
+    }
+
+    scene.build()?;
+    Ok(LoadedScene {
+        scene,
+        approximations: imported.approximations,
+    })
+}
+
+/// Parses and builds a scene, relative paths are resolved against `base_dir`
+pub fn from_str(text: &str, base_dir: &Path) -> Result<LoadedScene, SceneError> {
+    let desc: SceneDescription = ron::from_str(text)?;
+
+    let camera = desc.camera;
+    if camera.projection == Projection::Perspective && !(camera.vfov > 0.0 && camera.vfov < 180.0) {
+        return Err(SceneError::InvalidCamera {
+            field: "vfov",
+            message: "must be between 0 and 180 degrees",
+        });
+    }
+    camera
+        .projection
+        .validate()
+        .map_err(|message| SceneError::InvalidCamera {
+            field: "projection",
+            message,
+        })?;
+
+    let mut scene = Scene::default();
+    scene.camera = camera;
+    let mut approximations = Vec::new();
+
+    for (name, material) in desc.materials {
+        if scene.material_index(&name).is_some() {
+            return Err(SceneError::DuplicateMaterial(name));
+        }
+
+        let material: Material = match material {
+            MaterialDescription::Lambertian { albedo } => Lambertian::new(albedo).into(),
+            MaterialDescription::Metal { albedo, fuzz } => Metal::new(albedo, fuzz).into(),
+            MaterialDescription::Dielectric { refraction_index } => {
+                Dielectric::new(refraction_index).into()
+            }
+            MaterialDescription::DiffuseLight { emitted } => DiffuseLight::new(emitted).into(),
+        };
+        scene.add_material(name, material);
+    }
+
+    let mut meshes = Vec::new();
+    for (name, shared) in desc.meshes {
+        if meshes.iter().any(|(n, _)| *n == name) {
+            return Err(SceneError::DuplicateMesh(name));
+        }
+
+        // Loaded like a mesh object, then moved out of the scene's groups
+        let first_group = scene.groups().len();
+        let imported = mesh::load(
+            &mut scene,
+            base_dir.join(&shared.path),
+            &Transform::default(),
+            None,
+        )
+        .map_err(|error| SceneError::SharedMesh {
+            mesh: name.clone(),
+            error,
+        })?;
+        let index = scene.share_groups(first_group..scene.groups().len(), name.clone());
+        scene.set_mesh_source(index, shared.path);
+
+        approximations.extend(
+            imported
+                .approximations
+                .into_iter()
+                .map(|note| format!("mesh `{name}`: {note}")),
+        );
+        meshes.push((name, index));
+    }
+
+    let mut group = PendingGroup::default();
+    for (i, object) in desc.objects.iter().enumerate() {
+        if !matches!(object, Object::Triangle { .. } | Object::Quad { .. }) {
+            group.flush(&mut scene);
+        }
+
+        match object {
+            &Object::Sphere {
+                center,
+                center_end,
+                radius,
+                ref material,
+            } => {
+                let material = material_index(&scene, i, material)?;
+                if radius.is_nan() || radius <= 0.0 {
+                    return Err(SceneError::InvalidValue {
+                        object: i,
+                        field: "radius",
+                        message: "must be positive",
+                    });
+                }
+                let end = center_end.unwrap_or(center);
+                scene.add_sphere(Sphere::moving(center, end, radius, material));
+            }
+            &Object::Triangle {
+                ref name,
+                vertices: [v0, v1, v2],
+                vertices_end,
+                normals,
+                colors,
+                ref material,
+            } => {
+                let material = material_index(&scene, i, material)?;
+                let mut start = Triangle::new(v0, v1, v2, material);
+                if let Some(normals) = normals {
+                    start = start.with_normals(normals);
+                }
+                if let Some(colors) = colors {
+                    start = start.with_colors(colors);
+                }
+                let end =
+                    vertices_end.map(|[e0, e1, e2]| vec![Triangle::new(e0, e1, e2, material)]);
+                group.push(&mut scene, i, name, vec![start], end);
+            }
+            &Object::Quad {
+                ref name,
+                corners: [p0, p1, p2, p3],
+                corners_end,
+                ref material,
+            } => {
+                let material = material_index(&scene, i, material)?;
+                let start = Triangle::quad(p0, p1, p2, p3, material).to_vec();
+                let end = corners_end
+                    .map(|[e0, e1, e2, e3]| Triangle::quad(e0, e1, e2, e3, material).to_vec());
+                group.push(&mut scene, i, name, start, end);
+            }
+            Object::Mesh {
+                path,
+                transform,
+                transform_end,
+                material,
+            } => {
+                let material = match material {
+                    Some(name) => Some(material_index(&scene, i, name)?),
+                    None => None,
+                };
+                // The motion to `transform_end` starts from its inverse
+                if transform_end.is_some() && !instance::is_invertible(transform.matrix()) {
+                    return Err(SceneError::InvalidValue {
+                        object: i,
+                        field: "transform",
+                        message: "must be invertible",
+                    });
+                }
+                let first_group = scene.groups().len();
+                let imported = mesh::load(&mut scene, base_dir.join(path), transform, material)
+                    .map_err(|error| SceneError::Mesh { object: i, error })?;
+
+                // Moves the imported groups from `transform` to `transform_end`
+                let groups = first_group..scene.groups().len();
+                if let Some(end) = transform_end {
+                    let motion = end.matrix() * transform.matrix().inverse();
+                    for group in groups.clone() {
+                        scene
+                            .set_motion(group, motion)
+                            .expect("the group was just added");
+                    }
+                }
+                scene.set_source(
+                    groups,
+                    MeshSource {
+                        path: path.clone(),
+                        transform: *transform,
+                        transform_end: *transform_end,
+                        material,
+                    },
+                );
+                approximations.extend(
+                    imported
+                        .approximations
+                        .into_iter()
+                        .map(|note| format!("objects[{i}]: {note}")),
+                );
+            }
+            Object::Instance {
+                mesh,
+                transform,
+                material,
+            } => {
+                let material = match material {
+                    Some(name) => Some(material_index(&scene, i, name)?),
+                    None => None,
+                };
+                let &(_, index) = meshes.iter().find(|(n, _)| n == mesh).ok_or_else(|| {
+                    SceneError::UnknownMesh {
+                        object: i,
+                        mesh: mesh.clone(),
+                    }
+                })?;
+                scene
+                    .add_instance(Instance::new(index, transform.matrix(), material))
+                    .map_err(|_| SceneError::InvalidValue {
+                        object: i,
+                        field: "transform",
+                        message: "must be invertible",
+                    })?;
+            }
+        }
+    }
+    group.flush(&mut scene);
+
+    scene.environment = match desc.background {
+        Background::Gradient => Environment::Gradient,
+        Background::Hdr {
+            path,
+            rotation,
+            intensity,
+        } => {
+            let mut map = EnvironmentMap::load(base_dir.join(&path))
+                .map_err(|error| SceneError::Environment {
+                    path: path.clone(),
+                    error,
+                })?
+                .with_rotation(rotation)
+                .with_intensity(intensity);
+
+            // Keep the path as written so saving doesn't make it absolute
+            map.source = Some(path);
+            Environment::Map(Box::new(map))
+        }
+    };
+
+    scene.build()?;
+    Ok(LoadedScene {
+        scene,
+        approximations,
+    })
+}
+
+/// Triangles of consecutive objects with the same name, added to the scene
+/// as one group once an object with another name follows
+#[derive(Default)]
+struct PendingGroup {
+    name: Option<String>,
+    /// Index of the first object, which names the group without a name
+    object: usize,
+    start: Vec<Triangle>,
+    /// Where `start` is at time one, the same for triangles that don't move
+    end: Vec<Triangle>,
+    moving: bool,
+}
+
+impl PendingGroup {
+    fn push(
+        &mut self,
+        scene: &mut Scene,
+        object: usize,
+        name: &Option<String>,
+        start: Vec<Triangle>,
+        end: Option<Vec<Triangle>>,
+    ) {
+        if name.is_none() || *name != self.name {
+            self.flush(scene);
+            self.name = name.clone();
+            self.object = object;
+        }
+
+        self.moving |= end.is_some();
+        self.end.extend(end.unwrap_or_else(|| start.clone()));
+        self.start.extend(start);
+    }
+
+    fn flush(&mut self, scene: &mut Scene) {
+        let name = self.name.take();
+        if self.start.is_empty() {
+            return;
+        }
+
+        let name = name.unwrap_or_else(|| format!("objects[{}]", self.object));
+        let (start, end) = (mem::take(&mut self.start), mem::take(&mut self.end));
+        match mem::take(&mut self.moving) {
+            true => scene
+                .add_moving_triangles(name, &start, &end)
+                .expect("every triangle has an end position"),
+            false => scene.add_triangles(name, &start),
+        }
+    }
+}
+
+fn material_index(scene: &Scene, object: usize, name: &str) -> Result<u32, SceneError> {
+    scene
+        .material_index(name)
+        .ok_or_else(|| SceneError::UnknownMaterial {
+            object,
+            material: name.to_string(),
+        })
+}
+
+/// Writes `scene` in the text format. Meshes imported from a file refer to
+/// it unless they were edited since, other groups are written as quads or
+/// triangles under their name. Instances of meshes loaded from a file are
+/// written as instances, those of other meshes as their triangles.
+pub fn to_string(scene: &Scene) -> Result<String, SceneError> {
+    let materials = scene
+        .materials
+        .iter()
+        .zip(&scene.material_names)
+        .map(|(material, name)| {
+            let material = match *material {
+                Material::Lambertian(Lambertian { albedo }) => {
+                    MaterialDescription::Lambertian { albedo }
+                }
+                Material::Metal(Metal { albedo, fuzz }) => {
+                    MaterialDescription::Metal { albedo, fuzz }
+                }
+                Material::Dielectric(Dielectric { refraction_index }) => {
+                    MaterialDescription::Dielectric { refraction_index }
+                }
+                Material::DiffuseLight(DiffuseLight { emitted }) => {
+                    MaterialDescription::DiffuseLight { emitted }
+                }
+            };
+            (name.clone(), material)
+        })
+        .collect();
+
+    let name = |i: u32| scene.material_names[i as usize].clone();
+
+    let spheres = scene.spheres();
+    let triangles = scene.triangles();
+
+    let mut objects: Vec<_> = (0..spheres.len())
+        .map(|i| Object::Sphere {
+            center: spheres.center(i),
+            center_end: (spheres.motion(i) != Vec3::ZERO).then(|| spheres.center_at(i, 1.0)),
+            radius: spheres.radius(i),
+            material: name(spheres.material(i)),
+        })
+        .collect();
+
+    let mut groups = scene.groups();
+    while let Some(group) = groups.first() {
+        if let Some(mesh) = imported_mesh(groups) {
+            objects.push(Object::Mesh {
+                path: mesh.path.clone(),
+                transform: mesh.transform,
+                transform_end: mesh.transform_end,
+                material: mesh.material.map(name),
+            });
+            groups = &groups[group.source.as_ref().map_or(1, |s| s.parts)..];
+            continue;
+        }
+
+        let range = group.triangles.clone();
+        match quad_corners(triangles, range.clone()) {
+            Some((corners, corners_end)) => objects.push(Object::Quad {
+                name: Some(group.name.clone()),
+                corners,
+                corners_end,
+                material: name(triangles.material(range.start)),
+            }),
+            None => objects.extend(range.map(|i| Object::Triangle {
+                name: Some(group.name.clone()),
+                vertices: triangles.vertices(i),
+                vertices_end: triangles.vertices_end(i),
+                normals: triangles.vertex_normals(i),
+                colors: triangles.vertex_colors(i),
+                material: name(triangles.material(i)),
+            })),
+        }
+        groups = &groups[1..];
+    }
+
+    let meshes = scene
+        .meshes()
+        .iter()
+        .filter_map(|mesh| {
+            let path = mesh.source.clone()?;
+            Some((mesh.name.clone(), SharedMeshDescription { path }))
+        })
+        .collect();
+
+    for instance in scene.instances() {
+        let mesh = &scene.meshes()[instance.mesh as usize];
+        let transform = Transform::from_matrix(instance.transform());
+        if let (Some(_), Some(transform)) = (&mesh.source, transform) {
+            objects.push(Object::Instance {
+                mesh: mesh.name.clone(),
+                transform,
+                material: instance.material.map(name),
+            });
+            continue;
+        }
+
+        objects.extend((0..mesh.triangles().len()).map(|i| {
+            let (vertices, vertices_end) = instance.vertices(mesh, i);
+            Object::Triangle {
+                name: None,
+                vertices,
+                vertices_end,
+                normals: None,
+                colors: None,
+                material: name(instance.material(mesh, i)),
+            }
+        }));
+    }
+
+    let background = match &scene.environment {
+        Environment::Gradient => Background::Gradient,
+        Environment::Map(map) => match &map.source {
+            Some(path) => Background::Hdr {
+                path: path.clone(),
+                rotation: map.rotation,
+                intensity: map.intensity,
+            },
+            None => {
+                return Err(SceneError::Serialize(
+                    "environment map has no source file".to_string(),
+                ));
+            }
+        },
+    };
+
+    let desc = SceneDescription {
+        camera: scene.camera,
+        background,
+        materials,
+        meshes,
+        objects,
+    };
+
+    let config = ron::ser::PrettyConfig::new().depth_limit(3);
+    ron::ser::to_string_pretty(&desc, config).map_err(|err| SceneError::Serialize(err.to_string()))
+}
+
+/// The mesh file whose import added the groups at the start of `groups`,
+/// if all of them are still there and unedited
+fn imported_mesh(groups: &[TriangleGroup]) -> Option<&MeshSource> {
+    let source = groups.first()?.source.as_ref()?;
+    let parts = groups.get(..source.parts)?;
+    let complete = source.part == 0
+        && parts.iter().enumerate().all(|(part, group)| {
+            group
+                .source
+                .as_ref()
+                .is_some_and(|s| s.part == part && s.mesh == source.mesh)
+        });
+    complete.then_some(&source.mesh)
+}
+
+/// Corners of the two triangles in `range` if they are a flat shaded
+/// [`Triangle::quad`], and where they are at time one if they move
+fn quad_corners(
+    triangles: &Triangles,
+    range: Range<usize>,
+) -> Option<([Vec3; 4], Option<[Vec3; 4]>)> {
+    if range.len() != 2 {
+        return None;
+    }
+    let (a, b) = (range.start, range.start + 1);
+    // Vertex normals exist for any triangle with vertex attributes
+    if triangles.material(a) != triangles.material(b)
+        || triangles.vertex_normals(a).is_some()
+        || triangles.vertex_normals(b).is_some()
+    {
+        return None;
+    }
+
+    // The second triangle of a quad is (p1, p3, p2), its vertices are
+    // rebuilt from edges so they only match approximately
+    let corners = |[p0, p1, p2]: [Vec3; 3], [q1, p3, q2]: [Vec3; 3]| {
+        let close =
+            |p: Vec3, q: Vec3| (p - q).abs().max_element() <= 1e-5 * p.abs().max_element().max(1.0);
+        (close(p1, q1) && close(p2, q2)).then_some([p0, p1, p2, p3])
+    };
+
+    let start = corners(triangles.vertices(a), triangles.vertices(b))?;
+    match (triangles.vertices_end(a), triangles.vertices_end(b)) {
+        (None, None) => Some((start, None)),
+        (Some(ea), Some(eb)) => Some((start, Some(corners(ea, eb)?))),
+        _ => None,
+    }
+}
+
+pub fn save(scene: &Scene, path: impl AsRef<Path>) -> Result<(), SceneError> {
+    fs::write(path, to_string(scene)?)?;
+    Ok(())
+}
@@ -27,6 +27,9 @@ impl Default for f32x8 {
 impl f32x8 {
     pub const ZERO: f32x8 = f32x8 { lanes: [0.0; 8] };
     pub const ONE: f32x8 = f32x8 { lanes: [1.0; 8] };
+    pub const INFINITY: f32x8 = f32x8 {
+        lanes: [f32::INFINITY; 8],
+    };
 
     pub const EPSILON: f32x8 = f32x8 {
         lanes: [f32::EPSILON; 8],
@@ -67,6 +70,13 @@ impl f32x8 {
         }
     }
 
+    #[inline(always)]
+    pub fn cmp_le(self, rhs: f32x8) -> Bitmask {
+        Bitmask {
+            m256: unsafe { _mm256_cmp_ps::<_CMP_LE_OQ>(self.m256, rhs.m256) },
+        }
+    }
+
     #[inline(always)]
     pub fn cmp_gt(self, rhs: f32x8) -> Bitmask {
         Bitmask {
@@ -81,11 +91,58 @@ impl f32x8 {
         }
     }
 
+    /// `!(self < rhs)`, true for NaN lanes
+    #[inline(always)]
+    pub fn cmp_nlt(self, rhs: f32x8) -> Bitmask {
+        Bitmask {
+            m256: unsafe { _mm256_cmp_ps::<_CMP_NLT_UQ>(self.m256, rhs.m256) },
+        }
+    }
+
     #[inline(always)]
     pub fn min(a: f32x8, b: f32x8) -> f32x8 {
         unsafe { _mm256_min_ps(a.m256, b.m256) }.into()
     }
 
+    #[inline(always)]
+    pub fn max(a: f32x8, b: f32x8) -> f32x8 {
+        unsafe { _mm256_max_ps(a.m256, b.m256) }.into()
+    }
+
+    /// Broadcasts the smallest lane to all lanes
+    #[inline(always)]
+    pub fn horizontal_min(self) -> f32x8 {
+        let v1 = f32x8::min(self, self.permute::<0b10_11_00_01>());
+        let v2 = f32x8::min(v1, v1.permute::<0b01_00_11_10>());
+        f32x8::min(v2, f32x8::permute_2f128::<0b0000_0001>(v2, v2))
+    }
+
+    /// Sorts the lanes ascending with a bitonic network over the same
+    /// permutes as [`f32x8::horizontal_min`]
+    #[inline(always)]
+    pub fn sort(self) -> f32x8 {
+        // Each stage pairs every lane with its neighbour, the neighbouring
+        // pair or the other half, and `upper` lanes keep the larger key
+        let stage = |v: f32x8, partner: f32x8, upper: [f32; 8]| {
+            let upper = Bitmask {
+                m256: unsafe { f32x8::from_array(upper).m256 },
+            };
+            f32x8::blend(f32x8::min(v, partner), f32x8::max(v, partner), upper)
+        };
+        let swap1 = |v: f32x8| v.permute::<0b10_11_00_01>();
+        let swap2 = |v: f32x8| v.permute::<0b01_00_11_10>();
+        let swap4 = |v: f32x8| f32x8::permute_2f128::<0b0000_0001>(v, v);
+
+        const L: f32 = 0.0;
+        const U: f32 = -0.0;
+        let v = stage(self, swap1(self), [L, U, U, L, L, U, U, L]);
+        let v = stage(v, swap2(v), [L, L, U, U, U, U, L, L]);
+        let v = stage(v, swap1(v), [L, U, L, U, U, L, U, L]);
+        let v = stage(v, swap4(v), [L, L, L, L, U, U, U, U]);
+        let v = stage(v, swap2(v), [L, L, U, U, L, L, U, U]);
+        stage(v, swap1(v), [L, U, L, U, L, U, L, U])
+    }
+
     #[inline(always)]
     pub fn blend(a: f32x8, b: f32x8, mask: Bitmask) -> f32x8 {
         unsafe { _mm256_blendv_ps(a.m256, b.m256, mask.m256) }.into()
@@ -143,6 +200,24 @@ impl std::ops::Sub for f32x8 {
     }
 }
 
+impl std::ops::BitAnd for f32x8 {
+    type Output = Self;
+
+    #[inline(always)]
+    fn bitand(self, rhs: Self) -> Self::Output {
+        unsafe { _mm256_and_ps(self.m256, rhs.m256) }.into()
+    }
+}
+
+impl std::ops::BitOr for f32x8 {
+    type Output = Self;
+
+    #[inline(always)]
+    fn bitor(self, rhs: Self) -> Self::Output {
+        unsafe { _mm256_or_ps(self.m256, rhs.m256) }.into()
+    }
+}
+
 impl std::ops::Index<usize> for f32x8 {
     type Output = f32;
 
@@ -324,6 +399,16 @@ impl Vec3x8 {
         f32x8::fmadd(self.z, rhs.z, f32x8::fmadd(self.y, rhs.y, self.x * rhs.x))
     }
 
+    /// `self * s + add` per component
+    #[inline(always)]
+    pub fn mul_add(self, s: f32x8, add: Vec3x8) -> Vec3x8 {
+        Vec3x8 {
+            x: f32x8::fmadd(self.x, s, add.x),
+            y: f32x8::fmadd(self.y, s, add.y),
+            z: f32x8::fmadd(self.z, s, add.z),
+        }
+    }
+
     #[inline(always)]
     pub fn cross(self, rhs: Vec3x8) -> Vec3x8 {
         Vec3x8 {
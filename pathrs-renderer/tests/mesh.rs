@@ -0,0 +1,223 @@
+use std::{fs, path::PathBuf};
+
+use glam::vec3;
+use pathrs_renderer::{
+    mesh::{self, Transform},
+    scene::Scene,
+};
+
+/// Empty directory for the files of one test
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("pathrs-mesh-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn obj_negative_indices_count_back_from_the_last_vertex() {
+    let path = test_dir("obj-negative").join("tri.obj");
+    fs::write(
+        &path,
+        "v 9 9 9\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\nv 0 0 2\nf 2/1/1 3//1 -1\n",
+    )
+    .unwrap();
+
+    let mut scene = Scene::default();
+    let imported = mesh::load(&mut scene, &path, &Transform::default(), None).unwrap();
+    assert_eq!(imported.triangles, 2);
+    assert!(imported.approximations.is_empty());
+
+    let triangles = scene.triangles();
+    assert_eq!(
+        triangles.vertices(0),
+        [
+            vec3(0.0, 0.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+            vec3(0.0, 1.0, 0.0)
+        ]
+    );
+    assert_eq!(
+        triangles.vertices(1),
+        [
+            vec3(0.0, 0.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+            vec3(0.0, 0.0, 2.0)
+        ]
+    );
+}
+
+#[test]
+fn obj_polygons_are_fan_triangulated() {
+    let path = test_dir("obj-ngon").join("pentagon.obj");
+    fs::write(
+        &path,
+        "v 0 0 0\nv 2 0 0\nv 3 1 0\nv 1 2 0\nv -1 1 0\nf 1 2 3 4 5\nf 1 2 3 4\n",
+    )
+    .unwrap();
+
+    let mut scene = Scene::default();
+    let imported = mesh::load(&mut scene, &path, &Transform::default(), None).unwrap();
+    assert_eq!(imported.triangles, 5);
+
+    let triangles = scene.triangles();
+    let first: Vec<_> = (0..3).map(|i| triangles.vertices(i)[0]).collect();
+    assert_eq!(first, [vec3(0.0, 0.0, 0.0); 3]);
+    assert_eq!(triangles.vertices(2)[2], vec3(-1.0, 1.0, 0.0));
+    let area: f32 = (0..3).map(|i| triangles.area(i)).sum();
+    assert!((area - 5.0).abs() < 1e-5, "{area}");
+}
+
+#[test]
+fn obj_groups_and_objects_become_scene_groups() {
+    let path = test_dir("obj-groups").join("house.obj");
+    fs::write(
+        &path,
+        "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3\n\
+         o walls\nf 1 2 3 4\ng roof tiles\nf 1 2 3\nf 1 3 4\n\
+         g walls\nf 2 3 4\ng\nf 1 2 4\ng empty\n",
+    )
+    .unwrap();
+
+    let mut scene = Scene::default();
+    let imported = mesh::load(&mut scene, &path, &Transform::default(), None).unwrap();
+    assert_eq!(imported.triangles, 7);
+
+    let groups: Vec<_> = scene
+        .groups()
+        .iter()
+        .map(|g| (g.name.as_str(), g.triangles.len()))
+        .collect();
+    assert_eq!(groups, [("house", 2), ("walls", 3), ("roof tiles", 2)]);
+}
+
+#[test]
+fn obj_missing_materials_use_the_default() {
+    let dir = test_dir("obj-mtl");
+    fs::write(dir.join("known.mtl"), "newmtl red\nKd 1 0 0\n").unwrap();
+    let path = dir.join("box.obj");
+    fs::write(
+        &path,
+        "mtllib missing.mtl known.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\n\
+         usemtl red\nf 1 2 3\nusemtl blue\nf 1 2 3\nusemtl blue\nf 1 2 3\n",
+    )
+    .unwrap();
+
+    let mut scene = Scene::default();
+    let imported = mesh::load(&mut scene, &path, &Transform::default(), None).unwrap();
+    assert_eq!(imported.triangles, 3);
+    assert_eq!(
+        imported.approximations.len(),
+        2,
+        "{:?}",
+        imported.approximations
+    );
+    assert!(imported.approximations[0].contains("missing.mtl"));
+    assert!(imported.approximations[1].contains("blue"));
+
+    let triangles = scene.triangles();
+    assert_eq!(scene.material_index("box/red"), Some(triangles.material(0)));
+    assert_eq!(
+        scene.material_index("box/default"),
+        Some(triangles.material(1))
+    );
+    assert_eq!(triangles.material(2), triangles.material(1));
+}
+
+/// A quad with per-vertex normals and colors, written as `format`
+fn ply_quad(format: &str) -> Vec<u8> {
+    let positions = [
+        [0.0f32, 0.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [0.0, 1.0, 0.0],
+    ];
+    let colors = [[255u8, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 255]];
+    let face = [0i32, 1, 2, 3];
+
+    let mut data = format!(
+        "ply\nformat {format} 1.0\ncomment quad\n\
+         element vertex 4\nproperty float x\nproperty float y\nproperty float z\n\
+         property float nx\nproperty float ny\nproperty float nz\n\
+         property uchar red\nproperty uchar green\nproperty uchar blue\n\
+         element face 1\nproperty list uchar int vertex_indices\nend_header\n"
+    )
+    .into_bytes();
+
+    let floats = |v: &[f32], data: &mut Vec<u8>| {
+        for &f in v {
+            match format {
+                "binary_little_endian" => data.extend(f.to_le_bytes()),
+                "binary_big_endian" => data.extend(f.to_be_bytes()),
+                _ => data.extend(format!("{f} ").bytes()),
+            }
+        }
+    };
+    for (p, c) in positions.iter().zip(colors) {
+        floats(p, &mut data);
+        floats(&[0.0, 0.0, 1.0], &mut data);
+        match format {
+            "ascii" => data.extend(format!("{} {} {}\n", c[0], c[1], c[2]).bytes()),
+            _ => data.extend(c),
+        }
+    }
+    match format {
+        "binary_little_endian" => {
+            data.push(4);
+            face.iter().for_each(|i| data.extend(i.to_le_bytes()));
+        }
+        "binary_big_endian" => {
+            data.push(4);
+            face.iter().for_each(|i| data.extend(i.to_be_bytes()));
+        }
+        _ => data.extend(b"4 0 1 2 3\n"),
+    }
+    data
+}
+
+#[test]
+fn ply_reads_ascii_and_binary_bodies_with_normals_and_colors() {
+    let dir = test_dir("ply");
+    for format in ["ascii", "binary_little_endian", "binary_big_endian"] {
+        let path = dir.join(format!("{format}.ply"));
+        fs::write(&path, ply_quad(format)).unwrap();
+
+        let mut scene = Scene::default();
+        let imported = mesh::load(&mut scene, &path, &Transform::default(), None).unwrap();
+        assert_eq!(imported.triangles, 2, "{format}");
+
+        let triangles = scene.triangles();
+        assert_eq!(
+            triangles.vertices(1),
+            [
+                vec3(0.0, 0.0, 0.0),
+                vec3(1.0, 1.0, 0.0),
+                vec3(0.0, 1.0, 0.0)
+            ],
+            "{format}"
+        );
+        assert_eq!(
+            triangles.vertex_normals(0),
+            Some([vec3(0.0, 0.0, 1.0); 3]),
+            "{format}"
+        );
+        assert_eq!(
+            triangles.vertex_colors(0),
+            Some([
+                vec3(1.0, 0.0, 0.0),
+                vec3(0.0, 1.0, 0.0),
+                vec3(0.0, 0.0, 1.0)
+            ]),
+            "{format}"
+        );
+        assert_eq!(
+            triangles.vertex_colors(1),
+            Some([
+                vec3(1.0, 0.0, 0.0),
+                vec3(0.0, 0.0, 1.0),
+                vec3(1.0, 1.0, 1.0)
+            ]),
+            "{format}"
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    collections::BTreeSet,
+    time::{Duration, Instant},
+};
+
+use glam::vec3;
+use pathrs_renderer::{
+    Adaptive, RenderSettings, RenderSystem, RendererCmd, StopCondition,
+    aov::NO_ID,
+    camera::Camera,
+    geometry::Triangle,
+    material::Lambertian,
+    renderer::CPURenderer,
+    scene::{Scene, SceneEdit, test_scene},
+};
+
+fn render(scene: Scene, threads: usize, adaptive: Option<Adaptive>) -> Vec<[f32; 4]> {
+    let settings = RenderSettings {
+        threads,
+        tile_rows: 3,
+        seed: Some(7),
+        adaptive,
+        ..Default::default()
+    };
+    let (renderer, _, _) = RenderSystem::<CPURenderer>::new(40, 30, scene, settings);
+    let stop = StopCondition {
+        samples: 8,
+        time: None,
+    };
+    renderer.render_image(stop).0
+}
+
+#[test]
+fn same_seed_renders_identically_on_any_thread_count() {
+    let single = render(test_scene(), 1, None);
+    for threads in [2, 3, 8] {
+        assert!(
+            render(test_scene(), threads, None) == single,
+            "{threads} threads differ from one"
+        );
+    }
+}
+
+#[test]
+fn same_seed_converges_identically_on_any_thread_count() {
+    let adaptive = Some(Adaptive {
+        threshold: 0.2,
+        min_samples: 2,
+    });
+    let single = render(test_scene(), 1, adaptive);
+    assert!(render(test_scene(), 4, adaptive) == single);
+}
+
+/// `count` triangles side by side, each visible in a 160x40 image
+fn triangle_row(count: usize) -> Scene {
+    let mut scene = Scene::default();
+    let white = scene.add_material("white", Lambertian::new(vec3(0.7, 0.7, 0.7)));
+    let triangles: Vec<_> = (0..count)
+        .map(|i| {
+            let x = i as f32 - count as f32 / 2.0;
+            Triangle::new(
+                vec3(x, -1.0, 0.0),
+                vec3(x + 1.0, -1.0, 0.0),
+                vec3(x, 1.0, 0.0),
+                white,
+            )
+        })
+        .collect();
+    scene.add_triangles("row", &triangles);
+    scene.camera.look_from = vec3(0.0, 0.0, 5.0);
+    scene.camera.look_at = vec3(0.0, 0.0, 0.0);
+    scene.camera.vfov = 60.0;
+    scene
+}
+
+#[test]
+fn partial_packets_render_every_triangle() {
+    for count in [9, 15, 17] {
+        let mut scene = triangle_row(count);
+        scene.build().unwrap();
+
+        let settings = RenderSettings {
+            aovs: true,
+            seed: Some(7),
+            ..Default::default()
+        };
+        let (renderer, _, _) = RenderSystem::<CPURenderer>::new(160, 40, scene, settings);
+        let stop = StopCondition {
+            samples: 1,
+            time: None,
+        };
+        let (_, aovs) = renderer.render_image(stop);
+
+        let hit: BTreeSet<u32> = aovs
+            .primitive_id
+            .into_iter()
+            .filter(|&id| id != NO_ID)
+            .collect();
+        assert_eq!(hit, (0..count as u32).collect(), "{count} triangles");
+    }
+}
+
+#[test]
+fn depth_aov_focuses_on_the_plane_under_the_pixel() {
+    let mut scene = triangle_row(9);
+    scene.build().unwrap();
+    let camera = Camera::new(&scene.camera, glam::uvec2(160, 40));
+
+    let settings = RenderSettings {
+        aovs: true,
+        seed: Some(7),
+        ..Default::default()
+    };
+    let (renderer, _, _) = RenderSystem::<CPURenderer>::new(160, 40, scene, settings);
+    let stop = StopCondition {
+        samples: 4,
+        time: None,
+    };
+    let (_, aovs) = renderer.render_image(stop);
+
+    // The triangles lie in a plane 5 in front of the camera, which off center
+    // pixels see further away along their rays. The depth is averaged over
+    // the area of the pixel, not taken at its center.
+    let mut hits = 0;
+    for (i, &depth) in aovs.depth.iter().enumerate() {
+        if aovs.primitive_id[i] == NO_ID {
+            continue;
+        }
+        let (x, y) = ((i % 160) as f32, (i / 160) as f32);
+        let focus = camera.focus_distance_at(x, y, depth);
+        assert!(
+            (focus - 5.0).abs() < 0.05,
+            "pixel {x} {y}: {focus} at depth {depth}"
+        );
+        hits += 1;
+    }
+    assert!(hits > 100, "{hits} hits");
+}
+
+#[test]
+fn edits_come_back_in_the_published_scene_summary() {
+    let mut scene = triangle_row(3);
+    scene.build().unwrap();
+    let (renderer, cmd_tx, mut output) =
+        RenderSystem::<CPURenderer>::new(16, 4, scene, RenderSettings::default());
+    renderer.start_thread();
+
+    cmd_tx
+        .send(RendererCmd::Edit(SceneEdit::RemoveTriangles(0)))
+        .unwrap();
+    cmd_tx
+        .send(RendererCmd::Edit(SceneEdit::RemoveSphere(3)))
+        .unwrap();
+
+    let start = Instant::now();
+    loop {
+        let summary = &output.read().scene;
+        if summary.groups.is_empty() && !summary.errors.is_empty() {
+            assert_eq!(summary.errors, ["edit failed: no sphere 3"]);
+            break;
+        }
+        assert!(start.elapsed() < Duration::from_secs(10), "no summary");
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    cmd_tx.send(RendererCmd::Stop).unwrap();
+}
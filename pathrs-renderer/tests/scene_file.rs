@@ -0,0 +1,179 @@
+use std::{fs, path::PathBuf};
+
+use glam::{Vec3, vec3};
+use pathrs_renderer::scene_file;
+
+/// Empty directory for the files of one test
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("pathrs-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+const SCENE: &str = r#"(
+    camera: (look_from: (0.0, 1.0, 5.0), look_at: (0.0, 1.0, 0.0), vfov: 60.0),
+    materials: [("white", Lambertian(albedo: (0.7, 0.7, 0.7)))],
+    objects: [
+        Quad(name: Some("floor"), corners: ((-1.0, 0.0, -1.0), (1.0, 0.0, -1.0), (-1.0, 0.0, 1.0), (1.0, 0.0, 1.0)), material: "white"),
+        Triangle(name: Some("roof"), vertices: ((0.0, 2.0, 0.0), (1.0, 3.0, 0.0), (2.0, 2.0, 0.0)), normals: Some(((-0.6, 0.8, 0.0), (0.0, 1.0, 0.0), (0.6, 0.8, 0.0))), colors: Some(((1.0, 0.0, 0.0), (0.0, 1.0, 0.0), (0.0, 0.0, 1.0))), material: "white"),
+        Triangle(name: Some("roof"), vertices: ((0.0, 2.0, 1.0), (1.0, 3.0, 1.0), (2.0, 2.0, 1.0)), material: "white"),
+        Triangle(vertices: ((0.0, 0.0, 3.0), (1.0, 0.0, 3.0), (0.0, 1.0, 3.0)), vertices_end: Some(((0.0, 1.0, 3.0), (1.0, 1.0, 3.0), (0.0, 2.0, 3.0))), material: "white"),
+        Mesh(path: "tri.obj", transform: (translation: (0.0, 2.0, 0.0))),
+    ],
+)"#;
+
+#[test]
+fn save_keeps_groups_attributes_quads_and_mesh_files() {
+    let dir = test_dir("save");
+    fs::write(
+        dir.join("tri.obj"),
+        "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\nf 1 2 4 3\n",
+    )
+    .unwrap();
+
+    let scene = scene_file::from_str(SCENE, &dir).unwrap().scene;
+    let groups: Vec<_> = scene
+        .groups()
+        .iter()
+        .map(|g| (g.name.as_str(), g.triangles.len()))
+        .collect();
+    assert_eq!(
+        groups,
+        [("floor", 2), ("roof", 2), ("objects[3]", 1), ("tri", 2)]
+    );
+
+    let saved = scene_file::to_string(&scene).unwrap();
+    assert!(saved.contains("Quad("), "{saved}");
+    assert!(saved.contains("path: \"tri.obj\""), "{saved}");
+
+    let reloaded = scene_file::from_str(&saved, &dir).unwrap().scene;
+    assert_eq!(scene_file::to_string(&reloaded).unwrap(), saved);
+
+    let triangles = reloaded.triangles();
+    assert_eq!(reloaded.groups()[1].name, "roof");
+    assert_eq!(
+        triangles.vertex_normals(2),
+        Some([
+            vec3(-0.6, 0.8, 0.0),
+            vec3(0.0, 1.0, 0.0),
+            vec3(0.6, 0.8, 0.0)
+        ])
+    );
+    assert_eq!(
+        triangles.vertex_colors(2),
+        Some([Vec3::X, Vec3::Y, Vec3::Z])
+    );
+    assert_eq!(triangles.vertex_normals(3), None);
+    assert!(triangles.vertices_end(4).is_some());
+}
+
+#[test]
+fn edited_mesh_is_saved_as_triangles() {
+    let dir = test_dir("edited");
+    fs::write(dir.join("tri.obj"), "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+
+    let mut scene = scene_file::from_str(SCENE, &dir).unwrap().scene;
+    scene
+        .edit(pathrs_renderer::scene::SceneEdit::MoveTriangles {
+            index: 3,
+            offset: vec3(1.0, 0.0, 0.0),
+        })
+        .unwrap();
+
+    let saved = scene_file::to_string(&scene).unwrap();
+    assert!(!saved.contains("tri.obj"), "{saved}");
+    assert!(saved.contains("name: Some(\"tri\")"), "{saved}");
+}
+
+#[test]
+fn test_scene_round_trips_as_quads() {
+    let scene = pathrs_renderer::scene::test_scene();
+    let saved = scene_file::to_string(&scene).unwrap();
+    assert_eq!(
+        saved.matches("Quad(").count(),
+        scene.groups().len(),
+        "{saved}"
+    );
+
+    let reloaded = scene_file::from_str(&saved, &test_dir("quads"))
+        .unwrap()
+        .scene;
+    assert_eq!(scene_file::to_string(&reloaded).unwrap(), saved);
+}
+
+#[test]
+fn instances_are_saved_as_instances() {
+    let dir = test_dir("instances");
+    fs::write(dir.join("tri.obj"), "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+    let text = r#"(
+        camera: (look_from: (0.0, 1.0, 5.0), look_at: (0.0, 1.0, 0.0), vfov: 60.0),
+        materials: [("white", Lambertian(albedo: (0.7, 0.7, 0.7)))],
+        meshes: [("tri", (path: "tri.obj"))],
+        objects: [
+            Instance(mesh: "tri", transform: (translation: (2.0, 0.0, 0.0))),
+            Instance(mesh: "tri", transform: (scale: (2.0, 1.0, 3.0), rotation: (30.0, 90.0, -45.0)), material: Some("white")),
+        ],
+    )"#;
+
+    let scene = scene_file::from_str(text, &dir).unwrap().scene;
+    let saved = scene_file::to_string(&scene).unwrap();
+    assert!(saved.contains("path: \"tri.obj\""), "{saved}");
+    assert_eq!(saved.matches("Instance(").count(), 2, "{saved}");
+    assert!(!saved.contains("Triangle("), "{saved}");
+
+    let reloaded = scene_file::from_str(&saved, &dir).unwrap().scene;
+    for (a, b) in scene.instances().iter().zip(reloaded.instances()) {
+        assert!(a.transform().abs_diff_eq(b.transform(), 1e-5));
+        assert_eq!(a.material, b.material);
+    }
+}
+
+#[test]
+fn singular_instance_transforms_are_rejected() {
+    let dir = test_dir("singular");
+    fs::write(dir.join("tri.obj"), "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+    let text = r#"(
+        camera: (look_from: (0.0, 1.0, 5.0), look_at: (0.0, 1.0, 0.0), vfov: 60.0),
+        materials: [],
+        meshes: [("tri", (path: "tri.obj"))],
+        objects: [
+            Instance(mesh: "tri", transform: (scale: (0.001, 0.001, 0.001))),
+            Instance(mesh: "tri", transform: (scale: (1.0, 0.0, 1.0))),
+        ],
+    )"#;
+
+    let err = scene_file::from_str(text, &dir).err().unwrap();
+    assert_eq!(err.to_string(), "objects[1].transform: must be invertible");
+}
+
+#[test]
+fn invalid_cameras_are_rejected() {
+    let cases = [
+        (
+            "vfov: 0.0",
+            "camera.vfov: must be between 0 and 180 degrees",
+        ),
+        (
+            "vfov: 60.0, projection: Orthographic(width: 0.0)",
+            "camera.projection: width and field of view must be positive",
+        ),
+        (
+            "vfov: 60.0, projection: Orthographic(width: -2.0)",
+            "camera.projection: width and field of view must be positive",
+        ),
+        (
+            "vfov: 60.0, projection: Cylindrical(fov: 0.0)",
+            "camera.projection: width and field of view must be positive",
+        ),
+    ];
+    for (camera, message) in cases {
+        let text = format!(
+            "(camera: (look_from: (0.0, 0.0, 1.0), look_at: (0.0, 0.0, 0.0), {camera}), materials: [])"
+        );
+        let err = scene_file::from_str(&text, &test_dir("camera"))
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), message, "{camera}");
+    }
+}
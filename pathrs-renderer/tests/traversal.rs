@@ -0,0 +1,97 @@
+use glam::{Vec3, vec3};
+use pathrs_renderer::{
+    Primitive, Ray,
+    geometry::{Sphere, Triangle},
+    material::Lambertian,
+    scene::Scene,
+};
+
+/// Random triangles and spheres in a box, each with its own material
+fn mixed_scene(rng: &mut fastrand::Rng) -> Scene {
+    let mut scene = Scene::default();
+    let point =
+        |rng: &mut fastrand::Rng| vec3(rng.f32(), rng.f32(), rng.f32()) * 20.0 - Vec3::splat(10.0);
+
+    let triangles: Vec<_> = (0..300)
+        .map(|i| {
+            let material = scene.add_material(format!("triangle {i}"), Lambertian::new(Vec3::ONE));
+            let v0 = point(rng);
+            let e1 = vec3(rng.f32(), rng.f32(), rng.f32()) * 3.0 - Vec3::splat(1.5);
+            let e2 = vec3(rng.f32(), rng.f32(), rng.f32()) * 3.0 - Vec3::splat(1.5);
+            Triangle::new(v0, v0 + e1, v0 + e2, material)
+        })
+        .collect();
+    scene.add_triangles("triangles", &triangles);
+
+    for i in 0..60 {
+        let material = scene.add_material(format!("sphere {i}"), Lambertian::new(Vec3::ONE));
+        let center = point(rng);
+        scene.add_sphere(Sphere::new(center, 0.2 + rng.f32(), material));
+    }
+
+    scene.build().unwrap();
+    scene
+}
+
+/// Closest hit found by testing every primitive, its `t`, primitive and
+/// material
+fn brute_force(scene: &Scene, ray: &Ray, tmin: f32, tmax: f32) -> Option<(f32, Primitive, u32)> {
+    let mut closest = None;
+    let mut tmax = tmax;
+
+    let triangles = scene.triangles();
+    for i in 0..triangles.len() {
+        if let Some(t) = triangles.intersect(i, ray, tmin, tmax) {
+            tmax = t;
+            closest = Some((t, Primitive::Triangle(i as u32), triangles.material(i)));
+        }
+    }
+    let spheres = scene.spheres();
+    for i in 0..spheres.len() {
+        if let Some(t) = spheres.intersect(i, ray, tmin, tmax) {
+            tmax = t;
+            closest = Some((t, Primitive::Sphere(i as u32), spheres.material(i)));
+        }
+    }
+
+    closest
+}
+
+#[test]
+fn bvh_traversal_matches_brute_force() {
+    let mut rng = fastrand::Rng::with_seed(3);
+    let scene = mixed_scene(&mut rng);
+
+    let mut hits = 0;
+    for _ in 0..5000 {
+        let origin = vec3(rng.f32(), rng.f32(), rng.f32()) * 30.0 - Vec3::splat(15.0);
+        let target = vec3(rng.f32(), rng.f32(), rng.f32()) * 20.0 - Vec3::splat(10.0);
+        let ray = Ray::new(origin, (target - origin).normalize(), 0.0);
+        let (tmin, tmax) = (1e-3, if rng.bool() { f32::INFINITY } else { 20.0 });
+
+        let found = scene.closest_hit(&ray, tmin, tmax);
+        let expected = brute_force(&scene, &ray, tmin, tmax);
+        match (&found, expected) {
+            (None, None) => {}
+            (Some(hit), Some((t, primitive, material))) => {
+                assert!((hit.t - t).abs() <= 1e-4 * t.max(1.0), "{} != {t}", hit.t);
+                assert_eq!(hit.primitive, primitive);
+                assert_eq!(hit.material, material);
+                hits += 1;
+            }
+            _ => panic!(
+                "BVH hit {:?}, brute force hit {expected:?}",
+                found.map(|h| (h.t, h.primitive))
+            ),
+        }
+
+        assert_eq!(
+            scene.occluded(&ray, tmin, tmax),
+            found.is_some_and(|h| h.t < tmax),
+            "ray from {origin} towards {target}"
+        );
+    }
+
+    // Enough rays hit something for the comparison to mean anything
+    assert!(hits > 1000, "{hits} hits");
+}
@@ -1,31 +1,57 @@
 use bevy::{
     diagnostic::FrameTimeDiagnosticsPlugin,
+    ecs::system::SystemParam,
     prelude::*,
     render::render_resource::{
         Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
     },
 };
 
-use crate::ui::{EguiViewport, init_ui, render_ui};
+use crate::inspector::SceneInspector;
+use crate::ui::{
+    EguiViewport, FocusPick, ViewportCamera, ViewportDenoise, ViewportLayer, init_ui, render_ui,
+};
 use bevy_egui::{EguiContexts, EguiPlugin};
 use crossbeam_channel::Sender;
 use pathrs_renderer::{
-    RenderResult, RenderSystem, RendererCmd, metrics::RendererMetrics, renderer::CPURenderer,
+    RenderResult, RenderSettings, RenderSystem, RendererCmd, camera::Camera, denoise::Denoiser,
+    display::DisplayTransform, metrics::RendererMetrics, renderer::CPURenderer, scene::Scene,
 };
 
-pub fn run_bevy_app() {
+pub fn run_bevy_app(scene: Scene, display: DisplayTransform) {
     App::new()
+        .insert_resource(InitialScene(Some(scene)))
+        .insert_resource(ViewTransform(display))
+        .init_resource::<ViewportLayer>()
+        .init_resource::<ViewportDenoise>()
+        .init_resource::<FocusPick>()
         .add_plugins(DefaultPlugins)
         .add_plugins(FrameTimeDiagnosticsPlugin)
         .add_plugins(EguiPlugin)
         .add_systems(Startup, (init_ui, init_renderer))
         .add_systems(
             Update,
-            (render_ui, resize_render_target, receive_render).chain(),
+            (
+                render_ui,
+                send_camera,
+                send_denoise,
+                send_aovs,
+                resize_render_target,
+                receive_render,
+            )
+                .chain(),
         )
         .run();
 }
 
+/// Scene handed to the render thread on startup
+#[derive(Resource)]
+struct InitialScene(Option<Scene>);
+
+/// Display transform applied to the rendered image before it is shown
+#[derive(Resource)]
+pub struct ViewTransform(pub DisplayTransform);
+
 #[derive(Resource)]
 struct RenderTarget {
     image_handle: Handle<Image>,
@@ -34,7 +60,7 @@ struct RenderTarget {
 
 #[derive(Resource)]
 pub struct RenderTask {
-    cmd_tx: Sender<RendererCmd>,
+    pub cmd_tx: Sender<RendererCmd>,
     output: triple_buffer::Output<RenderResult>,
     pub metrics: RendererMetrics,
 }
@@ -49,6 +75,7 @@ fn init_renderer(
     mut commands: Commands,
     mut images: ResMut<Assets<Image>>,
     mut contexts: EguiContexts,
+    mut scene: ResMut<InitialScene>,
 ) {
     let size = Extent3d {
         width: 100,
@@ -63,7 +90,9 @@ fn init_renderer(
             mip_level_count: 1,
             sample_count: 1,
             dimension: TextureDimension::D2,
-            format: TextureFormat::Rgba32Float,
+            // Filled with sRGB encoded colors, sampling decodes them to the
+            // linear values the egui shader expects
+            format: TextureFormat::Rgba8UnormSrgb,
             usage: TextureUsages::TEXTURE_BINDING
                 | TextureUsages::COPY_DST
                 | TextureUsages::RENDER_ATTACHMENT,
@@ -87,7 +116,14 @@ fn init_renderer(
         size,
     });
 
-    let (renderer, cmd_tx, out) = RenderSystem::<CPURenderer>::new(size.x, size.y);
+    let scene = scene.0.take().unwrap_or_default();
+    commands.insert_resource(ViewportCamera(scene.camera));
+    commands.insert_resource(SceneInspector::new(scene.summary()));
+
+    // The AOVs are only accumulated once the viewport needs them, see
+    // `send_aovs`
+    let settings = RenderSettings::default();
+    let (renderer, cmd_tx, out) = RenderSystem::<CPURenderer>::new(size.x, size.y, scene, settings);
 
     renderer.start_thread();
 
@@ -98,6 +134,36 @@ fn init_renderer(
     });
 }
 
+/// Hands camera moves made in the viewport to the render thread
+fn send_camera(render_task: Res<RenderTask>, camera: Res<ViewportCamera>) {
+    if camera.is_changed() && !camera.is_added() {
+        _ = render_task.cmd_tx.send(RendererCmd::SetCamera(camera.0));
+    }
+}
+
+/// Has the render thread filter its passes while the viewport shows them
+/// denoised
+fn send_denoise(render_task: Res<RenderTask>, denoise: Res<ViewportDenoise>) {
+    if denoise.is_changed() && !denoise.is_added() {
+        let denoiser = denoise.0.then(Denoiser::default);
+        _ = render_task.cmd_tx.send(RendererCmd::SetDenoise(denoiser));
+    }
+}
+
+/// Has the render thread accumulate the AOVs once the viewport shows one or
+/// needs the depth to focus, they stay on from then
+fn send_aovs(
+    render_task: Res<RenderTask>,
+    layer: Res<ViewportLayer>,
+    focus: Res<FocusPick>,
+    mut sent: Local<bool>,
+) {
+    if !*sent && (layer.0.is_some() || focus.0.is_some()) {
+        _ = render_task.cmd_tx.send(RendererCmd::EnableAovs);
+        *sent = true;
+    }
+}
+
 fn resize_render_target(
     render_task: Res<RenderTask>,
     mut render_target: ResMut<RenderTarget>,
@@ -121,22 +187,55 @@ fn resize_render_target(
     *last_size = egui_viewport.size;
 }
 
+/// Settings for what the viewport shows of the render result
+#[derive(SystemParam)]
+struct ViewportView<'w> {
+    transform: Res<'w, ViewTransform>,
+    layer: Res<'w, ViewportLayer>,
+    denoise: Res<'w, ViewportDenoise>,
+}
+
 fn receive_render(
     mut render_task: ResMut<RenderTask>,
     render_target: Res<RenderTarget>,
     mut egui_viewport: ResMut<EguiViewport>,
     mut images: ResMut<Assets<Image>>,
+    view: ViewportView,
+    (mut camera, mut focus): (ResMut<ViewportCamera>, ResMut<FocusPick>),
+    mut inspector: ResMut<SceneInspector>,
 ) {
     let RenderResult {
         image_data,
+        aovs,
+        denoised,
         image_size,
         render_pass_metrics,
+        scene_metrics,
+        scene,
     } = render_task.output.read();
 
     if image_size.x == 0 || image_size.y == 0 {
         return;
     }
 
+    inspector.update(scene);
+
+    // The preview passes leave the AOVs out, so a pick waits for the next
+    // full pass
+    if let Some(pick) = focus.0
+        && aovs.depth.len() == image_data.len()
+    {
+        focus.0 = None;
+        let x = ((pick.x * image_size.x as f32) as u32).min(image_size.x - 1);
+        let y = ((pick.y * image_size.y as f32) as u32).min(image_size.y - 1);
+        let depth = aovs.depth[(y * image_size.x + x) as usize];
+        // Nothing to focus on over the background
+        if depth.is_finite() {
+            let lens = Camera::new(&camera.0, *image_size);
+            camera.0.focus_distance = Some(lens.focus_distance_at(x as f32, y as f32, depth));
+        }
+    }
+
     let image = images.get_mut(&render_target.image_handle).unwrap();
 
     image.resize(Extent3d {
@@ -146,14 +245,22 @@ fn receive_render(
     });
     egui_viewport.texture.size = bevy_egui::egui::vec2(image_size.x as f32, image_size.y as f32);
 
-    let image_bytes = image_data
-        .iter()
-        .flatten()
-        .flat_map(|f| f.to_le_bytes())
+    let colors = match view.layer.0 {
+        Some(aov) if !aovs.is_empty() => aovs.preview(aov),
+        // The noisy image stands in until the first filtered one arrives
+        _ if view.denoise.0 && denoised.len() == image_data.len() => {
+            view.transform.0.apply(denoised)
+        }
+        _ => view.transform.0.apply(image_data),
+    };
+    let image_bytes = colors
+        .into_iter()
+        .flat_map(|[r, g, b]| [r, g, b, 1.0].map(|c| (c * 255.0).round() as u8))
         .collect();
 
     image.data = image_bytes;
 
-    let metrics = *render_pass_metrics;
+    let metrics = render_pass_metrics.clone();
+    render_task.metrics.scene = *scene_metrics;
     render_task.metrics.add_pass(metrics);
 }
@@ -0,0 +1,402 @@
+//! Scene inspector pane, lists the materials and objects of the scene and
+//! sends edits of them to the render thread
+
+use std::{path::Path, sync::Arc};
+
+use bevy::prelude::*;
+use bevy_egui::egui;
+use crossbeam_channel::Sender;
+use pathrs_renderer::{
+    RendererCmd,
+    camera::{CameraSettings, FisheyeMapping, Projection},
+    geometry::Sphere,
+    material::Material,
+    scene::{SceneEdit, SceneSummary},
+    scene_file,
+};
+
+/// Shows the summary of the scene the render thread last published, edits
+/// go to the render thread and show up once it applied them
+#[derive(Resource)]
+pub struct SceneInspector {
+    scene: Arc<SceneSummary>,
+    /// Scene file path typed into the inspector
+    path: String,
+    /// Warnings and errors of the last load or edit
+    messages: Vec<String>,
+}
+
+impl SceneInspector {
+    pub fn new(scene: SceneSummary) -> Self {
+        SceneInspector {
+            scene: Arc::new(scene),
+            path: String::new(),
+            messages: Vec::new(),
+        }
+    }
+
+    /// Takes the summary published with a render result, showing the edits
+    /// that failed since the last one
+    pub fn update(&mut self, scene: &Arc<SceneSummary>) {
+        if Arc::ptr_eq(&self.scene, scene) {
+            return;
+        }
+        if !scene.errors.is_empty() {
+            self.messages.clone_from(&scene.errors);
+        }
+        self.scene = Arc::clone(scene);
+    }
+
+    /// Loads the scene at the typed path and hands it to the render thread,
+    /// the camera moves to the one of the new scene
+    fn load(&mut self, cmd_tx: &Sender<RendererCmd>, camera: &mut CameraSettings) {
+        let path = self.path.trim();
+        match scene_file::load(Path::new(path)) {
+            Ok(loaded) => {
+                self.messages = loaded.approximations;
+                *camera = loaded.scene.camera;
+                _ = cmd_tx.send(RendererCmd::SetScene(Box::new(loaded.scene)));
+            }
+            Err(err) => self.messages = vec![format!("Error loading scene {path}: {err}")],
+        }
+    }
+
+    pub fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        cmd_tx: &Sender<RendererCmd>,
+        camera: &mut CameraSettings,
+    ) {
+        ui.horizontal(|ui| {
+            let field = ui.add(egui::TextEdit::singleline(&mut self.path).hint_text("scene file"));
+            let entered = field.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            if ui.button("Load").clicked() || entered {
+                self.load(cmd_tx, camera);
+            }
+        });
+        for message in &self.messages {
+            ui.label(message);
+        }
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            egui::CollapsingHeader::new("Camera")
+                .default_open(true)
+                .show(ui, |ui| camera_ui(ui, camera));
+            egui::CollapsingHeader::new("Materials")
+                .default_open(true)
+                .show(ui, |ui| self.materials_ui(ui, cmd_tx));
+            egui::CollapsingHeader::new("Spheres")
+                .default_open(true)
+                .show(ui, |ui| self.spheres_ui(ui, cmd_tx, camera));
+            egui::CollapsingHeader::new("Triangle groups")
+                .default_open(true)
+                .show(ui, |ui| self.groups_ui(ui, cmd_tx));
+        });
+    }
+
+    fn materials_ui(&self, ui: &mut egui::Ui, cmd_tx: &Sender<RendererCmd>) {
+        for index in 0..self.scene.materials.len() {
+            let mut material = self.scene.materials[index];
+            let name = &self.scene.material_names[index];
+
+            let changed = egui::CollapsingHeader::new(name)
+                .id_salt(("material", index))
+                .show(ui, |ui| material_ui(ui, &mut material))
+                .body_returned
+                .unwrap_or(false);
+
+            if changed {
+                let index = index as u32;
+                _ = cmd_tx.send(RendererCmd::Edit(SceneEdit::SetMaterial {
+                    index,
+                    material,
+                }));
+            }
+        }
+    }
+
+    fn spheres_ui(&self, ui: &mut egui::Ui, cmd_tx: &Sender<RendererCmd>, camera: &CameraSettings) {
+        let mut edits = Vec::new();
+
+        for (index, sphere) in self.scene.spheres.iter().enumerate() {
+            let mut center = sphere.center().to_array();
+            let mut motion = sphere.motion().to_array();
+            let mut radius = sphere.radius();
+            let mut material = sphere.material();
+            let name = &self.scene.material_names[material as usize];
+
+            egui::CollapsingHeader::new(format!("sphere {index} ({name})"))
+                .id_salt(("sphere", index))
+                .show(ui, |ui| {
+                    let mut changed = vec3_ui(ui, "center", &mut center, 0.1);
+                    changed |= vec3_ui(ui, "motion", &mut motion, 0.1);
+                    changed |= ui
+                        .horizontal(|ui| {
+                            ui.label("radius");
+                            ui.add(
+                                egui::DragValue::new(&mut radius)
+                                    .speed(0.05)
+                                    .range(0.01..=f32::MAX),
+                            )
+                            .changed()
+                        })
+                        .inner;
+                    changed |= material_combo(
+                        ui,
+                        &self.scene.material_names,
+                        ("sphere", index),
+                        &mut material,
+                    );
+
+                    if changed {
+                        let end = std::array::from_fn(|i| center[i] + motion[i]);
+                        let sphere = Sphere::moving(center.into(), end.into(), radius, material);
+                        edits.push(SceneEdit::SetSphere { index, sphere });
+                    }
+                    if ui.button("Remove").clicked() {
+                        edits.push(SceneEdit::RemoveSphere(index));
+                    }
+                });
+        }
+
+        // New spheres appear where the camera looks
+        if ui.button("Add sphere").clicked() && !self.scene.materials.is_empty() {
+            edits.push(SceneEdit::AddSphere(Sphere::new(camera.look_at, 1.0, 0)));
+        }
+
+        for edit in edits {
+            _ = cmd_tx.send(RendererCmd::Edit(edit));
+        }
+    }
+
+    fn groups_ui(&self, ui: &mut egui::Ui, cmd_tx: &Sender<RendererCmd>) {
+        let mut edits = Vec::new();
+
+        for (index, (name, count)) in self.scene.groups.iter().enumerate() {
+            egui::CollapsingHeader::new(format!("{name} ({count} triangles)"))
+                .id_salt(("group", index))
+                .show(ui, |ui| {
+                    // Dragging moves the group by the amount dragged
+                    let mut offset = [0.0; 3];
+                    if vec3_ui(ui, "move", &mut offset, 0.1) {
+                        let offset = offset.into();
+                        edits.push(SceneEdit::MoveTriangles { index, offset });
+                    }
+                    if ui.button("Remove").clicked() {
+                        edits.push(SceneEdit::RemoveTriangles(index));
+                    }
+                });
+        }
+
+        for edit in edits {
+            _ = cmd_tx.send(RendererCmd::Edit(edit));
+        }
+    }
+}
+
+/// Projection and lens settings of the camera, placement is left to the
+/// viewport controls
+fn camera_ui(ui: &mut egui::Ui, camera: &mut CameraSettings) {
+    // Motion runs from time zero to one
+    scalar_ui(
+        ui,
+        "shutter open",
+        &mut camera.shutter_open,
+        0.01,
+        0.0..=1.0,
+    );
+    scalar_ui(
+        ui,
+        "shutter close",
+        &mut camera.shutter_close,
+        0.01,
+        0.0..=1.0,
+    );
+
+    projection_ui(ui, &mut camera.projection);
+    if camera.projection != Projection::Perspective {
+        return;
+    }
+
+    scalar_ui(ui, "field of view", &mut camera.vfov, 0.1, 1.0..=179.0);
+    scalar_ui(ui, "aperture", &mut camera.aperture, 0.01, 0.0..=f32::MAX);
+
+    ui.horizontal(|ui| {
+        ui.label("focus distance");
+        let mut on_target = camera.focus_distance.is_none();
+        if ui.checkbox(&mut on_target, "target").changed() {
+            let target = (camera.look_at - camera.look_from).length();
+            camera.focus_distance = (!on_target).then_some(target);
+        }
+        if let Some(distance) = &mut camera.focus_distance {
+            ui.add(
+                egui::DragValue::new(distance)
+                    .speed(0.05)
+                    .range(0.01..=f32::MAX),
+            );
+        }
+    });
+
+    ui.horizontal(|ui| {
+        ui.label("blades");
+        ui.add(egui::DragValue::new(&mut camera.blades).range(0..=16));
+    });
+    if camera.blades >= 3 {
+        scalar_ui(
+            ui,
+            "blade rotation",
+            &mut camera.blade_rotation,
+            0.5,
+            -180.0..=180.0,
+        );
+    }
+}
+
+/// Combo box picking the kind of projection, followed by its parameters
+fn projection_ui(ui: &mut egui::Ui, projection: &mut Projection) {
+    let kinds = [
+        ("perspective", Projection::Perspective),
+        ("orthographic", Projection::Orthographic { width: 10.0 }),
+        ("equirectangular", Projection::Equirectangular),
+        (
+            "fisheye",
+            Projection::Fisheye {
+                fov: 180.0,
+                mapping: FisheyeMapping::Equidistant,
+            },
+        ),
+        ("cylindrical", Projection::Cylindrical { fov: 180.0 }),
+    ];
+    let current = std::mem::discriminant(projection);
+
+    ui.horizontal(|ui| {
+        ui.label("projection");
+        let (selected, _) = kinds
+            .iter()
+            .find(|(_, kind)| std::mem::discriminant(kind) == current)
+            .expect("every projection is listed");
+        egui::ComboBox::from_id_salt("projection")
+            .selected_text(*selected)
+            .show_ui(ui, |ui| {
+                for (name, kind) in kinds {
+                    let is_current = std::mem::discriminant(&kind) == current;
+                    if ui.selectable_label(is_current, name).clicked() && !is_current {
+                        *projection = kind;
+                    }
+                }
+            });
+    });
+
+    match projection {
+        Projection::Perspective | Projection::Equirectangular => {}
+        Projection::Orthographic { width } => {
+            scalar_ui(ui, "width", width, 0.05, 0.01..=f32::MAX);
+        }
+        Projection::Fisheye { fov, mapping } => {
+            scalar_ui(ui, "field of view", fov, 0.5, 1.0..=360.0);
+            ui.horizontal(|ui| {
+                ui.label("mapping");
+                ui.selectable_value(mapping, FisheyeMapping::Equidistant, "equidistant");
+                ui.selectable_value(mapping, FisheyeMapping::Equisolid, "equisolid");
+            });
+        }
+        Projection::Cylindrical { fov } => {
+            scalar_ui(ui, "field of view", fov, 0.5, 1.0..=360.0);
+        }
+    }
+}
+
+/// Fields of `material`, true if any changed
+fn material_ui(ui: &mut egui::Ui, material: &mut Material) -> bool {
+    match material {
+        Material::Lambertian(l) => {
+            ui.label("Lambertian");
+            let mut albedo = l.albedo.to_array();
+            let changed = color_ui(ui, "albedo", &mut albedo);
+            l.albedo = albedo.into();
+            changed
+        }
+        Material::Metal(m) => {
+            ui.label("Metal");
+            let mut albedo = m.albedo.to_array();
+            let changed = color_ui(ui, "albedo", &mut albedo);
+            m.albedo = albedo.into();
+            changed | scalar_ui(ui, "fuzz", &mut m.fuzz, 0.01, 0.0..=1.0)
+        }
+        Material::Dielectric(d) => {
+            ui.label("Dielectric");
+            scalar_ui(
+                ui,
+                "refraction index",
+                &mut d.refraction_index,
+                0.01,
+                1.0..=3.0,
+            )
+        }
+        Material::DiffuseLight(dl) => {
+            ui.label("Diffuse light");
+            // Emission goes past one, so no color picker
+            let mut emitted = dl.emitted.to_array();
+            let changed = vec3_ui(ui, "emitted", &mut emitted, 0.05);
+            dl.emitted = emitted.map(|c| c.max(0.0)).into();
+            changed
+        }
+    }
+}
+
+/// Linear RGB color picker
+fn color_ui(ui: &mut egui::Ui, label: &str, value: &mut [f32; 3]) -> bool {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        ui.color_edit_button_rgb(value).changed()
+    })
+    .inner
+}
+
+fn scalar_ui(
+    ui: &mut egui::Ui,
+    label: &str,
+    value: &mut f32,
+    speed: f32,
+    range: std::ops::RangeInclusive<f32>,
+) -> bool {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        ui.add(egui::DragValue::new(value).speed(speed).range(range))
+            .changed()
+    })
+    .inner
+}
+
+/// Drag values for the three components, true if any changed
+fn vec3_ui(ui: &mut egui::Ui, label: &str, value: &mut [f32; 3], speed: f32) -> bool {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        let mut changed = false;
+        for (c, prefix) in value.iter_mut().zip(["x ", "y ", "z "]) {
+            changed |= ui
+                .add(egui::DragValue::new(c).speed(speed).prefix(prefix))
+                .changed();
+        }
+        changed
+    })
+    .inner
+}
+
+/// Combo box picking one of the scene's materials by name
+fn material_combo(
+    ui: &mut egui::Ui,
+    names: &[String],
+    id: impl std::hash::Hash,
+    material: &mut u32,
+) -> bool {
+    let before = *material;
+    egui::ComboBox::from_id_salt(id)
+        .selected_text(&names[*material as usize])
+        .show_ui(ui, |ui| {
+            for (i, name) in names.iter().enumerate() {
+                ui.selectable_value(material, i as u32, name);
+            }
+        });
+    *material != before
+}
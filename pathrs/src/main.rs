@@ -1,9 +1,22 @@
+use std::{path::PathBuf, process::ExitCode, time::Duration};
+
 use clap::{Parser, Subcommand};
-use pathrs_renderer::{RenderSystem, renderer::CPURenderer};
-use ppm::write_ppm_file;
+use output::{ExrCompression, ExrPrecision, Format, PngDepth, write_image};
+use pathrs_renderer::{
+    Adaptive, RenderSettings, RenderSystem, StopCondition,
+    aov::Aovs,
+    camera::Projection,
+    denoise::Denoiser,
+    display::{DisplayTransform, NEUTRAL_TEMPERATURE, ToneCurve},
+    environment::{Environment, EnvironmentMap},
+    renderer::CPURenderer,
+    scene::{Scene, test_scene},
+    scene_file,
+};
 
 mod app;
-mod ppm;
+mod inspector;
+mod output;
 mod ui;
 
 #[derive(Parser)]
@@ -14,29 +27,310 @@ struct Args {
 
 #[derive(Clone, Subcommand)]
 enum Command {
-    Run,
+    Run {
+        /// Scene file (.ron, .gltf or .glb) to open, the built-in test scene
+        /// is used when unset
+        #[arg(long)]
+        scene: Option<PathBuf>,
+
+        #[command(flatten)]
+        projection: ProjectionArg,
+
+        #[command(flatten)]
+        display: DisplayArgs,
+    },
     RenderImage {
         width: u32,
         height: u32,
+        /// Samples per pixel, the average with --noise
         samples_per_pixel: u32,
+
+        /// Scene file (.ron, .gltf or .glb) to render, the built-in test
+        /// scene is used when unset
+        #[arg(long)]
+        scene: Option<PathBuf>,
+
+        #[command(flatten)]
+        projection: ProjectionArg,
+
+        /// Image file to write, the format is picked from the extension:
+        /// .ppm, .png, .pfm or .exr
+        #[arg(short, long, default_value = "out.ppm")]
+        output: PathBuf,
+
+        /// Bits per channel of PNG output
+        #[arg(long, value_enum, default_value_t)]
+        png_depth: PngDepth,
+
+        /// Sample type of EXR output
+        #[arg(long, value_enum, default_value_t)]
+        exr_precision: ExrPrecision,
+
+        /// Compression of EXR output
+        #[arg(long, value_enum, default_value_t)]
+        exr_compression: ExrCompression,
+
+        #[command(flatten)]
+        display: DisplayArgs,
+
+        /// Render the first hit AOVs (albedo, normal, depth, position,
+        /// material and primitive ids) into EXR output
+        #[arg(long)]
+        aovs: bool,
+
+        /// Filter the noise out of the finished image, guided by the
+        /// albedo and normal AOVs
+        #[arg(long)]
+        denoise: bool,
+
+        /// Stop sampling pixels once their relative error is below this,
+        /// every pixel gets all samples when unset
+        #[arg(short, long)]
+        noise: Option<f32>,
+
+        /// Stop rendering after this many seconds
+        #[arg(long, value_parser = parse_seconds)]
+        time: Option<Duration>,
+
+        /// Number of render threads, defaults to the available parallelism
+        #[arg(short, long)]
+        threads: Option<usize>,
+
+        /// Seed for the random sampling, equal seeds produce identical images
+        #[arg(short, long)]
+        seed: Option<u64>,
+
+        /// Maximum number of diffuse bounces per path
+        #[arg(long)]
+        diffuse_bounces: Option<u32>,
+
+        /// Maximum number of specular reflections per path
+        #[arg(long)]
+        specular_bounces: Option<u32>,
+
+        /// Maximum number of refractions per path
+        #[arg(long)]
+        transmission_bounces: Option<u32>,
+
+        /// Bounces before Russian roulette may terminate a path
+        #[arg(long)]
+        roulette_depth: Option<u32>,
+
+        /// Equirectangular Radiance .hdr image lighting the scene, replaces
+        /// the background of the scene when set
+        #[arg(short, long)]
+        environment: Option<PathBuf>,
+
+        /// Rotation of the environment around the up axis, in degrees
+        #[arg(long, default_value_t = 0.0)]
+        environment_rotation: f32,
+
+        /// Multiplier for the environment radiance
+        #[arg(long, default_value_t = 1.0)]
+        environment_intensity: f32,
     },
 }
 
-fn main() {
+/// Override of the scene camera's projection
+#[derive(Clone, clap::Args)]
+struct ProjectionArg {
+    /// Camera projection replacing the one of the scene: perspective,
+    /// orthographic:WIDTH, equirectangular, fisheye:FOV, equisolid:FOV or
+    /// cylindrical:FOV, with the fields of view in degrees
+    #[arg(long)]
+    projection: Option<Projection>,
+}
+
+impl ProjectionArg {
+    fn apply(&self, scene: &mut Scene) {
+        if let Some(projection) = self.projection {
+            scene.camera.projection = projection;
+        }
+    }
+}
+
+/// Display transform of the viewport and the PPM and PNG output
+#[derive(Clone, clap::Args)]
+struct DisplayArgs {
+    /// Exposure adjustment in stops
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    exposure: f32,
+
+    /// Color temperature in kelvin that appears white
+    #[arg(long, default_value_t = NEUTRAL_TEMPERATURE)]
+    white_balance: f32,
+
+    /// Curve compressing highlights: clamp, reinhard, aces, agx or filmic
+    #[arg(long, default_value_t)]
+    tone_curve: ToneCurve,
+}
+
+impl DisplayArgs {
+    fn transform(&self) -> DisplayTransform {
+        DisplayTransform {
+            exposure: self.exposure,
+            white_balance: self.white_balance,
+            tone_curve: self.tone_curve,
+        }
+    }
+}
+
+fn main() -> ExitCode {
     let args = Args::parse();
 
     match args.command {
-        Command::Run => app::run_bevy_app(),
+        Command::Run {
+            scene,
+            projection,
+            display,
+        } => {
+            let Some(mut scene) = load_scene(scene) else {
+                return ExitCode::FAILURE;
+            };
+            projection.apply(&mut scene);
+            app::run_bevy_app(scene, display.transform());
+            ExitCode::SUCCESS
+        }
         Command::RenderImage {
             width,
             height,
             samples_per_pixel,
-        } => render_image(width, height, samples_per_pixel),
+            scene,
+            projection,
+            output,
+            png_depth,
+            exr_precision,
+            exr_compression,
+            display,
+            aovs,
+            denoise,
+            noise,
+            time,
+            threads,
+            seed,
+            diffuse_bounces,
+            specular_bounces,
+            transmission_bounces,
+            roulette_depth,
+            environment,
+            environment_rotation,
+            environment_intensity,
+        } => {
+            let Some(format) =
+                Format::from_path(&output, png_depth, exr_precision, exr_compression)
+            else {
+                eprintln!(
+                    "Unsupported output format {}, expected .ppm, .png, .pfm or .exr",
+                    output.display()
+                );
+                return ExitCode::FAILURE;
+            };
+            if aovs && !matches!(format, Format::Exr(..)) {
+                eprintln!("warning: AOVs are only written to .exr files");
+            }
+
+            let mut settings = RenderSettings {
+                seed,
+                aovs,
+                denoise: denoise.then(Denoiser::default),
+                adaptive: noise.map(|threshold| Adaptive {
+                    threshold,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            if let Some(threads) = threads {
+                settings.threads = threads;
+            }
+
+            let bounces = &mut settings.bounces;
+            bounces.diffuse = diffuse_bounces.unwrap_or(bounces.diffuse);
+            bounces.specular = specular_bounces.unwrap_or(bounces.specular);
+            bounces.transmission = transmission_bounces.unwrap_or(bounces.transmission);
+            bounces.roulette_depth = roulette_depth.unwrap_or(bounces.roulette_depth);
+
+            let stop = StopCondition {
+                samples: samples_per_pixel,
+                time,
+            };
+
+            let Some(mut scene) = load_scene(scene) else {
+                return ExitCode::FAILURE;
+            };
+            projection.apply(&mut scene);
+
+            if let Some(path) = environment {
+                match EnvironmentMap::load(&path) {
+                    Ok(map) => {
+                        scene.environment = Environment::Map(Box::new(
+                            map.with_rotation(environment_rotation)
+                                .with_intensity(environment_intensity),
+                        ))
+                    }
+                    Err(err) => {
+                        eprintln!("Error loading environment {}: {err}", path.display());
+                        return ExitCode::FAILURE;
+                    }
+                }
+            }
+
+            let output = ImageOutput {
+                path: output,
+                format,
+                display: display.transform(),
+            };
+            render_image(width, height, stop, scene, settings, &output)
+        }
     }
 }
 
+/// Loads the scene file at `path`, or the test scene without one. Errors are
+/// printed and give `None`, approximations made importing it are printed
+/// as warnings.
+fn load_scene(path: Option<PathBuf>) -> Option<Scene> {
+    let Some(path) = path else {
+        return Some(test_scene());
+    };
+
+    match scene_file::load(&path) {
+        Ok(loaded) => {
+            for note in &loaded.approximations {
+                eprintln!("warning: {note}");
+            }
+            Some(loaded.scene)
+        }
+        Err(err) => {
+            eprintln!("Error loading scene {}: {err}", path.display());
+            None
+        }
+    }
+}
+
+/// Parses a positive, finite number of seconds
+fn parse_seconds(arg: &str) -> Result<Duration, String> {
+    let seconds: f32 = arg.parse().map_err(|err| format!("{err}"))?;
+    if seconds <= 0.0 {
+        return Err("expected a positive number of seconds".into());
+    }
+    Duration::try_from_secs_f32(seconds).map_err(|err| err.to_string())
+}
+
+/// Image file `render-image` writes its result to
+struct ImageOutput {
+    path: PathBuf,
+    format: Format,
+    display: DisplayTransform,
+}
+
 #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
-fn render_image(width: u32, height: u32, samples_per_pixel: u32) {
+fn render_image(
+    width: u32,
+    height: u32,
+    stop: StopCondition,
+    scene: Scene,
+    settings: RenderSettings,
+    output: &ImageOutput,
+) -> ExitCode {
     #[cfg(feature = "tracing")]
     use tracing_subscriber::prelude::*;
     #[cfg(feature = "tracing")]
@@ -44,11 +338,33 @@ fn render_image(width: u32, height: u32, samples_per_pixel: u32) {
     #[cfg(feature = "tracing")]
     tracing_subscriber::registry().with(chrome_layer).init();
 
-    let (renderer, _, _) = RenderSystem::<CPURenderer>::new(width, height);
+    println!("triangle BVH: {}", scene.metrics.triangle_bvh);
+    println!("sphere BVH: {}", scene.metrics.sphere_bvh);
+    println!("instance BVH: {}", scene.metrics.instance_bvh);
+
+    let (renderer, _, _) = RenderSystem::<CPURenderer>::new(width, height, scene, settings);
+    println!("seed: {}", renderer.seed());
 
-    let img = renderer.render_image(samples_per_pixel);
+    let aovs_requested = settings.aovs;
+    let (img, mut aovs) = renderer.render_image(stop);
+    // Denoising renders the AOVs it needs, they're only written on request
+    if !aovs_requested {
+        aovs = Aovs::default();
+    }
 
-    if let Err(err) = write_ppm_file(&img, width, height) {
-        eprintln!("Error writing ppm file: {err}");
+    let ImageOutput {
+        path,
+        format,
+        display,
+    } = output;
+    match write_image(path, *format, display, &img, &aovs, width, height) {
+        Ok(()) => {
+            println!("wrote {format} {}", path.display());
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("Error writing {}: {err}", path.display());
+            ExitCode::FAILURE
+        }
     }
 }
@@ -0,0 +1,99 @@
+use std::{io, path::Path};
+
+use ::exr::{
+    error::Error,
+    prelude::{
+        AnyChannel, AnyChannels, Blocks, Compression, Encoding, FlatSamples, Image, Layer,
+        LayerAttributes, LineOrder, SmallVec, WritableImage, f16,
+    },
+};
+use pathrs_renderer::aov::{Aov, Aovs};
+
+use super::{ExrCompression, ExrPrecision};
+
+/// Single layer scan line file with the RGBA beauty image. AOVs are added
+/// as channels prefixed with their name, like `normal.X`. Depth, position
+/// and variance are always stored as floats, ids as unsigned integers.
+pub fn write_exr_file(
+    path: &Path,
+    buf: &[[f32; 4]],
+    aovs: &Aovs,
+    width: u32,
+    height: u32,
+    precision: ExrPrecision,
+    compression: ExrCompression,
+) -> io::Result<()> {
+    let encoding = Encoding {
+        compression: match compression {
+            ExrCompression::None => Compression::Uncompressed,
+            ExrCompression::Zip => Compression::ZIP16,
+        },
+        blocks: Blocks::ScanLines,
+        line_order: LineOrder::Increasing,
+    };
+
+    let samples = |values: Vec<f32>| match precision {
+        ExrPrecision::Half => FlatSamples::F16(values.into_iter().map(f16::from_f32).collect()),
+        ExrPrecision::Float => FlatSamples::F32(values),
+    };
+
+    let mut channels: SmallVec<[AnyChannel<FlatSamples>; 4]> = SmallVec::new();
+    for (c, name) in ["R", "G", "B", "A"].into_iter().enumerate() {
+        let values = buf.iter().map(|v| v[c]).collect();
+        channels.push(AnyChannel::new(name, samples(values)));
+    }
+
+    if !aovs.is_empty() {
+        // Position needs the full precision to be of any use
+        let vectors = [
+            (Aov::Albedo, &aovs.albedo, ["R", "G", "B"], true),
+            (Aov::Normal, &aovs.normal, ["X", "Y", "Z"], true),
+            (Aov::Position, &aovs.position, ["X", "Y", "Z"], false),
+        ];
+        for (aov, values, names, reduced) in vectors {
+            for (c, name) in names.into_iter().enumerate() {
+                let values: Vec<f32> = values.iter().map(|v| v[c]).collect();
+                let values = if reduced {
+                    samples(values)
+                } else {
+                    FlatSamples::F32(values)
+                };
+                channels.push(AnyChannel::new(format!("{aov}.{name}").as_str(), values));
+            }
+        }
+
+        channels.push(AnyChannel::new(
+            format!("{}.Z", Aov::Depth).as_str(),
+            FlatSamples::F32(aovs.depth.clone()),
+        ));
+        channels.push(AnyChannel::new(
+            format!("{}.Y", Aov::Variance).as_str(),
+            FlatSamples::F32(aovs.variance.clone()),
+        ));
+        for (aov, ids) in [
+            (Aov::MaterialId, &aovs.material_id),
+            (Aov::PrimitiveId, &aovs.primitive_id),
+        ] {
+            channels.push(AnyChannel::new(
+                format!("{aov}.id").as_str(),
+                FlatSamples::U32(ids.clone()),
+            ));
+        }
+    }
+
+    let size = (width as usize, height as usize);
+    let layer = Layer::new(
+        size,
+        LayerAttributes::default(),
+        encoding,
+        AnyChannels::sort(channels),
+    );
+
+    Image::from_layer(layer)
+        .write()
+        .to_file(path)
+        .map_err(|err| match err {
+            Error::Io(err) => err,
+            err => io::Error::other(err),
+        })
+}
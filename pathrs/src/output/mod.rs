@@ -0,0 +1,122 @@
+//! Writing rendered images, the format is picked from the file extension
+
+use std::{fmt, io, path::Path};
+
+use clap::ValueEnum;
+use pathrs_renderer::{aov::Aovs, display::DisplayTransform};
+
+mod exr;
+mod pfm;
+mod png;
+mod ppm;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum PngDepth {
+    #[default]
+    #[value(name = "8")]
+    Eight,
+    #[value(name = "16")]
+    Sixteen,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ExrPrecision {
+    #[default]
+    Half,
+    Float,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ExrCompression {
+    None,
+    #[default]
+    Zip,
+}
+
+/// Image file format. PPM and PNG are display referred and go through the
+/// display transform, PFM and EXR keep the linear radiance as rendered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// ASCII `P3`, 8 bits per channel
+    Ppm,
+    Png(PngDepth),
+    /// Little endian RGB floats, alpha is dropped
+    Pfm,
+    Exr(ExrPrecision, ExrCompression),
+}
+
+impl Format {
+    /// Format for the extension of `path`, `png` and `exr` with the given
+    /// options. `None` for an unknown extension.
+    pub fn from_path(
+        path: &Path,
+        png_depth: PngDepth,
+        exr_precision: ExrPrecision,
+        exr_compression: ExrCompression,
+    ) -> Option<Format> {
+        let extension = path.extension()?.to_string_lossy().to_ascii_lowercase();
+        match extension.as_str() {
+            "ppm" => Some(Format::Ppm),
+            "png" => Some(Format::Png(png_depth)),
+            "pfm" => Some(Format::Pfm),
+            "exr" => Some(Format::Exr(exr_precision, exr_compression)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Ppm => write!(f, "PPM"),
+            Format::Png(PngDepth::Eight) => write!(f, "8-bit PNG"),
+            Format::Png(PngDepth::Sixteen) => write!(f, "16-bit PNG"),
+            Format::Pfm => write!(f, "PFM"),
+            Format::Exr(precision, compression) => {
+                let precision = match precision {
+                    ExrPrecision::Half => "half",
+                    ExrPrecision::Float => "float",
+                };
+                let compression = match compression {
+                    ExrCompression::None => "uncompressed",
+                    ExrCompression::Zip => "ZIP",
+                };
+                write!(f, "{compression} {precision} EXR")
+            }
+        }
+    }
+}
+
+/// Writes the linear RGBA pixels `buf`, rows top to bottom, to `path`.
+/// `display` maps them to the PPM and PNG output, `aovs` are only written
+/// to EXR files.
+pub fn write_image(
+    path: &Path,
+    format: Format,
+    display: &DisplayTransform,
+    buf: &[[f32; 4]],
+    aovs: &Aovs,
+    width: u32,
+    height: u32,
+) -> io::Result<()> {
+    assert_eq!(buf.len(), width as usize * height as usize);
+
+    match format {
+        Format::Ppm => ppm::write_ppm_file(path, &display.apply(buf), width, height),
+        Format::Png(depth) => png::write_png_file(path, &display.apply(buf), width, height, depth),
+        Format::Pfm => pfm::write_pfm_file(path, buf, width, height),
+        Format::Exr(precision, compression) => {
+            exr::write_exr_file(path, buf, aovs, width, height, precision, compression)
+        }
+    }
+}
+
+/// Display encoded colors quantized to 8 bits
+fn quantize8(c: [f32; 3]) -> [u8; 3] {
+    c.map(|c| (c * 255.0).round() as u8)
+}
+
+/// Display encoded colors quantized to 16 bits
+fn quantize16(c: [f32; 3]) -> [u16; 3] {
+    c.map(|c| (c * 65535.0).round() as u16)
+}
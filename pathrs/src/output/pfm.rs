@@ -0,0 +1,29 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+/// Color `PF` file, the negative scale marks the floats as little endian
+pub fn write_pfm_file(
+    path: &Path,
+    buf: &[[f32; 4]],
+    width: u32,
+    height: u32,
+) -> std::io::Result<()> {
+    let file = File::create(path)?;
+    let mut writer = BufWriter::new(file);
+
+    write!(&mut writer, "PF\n{width} {height}\n-1.0\n")?;
+
+    // Rows are stored bottom to top
+    for row in buf.chunks_exact(width as usize).rev() {
+        for v in row {
+            for c in &v[..3] {
+                writer.write_all(&c.to_le_bytes())?;
+            }
+        }
+    }
+
+    writer.flush()
+}
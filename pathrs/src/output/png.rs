@@ -0,0 +1,39 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use ::png::{BitDepth, ColorType, Encoder, SrgbRenderingIntent};
+
+use super::{PngDepth, quantize8, quantize16};
+
+/// RGB PNG of display encoded colors, tagged as sRGB
+pub fn write_png_file(
+    path: &Path,
+    buf: &[[f32; 3]],
+    width: u32,
+    height: u32,
+    depth: PngDepth,
+) -> std::io::Result<()> {
+    let file = File::create(path)?;
+    let mut encoder = Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(ColorType::Rgb);
+    encoder.set_source_srgb(SrgbRenderingIntent::Perceptual);
+
+    let data: Vec<u8> = match depth {
+        PngDepth::Eight => {
+            encoder.set_depth(BitDepth::Eight);
+            buf.iter().flat_map(|&v| quantize8(v)).collect()
+        }
+        PngDepth::Sixteen => {
+            // 16-bit samples are big endian
+            encoder.set_depth(BitDepth::Sixteen);
+            buf.iter()
+                .flat_map(|&v| quantize16(v))
+                .flat_map(u16::to_be_bytes)
+                .collect()
+        }
+    };
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+    Ok(())
+}
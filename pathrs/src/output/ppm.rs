@@ -0,0 +1,26 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use super::quantize8;
+
+pub fn write_ppm_file(
+    path: &Path,
+    buf: &[[f32; 3]],
+    width: u32,
+    height: u32,
+) -> std::io::Result<()> {
+    let file = File::create(path)?;
+    let mut writer = BufWriter::new(file);
+
+    write!(&mut writer, "P3\n{width} {height}\n255\n")?;
+
+    for &v in buf {
+        let [ir, ig, ib] = quantize8(v);
+        writeln!(&mut writer, "{ir} {ig} {ib}")?;
+    }
+
+    writer.flush()
+}
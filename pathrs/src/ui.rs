@@ -1,13 +1,15 @@
 use bevy::{
     diagnostic::{Diagnostic, DiagnosticsStore, FrameTimeDiagnosticsPlugin},
-    math::uvec2,
+    ecs::system::SystemParam,
+    math::{uvec2, vec2},
     prelude::*,
 };
 use bevy_egui::{EguiContexts, egui};
+use crossbeam_channel::Sender;
 use egui_tiles::{Container, Linear, LinearDir, Tile, TileId, Tiles, Tree, UiResponse};
-use pathrs_renderer::metrics::RendererMetrics;
+use pathrs_renderer::{RendererCmd, aov::Aov, camera::CameraSettings, metrics::RendererMetrics};
 
-use crate::app::RenderTask;
+use crate::{app::RenderTask, inspector::SceneInspector};
 
 #[derive(Resource)]
 pub struct UiState {
@@ -20,16 +22,40 @@ pub struct EguiViewport {
     pub size: UVec2,
 }
 
+/// What the viewport shows, the beauty image or one of the AOVs
+#[derive(Resource, Default)]
+pub struct ViewportLayer(pub Option<Aov>);
+
+/// Camera the viewport controls move, sent to the render thread whenever it
+/// changes
+#[derive(Resource, PartialEq)]
+pub struct ViewportCamera(pub CameraSettings);
+
+/// Whether the viewport shows the beauty image denoised, sampling goes on
+/// with the noisy image either way
+#[derive(Resource, Default)]
+pub struct ViewportDenoise(pub bool);
+
+/// Point of the viewport image clicked to focus on, as a fraction of the
+/// image size, until the depth AOV under it arrives
+#[derive(Resource, Default)]
+pub struct FocusPick(pub Option<Vec2>);
+
 enum Pane {
     Viewport,
     Performance,
+    Inspector,
 }
 
 pub fn init_ui(mut commands: Commands) {
     let mut tiles = Tiles::default();
+    let side = vec![
+        tiles.insert_pane(Pane::Inspector),
+        tiles.insert_pane(Pane::Performance),
+    ];
     let panes = [
         tiles.insert_pane(Pane::Viewport),
-        tiles.insert_pane(Pane::Performance),
+        tiles.insert_tab_tile(side),
     ];
     let container = Tile::Container(Container::Linear(Linear::new_binary(
         LinearDir::Horizontal,
@@ -43,15 +69,33 @@ pub fn init_ui(mut commands: Commands) {
     commands.insert_resource(UiState { tree });
 }
 
+/// Viewport settings edited in the viewport pane
+#[derive(SystemParam)]
+pub struct ViewportSettings<'w> {
+    layer: ResMut<'w, ViewportLayer>,
+    denoise: ResMut<'w, ViewportDenoise>,
+    camera: ResMut<'w, ViewportCamera>,
+    focus: ResMut<'w, FocusPick>,
+}
+
 pub fn render_ui(
     mut contexts: EguiContexts,
     mut ui_state: ResMut<UiState>,
     mut viewport: ResMut<EguiViewport>,
+    mut settings: ViewportSettings,
+    mut inspector: ResMut<SceneInspector>,
     diagnostics: Res<DiagnosticsStore>,
     render_task: Res<RenderTask>,
 ) {
+    let mut camera = settings.camera.0;
     let mut tab_behavior = TabBehavior {
         viewport: &mut viewport,
+        layer: &mut settings.layer,
+        denoise: &mut settings.denoise,
+        camera: &mut camera,
+        focus: &mut settings.focus,
+        inspector: &mut inspector,
+        cmd_tx: &render_task.cmd_tx,
         diagnostics: &diagnostics,
         renderer_metrics: &render_task.metrics,
     };
@@ -59,10 +103,19 @@ pub fn render_ui(
     egui::CentralPanel::default().show(contexts.ctx_mut(), |ui| {
         ui_state.tree.ui(&mut tab_behavior, ui)
     });
+
+    // Only an actual move restarts the render
+    settings.camera.set_if_neq(ViewportCamera(camera));
 }
 
 struct TabBehavior<'a> {
     viewport: &'a mut EguiViewport,
+    layer: &'a mut ViewportLayer,
+    denoise: &'a mut ViewportDenoise,
+    camera: &'a mut CameraSettings,
+    focus: &'a mut FocusPick,
+    inspector: &'a mut SceneInspector,
+    cmd_tx: &'a Sender<RendererCmd>,
     diagnostics: &'a DiagnosticsStore,
     renderer_metrics: &'a RendererMetrics,
 }
@@ -72,6 +125,7 @@ impl egui_tiles::Behavior<Pane> for TabBehavior<'_> {
         match pane {
             Pane::Viewport => "Viewport".into(),
             Pane::Performance => "Performance".into(),
+            Pane::Inspector => "Scene".into(),
         }
     }
 
@@ -85,16 +139,37 @@ impl egui_tiles::Behavior<Pane> for TabBehavior<'_> {
     fn pane_ui(&mut self, ui: &mut egui::Ui, _tile_id: TileId, pane: &mut Pane) -> UiResponse {
         match pane {
             Pane::Viewport => {
+                ui.horizontal(|ui| {
+                    let layer_name = |layer: Option<Aov>| layer.map_or("beauty", Aov::name);
+                    egui::ComboBox::from_id_salt("viewport_layer")
+                        .selected_text(layer_name(self.layer.0))
+                        .show_ui(ui, |ui| {
+                            for layer in [None].into_iter().chain(Aov::ALL.map(Some)) {
+                                ui.selectable_value(&mut self.layer.0, layer, layer_name(layer));
+                            }
+                        });
+
+                    // Only touch the resource on clicks, the viewport redoes
+                    // the filtering when it changes
+                    let mut denoise = self.denoise.0;
+                    if ui.checkbox(&mut denoise, "Denoise").changed() {
+                        self.denoise.0 = denoise;
+                    }
+                });
+
                 let available_size = ui.available_size();
                 self.viewport.size = uvec2(
                     available_size.x.ceil() as u32,
                     available_size.y.ceil() as u32,
                 );
 
-                let image = egui::Image::from_texture(self.viewport.texture);
+                let image = egui::Image::from_texture(self.viewport.texture)
+                    .sense(egui::Sense::click_and_drag());
 
-                ui.add(image);
+                let response = ui.add(image);
+                camera_controls(ui, &response, self.camera, self.focus);
             }
+            Pane::Inspector => self.inspector.ui(ui, self.cmd_tx, self.camera),
             Pane::Performance => {
                 if let Some(fps) = self
                     .diagnostics
@@ -112,6 +187,11 @@ impl egui_tiles::Behavior<Pane> for TabBehavior<'_> {
                     ui.label(format!("frame-time: {frame_time:#.2} ms"));
                 }
 
+                let scene = &self.renderer_metrics.scene;
+                ui.label(format!("Triangle BVH: {}", scene.triangle_bvh));
+                ui.label(format!("Sphere BVH: {}", scene.sphere_bvh));
+                ui.label(format!("Instance BVH: {}", scene.instance_bvh));
+
                 let passes = self.renderer_metrics.capacity;
                 ui.label("Render time:");
                 {
@@ -217,6 +297,10 @@ impl egui_tiles::Behavior<Pane> for TabBehavior<'_> {
                             plot_ui.text(avg_label);
                         });
                 }
+                ui.label(format!(
+                    "Shadow rays: {:.0}%",
+                    self.renderer_metrics.shadow_ray_share() * 100.0
+                ));
 
                 ui.label("Ray depths:");
                 let histogram = self.renderer_metrics.average_depth_histogram();
@@ -243,7 +327,7 @@ impl egui_tiles::Behavior<Pane> for TabBehavior<'_> {
                         .include_y(0.0)
                         .include_y(1.0)
                         .include_x(0.0)
-                        .include_x(10.0)
+                        .include_x(histogram.len().saturating_sub(1) as f64)
                         .show_axes([false, false])
                         .show(ui, |plot_ui| {
                             plot_ui.bar_chart(chart);
@@ -255,3 +339,66 @@ impl egui_tiles::Behavior<Pane> for TabBehavior<'_> {
         Default::default()
     }
 }
+
+/// Moves `camera` with the mouse and keyboard input on the viewport image:
+/// dragging orbits around the target, right dragging looks around, middle
+/// or shift dragging pans and scrolling dollies. WASD flies while the
+/// pointer is over the image, Q and E move down and up. Clicking picks the
+/// surface under the pointer to focus on.
+fn camera_controls(
+    ui: &egui::Ui,
+    response: &egui::Response,
+    camera: &mut CameraSettings,
+    focus: &mut FocusPick,
+) {
+    if response.clicked()
+        && let Some(pos) = response.interact_pointer_pos()
+    {
+        let pick = (pos - response.rect.min) / response.rect.size();
+        focus.0 = Some(vec2(pick.x, pick.y));
+    }
+
+    // Dragging across the image height turns by half a circle
+    let height = response.rect.height().max(1.0);
+    let turn = std::f32::consts::PI / height;
+    let delta = response.drag_delta();
+    let shift = ui.input(|i| i.modifiers.shift);
+
+    if response.dragged_by(egui::PointerButton::Middle)
+        || (shift && response.dragged_by(egui::PointerButton::Primary))
+    {
+        camera.pan(-delta.x / height, delta.y / height);
+    } else if response.dragged_by(egui::PointerButton::Primary) {
+        camera.orbit(-delta.x * turn, delta.y * turn);
+    } else if response.dragged_by(egui::PointerButton::Secondary) {
+        camera.look(-delta.x * turn, -delta.y * turn);
+    }
+
+    if !response.hovered() {
+        return;
+    }
+
+    let scroll = ui.input(|i| i.smooth_scroll_delta.y);
+    if scroll != 0.0 {
+        camera.dolly((-scroll * 0.002).exp());
+    }
+
+    if ui.ctx().wants_keyboard_input() {
+        return;
+    }
+
+    // Covers the distance to the target in a second
+    let (right, up, forward, dt) = ui.input(|i| {
+        let axis = |pos, neg| i.key_down(pos) as i32 as f32 - i.key_down(neg) as i32 as f32;
+        (
+            axis(egui::Key::D, egui::Key::A),
+            axis(egui::Key::E, egui::Key::Q),
+            axis(egui::Key::W, egui::Key::S),
+            i.stable_dt,
+        )
+    });
+    if right != 0.0 || up != 0.0 || forward != 0.0 {
+        let speed = (camera.look_at - camera.look_from).length() * dt;
+        camera.fly(right * speed, up * speed, forward * speed);
+    }
+}